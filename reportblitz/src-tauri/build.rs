@@ -1,3 +1,6 @@
 fn main() {
-    tauri_build::build()
+    // The command line tool is built without Tauri
+    if std::env::var_os("CARGO_FEATURE_GUI").is_some() {
+        tauri_build::build()
+    }
 }
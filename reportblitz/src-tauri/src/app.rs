@@ -0,0 +1,2963 @@
+//app.rs
+// The Tauri desktop app: shortcuts, tray, windows and the commands the UI calls.
+// Learn more about Tauri commands at https://tauri.app/develop/calling-rust/
+use anyhow::{anyhow, Result};
+use cpal::traits::DeviceTrait;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use tauri::{Emitter, Listener, Manager};
+//use tokio::sync::mpsc;
+use serde_json::{json, Value};
+use tauri_plugin_global_shortcut::{GlobalShortcutExt, Shortcut, ShortcutState};
+use tauri_plugin_http::init as http_init;
+use tauri_plugin_opener::init as opener_init;
+use tauri_plugin_shell::init as shell_init;
+use tauri_plugin_store::StoreExt;
+// Use these v2 imports instead
+use tauri::menu::{CheckMenuItemBuilder, Menu, MenuEvent, MenuItemBuilder, SubmenuBuilder};
+use tauri::tray::TrayIconBuilder;
+use tauri::webview::PageLoadEvent;
+use tauri::Wry;
+use tauri::{AppHandle, Runtime};
+use std::time::{Duration, Instant};
+
+use crate::{
+    archive, audio, chunking, control, credentials, devices, dictionary, encoding, history,
+    insertion, local_whisper, pipeline, postprocess, queue, retry, settings, snippets, templates,
+    transcription, vad, voice_commands,
+};
+use archive::{ArchivedRecording, ArchivedTranscript, RecordingArchive};
+use audio::{StreamResampler, TARGET_SAMPLE_RATE};
+use chunking::{ChunkerConfig, SilenceChunker};
+use control::{ControlApi, ControlStatus, Controller, LastTranscript};
+use credentials::{CredentialStore, DEFAULT_PROFILE, OPENAI_API_KEY};
+use devices::{InputDeviceInfo, SelectedDevice};
+use dictionary::{DictionaryEntry, DictionaryStore};
+use history::{HistoryEntry, HistoryStore, NewHistoryEntry};
+use insertion::InsertionMode;
+use pipeline::{Capture, DictationEvent};
+use postprocess::PostProcessSettings;
+use queue::{JobQueue, NewChunk, PendingJob};
+use retry::RetryPolicy;
+use settings::{
+    ArchiveOptions, ControlOptions, DictionaryOptions, HistoryOptions, Settings,
+    TranscriptionSettings, SETTINGS_KEY, SETTINGS_VERSION,
+};
+use snippets::SnippetSettings;
+use templates::{FieldExtraction, RenderedReport, Template, TemplateStore};
+use transcription::{
+    ApiKeyCheck, ProviderKind, TranscriptionError, TranscriptionProvider, OPENAI_BASE_URL,
+};
+use vad::VadConfig;
+use voice_commands::{VoiceCommandParser, VoiceCommandSettings, VoicePhrase};
+
+// App state structure
+use std::sync::atomic::AtomicBool;
+
+pub struct AppState {
+    settings: Arc<Mutex<Settings>>, // Cached copy of what is saved in settings.dat
+    // Held from reading the settings to saving the change, so concurrent
+    // changes can't overwrite each other
+    settings_write: Arc<Mutex<()>>,
+    is_recording: Arc<AtomicBool>,
+    is_cancelled: Arc<AtomicBool>, // Track if recording was cancelled
+    last_trigger: Arc<Mutex<Option<(String, Instant)>>>,
+    credentials: Option<Arc<dyn CredentialStore>>, // None if no secret storage works
+    bundled_env_file: Option<PathBuf>, // A .env with a key that shipped inside the app
+    history: Option<Arc<HistoryStore>>, // None if the database couldn't be opened
+    queue: Option<Arc<JobQueue>>, // Recordings waiting to be transcribed; None if unavailable
+    dictionary: Option<Arc<DictionaryStore>>, // None if dictionary.json couldn't be read
+    last_transcript: Arc<Mutex<Option<LastTranscript>>>, // Reported by the control API
+    control: Arc<ControlApi>,
+    // Cancel flags of running file transcriptions, by file path or archive id
+    file_transcriptions: Arc<Mutex<HashMap<String, Arc<AtomicBool>>>>,
+}
+
+#[tauri::command]
+fn toggle_strict_text_field_mode(app_handle: AppHandle<Wry>) -> Result<bool, String> {
+    let settings = modify_settings(&app_handle, |settings| {
+        settings.strict_text_field_mode = !settings.strict_text_field_mode;
+    })?;
+    Ok(settings.strict_text_field_mode)
+}
+
+// Add this command to get the current mode
+#[tauri::command]
+fn get_strict_text_field_mode(
+    state: tauri::State<'_, AppState>,
+) -> bool {
+    state.settings.lock().unwrap().strict_text_field_mode
+}
+
+// Function to get the API key of the active credential profile
+fn get_api_key<R: Runtime>(app_handle: &AppHandle<R>) -> Result<String> {
+    let profile = current_settings(app_handle).credential_profiles.active;
+    get_profile_api_key(app_handle, &profile)
+}
+
+fn get_profile_api_key<R: Runtime>(app_handle: &AppHandle<R>, profile: &str) -> Result<String> {
+    let credentials = app_handle.state::<AppState>().credentials.clone();
+    Ok(pipeline::resolve_api_key(credentials.as_deref(), profile))
+}
+
+// Key of the named profile, or of the active one for None
+fn get_api_key_for<R: Runtime>(app_handle: &AppHandle<R>, profile: Option<&str>) -> Result<String> {
+    match profile {
+        Some(profile) => get_profile_api_key(app_handle, profile),
+        None => get_api_key(app_handle),
+    }
+}
+
+fn credential_store(
+    state: &tauri::State<'_, AppState>,
+) -> Result<Arc<dyn CredentialStore>, String> {
+    state
+        .credentials
+        .clone()
+        .ok_or_else(|| "Secure credential storage is unavailable".to_string())
+}
+
+// Move a key saved in plain text by older versions into the credential store
+fn migrate_plaintext_api_key<R: Runtime>(
+    app_handle: &AppHandle<R>,
+    credentials: &dyn CredentialStore,
+) {
+    let store = match app_handle.store("api_keys.dat") {
+        Ok(store) => store,
+        Err(e) => {
+            println!("Error accessing store: {}", e);
+            return;
+        }
+    };
+
+    let key = match store.get(OPENAI_API_KEY) {
+        Some(key) => key.as_str().unwrap_or_default().to_string(),
+        None => return,
+    };
+
+    if !key.is_empty() {
+        // Don't overwrite a key that was already saved securely
+        let existing = credentials.get(OPENAI_API_KEY).unwrap_or(None);
+        if existing.is_none() {
+            if let Err(e) = credentials.set(OPENAI_API_KEY, &key) {
+                eprintln!("Failed to move API key to {}: {}", credentials.name(), e);
+                return;
+            }
+        }
+    }
+
+    store.delete(OPENAI_API_KEY);
+    match store.save() {
+        Ok(()) => println!("API key moved from api_keys.dat to {}", credentials.name()),
+        Err(e) => eprintln!("Failed to remove API key from api_keys.dat: {}", e),
+    }
+}
+
+// Use the given credential profile, or the active one if none is given
+fn resolve_profile<R: Runtime>(
+    app_handle: &AppHandle<R>,
+    profile: Option<String>,
+) -> Result<String, String> {
+    let profiles = current_settings(app_handle).credential_profiles;
+    match profile {
+        Some(name) if profiles.contains(&name) => Ok(name),
+        Some(name) => Err(format!("Unknown credential profile \"{}\"", name)),
+        None => Ok(profiles.active),
+    }
+}
+
+// Command to save the API key entered in the settings
+#[tauri::command]
+fn set_api_key(
+    api_key: String,
+    profile: Option<String>,
+    app_handle: AppHandle<Wry>,
+    state: tauri::State<'_, AppState>,
+) -> Result<(), String> {
+    let profile = resolve_profile(&app_handle, profile)?;
+    let api_key = api_key.trim();
+    if api_key.is_empty() {
+        return Err("API key cannot be empty".to_string());
+    }
+    if api_key.chars().any(char::is_whitespace) {
+        return Err("API key cannot contain spaces".to_string());
+    }
+
+    let credentials = credential_store(&state)?;
+    credentials
+        .set(&credentials::api_key_name(&profile), api_key)
+        .map_err(|e| format!("Failed to save API key: {}", e))?;
+
+    println!("API key for profile {} saved to {}", profile, credentials.name());
+    let _ = app_handle.emit("api-key-changed", json!({ "profile": profile, "has_key": true }));
+    Ok(())
+}
+
+// Command to remove a saved API key. A key in the environment or the .env
+// file is still picked up afterwards.
+#[tauri::command]
+fn clear_api_key(
+    profile: Option<String>,
+    app_handle: AppHandle<Wry>,
+    state: tauri::State<'_, AppState>,
+) -> Result<(), String> {
+    let profile = resolve_profile(&app_handle, profile)?;
+    let credentials = credential_store(&state)?;
+    credentials
+        .delete(&credentials::api_key_name(&profile))
+        .map_err(|e| format!("Failed to remove API key: {}", e))?;
+
+    println!("API key for profile {} removed from {}", profile, credentials.name());
+    let _ = app_handle.emit("api-key-changed", json!({ "profile": profile, "has_key": false }));
+    Ok(())
+}
+
+// Command to check whether an API key is available, without exposing it
+#[tauri::command]
+fn has_api_key(profile: Option<String>, app_handle: AppHandle<Wry>) -> Result<bool, String> {
+    let profile = resolve_profile(&app_handle, profile)?;
+    Ok(get_profile_api_key(&app_handle, &profile)
+        .map(|key| !key.is_empty())
+        .unwrap_or(false))
+}
+
+// Command to check an API key. A typed key, or the saved key of the given
+// profile, is an OpenAI key and only ever sent to OpenAI. Without either, the
+// configured transcription server is checked with the key it would get: for
+// a compatible server, only that of the profile chosen for it, if any.
+#[tauri::command]
+async fn validate_api_key(
+    api_key: Option<String>,
+    profile: Option<String>,
+    app_handle: AppHandle<Wry>,
+) -> Result<ApiKeyCheck, String> {
+    if let Some(key) = api_key.map(|k| k.trim().to_string()).filter(|k| !k.is_empty()) {
+        return Ok(transcription::check_api_key(OPENAI_BASE_URL, Some(&key)).await);
+    }
+
+    let transcription = current_settings(&app_handle).transcription;
+    let (base_url, api_key) = match (transcription.provider, profile) {
+        (ProviderKind::OpenAiCompatible, None) => {
+            let api_key = pipeline::compatible_server_key(
+                transcription.api_key_profile.as_deref(),
+                |profile| get_api_key_for(&app_handle, profile),
+            )
+            .map_err(|e| e.to_string())?;
+            (transcription.base_url, api_key)
+        }
+        (_, profile) => {
+            let profile = resolve_profile(&app_handle, profile)?;
+            let api_key = get_profile_api_key(&app_handle, &profile).map_err(|e| e.to_string())?;
+            (OPENAI_BASE_URL.to_string(), Some(api_key))
+        }
+    };
+    Ok(transcription::check_api_key(&base_url, api_key.as_deref()).await)
+}
+
+#[derive(Serialize)]
+struct CredentialProfileInfo {
+    name: String,
+    active: bool,
+    has_key: bool,
+}
+
+// Command to list the credential profiles and whether each has a key
+#[tauri::command]
+fn list_credential_profiles(app_handle: AppHandle<Wry>) -> Vec<CredentialProfileInfo> {
+    let profiles = current_settings(&app_handle).credential_profiles;
+    profiles
+        .names
+        .iter()
+        .map(|name| CredentialProfileInfo {
+            name: name.clone(),
+            active: *name == profiles.active,
+            has_key: get_profile_api_key(&app_handle, name)
+                .map(|key| !key.is_empty())
+                .unwrap_or(false),
+        })
+        .collect()
+}
+
+// Command to add an empty credential profile
+#[tauri::command]
+fn add_credential_profile(name: String, app_handle: AppHandle<Wry>) -> Result<(), String> {
+    let name = name.trim().to_string();
+    if current_settings(&app_handle).credential_profiles.contains(&name) {
+        return Err(format!("Credential profile \"{}\" already exists", name));
+    }
+    modify_settings(&app_handle, |settings| settings.credential_profiles.names.push(name))?;
+    Ok(())
+}
+
+// Command to remove a credential profile and its saved key
+#[tauri::command]
+fn remove_credential_profile(
+    name: String,
+    app_handle: AppHandle<Wry>,
+    state: tauri::State<'_, AppState>,
+) -> Result<(), String> {
+    if name == DEFAULT_PROFILE {
+        return Err("The default credential profile cannot be removed".to_string());
+    }
+    resolve_profile(&app_handle, Some(name.clone()))?;
+
+    if let Some(credentials) = &state.credentials {
+        credentials
+            .delete(&credentials::api_key_name(&name))
+            .map_err(|e| format!("Failed to remove API key: {}", e))?;
+    }
+    modify_settings(&app_handle, |settings| {
+        let profiles = &mut settings.credential_profiles;
+        profiles.names.retain(|n| *n != name);
+        // Fall back to the default profile if the active one was removed
+        if profiles.active == name {
+            profiles.active = DEFAULT_PROFILE.to_string();
+        }
+        // Servers that used its key get none
+        for profile in [
+            &mut settings.transcription.api_key_profile,
+            &mut settings.post_processing.api_key_profile,
+        ] {
+            if profile.as_deref() == Some(name.as_str()) {
+                *profile = None;
+            }
+        }
+    })?;
+    Ok(())
+}
+
+// Command to choose whose API key is used for transcription
+#[tauri::command]
+fn set_active_credential_profile(name: String, app_handle: AppHandle<Wry>) -> Result<(), String> {
+    let name = resolve_profile(&app_handle, Some(name))?;
+    modify_settings(&app_handle, |settings| {
+        settings.credential_profiles.active = name.clone()
+    })?;
+    println!("Active credential profile set to {}", name);
+    let _ = app_handle.emit("credential-profile-changed", name);
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct ShortcutConfig {
+    _shortcut: String,
+    _hold_shortcut: String,
+    _cancel_shortcut: String,
+    api_key: String,
+}
+
+// Command to get the current shortcut configuration
+#[tauri::command]
+fn get_shortcut_config(state: tauri::State<'_, AppState>) -> ShortcutConfig {
+    let shortcuts = state.settings.lock().unwrap().shortcuts.clone();
+    ShortcutConfig {
+        _shortcut: shortcuts.toggle,
+        _hold_shortcut: shortcuts.hold,
+        _cancel_shortcut: shortcuts.cancel,
+        api_key: "".to_string(), // Don't expose API key to frontend
+    }
+}
+
+// Command to update the shortcut configuration
+#[tauri::command]
+fn update_shortcut_config(
+    toggle_shortcut: String,
+    hold_shortcut: String,
+    cancel_shortcut: String,
+    app_handle: AppHandle<Wry>,
+) -> Result<(), String> {
+    // Validation, registration and the "shortcuts-updated" event happen in apply_settings
+    modify_settings(&app_handle, |settings| {
+        settings.shortcuts.toggle = toggle_shortcut;
+        settings.shortcuts.hold = hold_shortcut;
+        settings.shortcuts.cancel = cancel_shortcut;
+    })?;
+    Ok(())
+}
+
+// Older builds shipped src-tauri/.env inside the app bundle, handing the
+// build machine's key to every user. Find such a file so it can be reported;
+// it is never read.
+fn find_bundled_env_file() -> Option<PathBuf> {
+    let current_exe = std::env::current_exe().ok()?;
+    let exe_dir = current_exe.parent()?;
+
+    // For macOS, the Resources directory is at ../Resources relative to the executable
+    #[cfg(target_os = "macos")]
+    let resources_dir = exe_dir.parent()?.join("Resources");
+
+    // For Windows/Linux, resources are typically in the same directory as the executable
+    #[cfg(not(target_os = "macos"))]
+    let resources_dir = exe_dir.join("resources");
+
+    let env_path = resources_dir.join(".env");
+    pipeline::read_env_file_key(&env_path, "OPENAI_API_KEY").map(|_| env_path)
+}
+
+// Function to update the global shortcut
+fn update_global_shortcut<R: Runtime>(
+    app_handle: &AppHandle<R>,
+    toggle_shortcut: &str,
+    hold_shortcut: &str,
+    cancel_shortcut: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    println!(
+        "Attempting to register shortcuts: toggle={}, hold={}, cancel={}",
+        toggle_shortcut, hold_shortcut, cancel_shortcut
+    );
+    
+    app_handle.global_shortcut().unregister_all()?;
+    app_handle.global_shortcut().register(toggle_shortcut)?;
+    app_handle.global_shortcut().register(hold_shortcut)?;
+    app_handle.global_shortcut().register(cancel_shortcut)?;
+    
+    println!("All shortcuts registered successfully");
+    Ok(())
+}
+
+use std::sync::atomic::Ordering;
+
+// Then modify your handle_shortcut function
+fn handle_shortcut<R: Runtime>(
+    app_handle: &AppHandle<R>,
+    shortcut: &Shortcut,
+    state: ShortcutState,
+) {
+    let now = Instant::now();
+    let shortcut_str = shortcut.to_string();
+    let app_state = app_handle.state::<AppState>();
+
+    // Use thread-safe debounce check
+    let should_process = {
+        let mut last_trigger = app_state.last_trigger.lock().unwrap();
+        if let Some((last_shortcut, last_time)) = &*last_trigger {
+            if last_shortcut == &shortcut_str
+                && now.duration_since(*last_time) < Duration::from_millis(300)
+            {
+                println!("Debounced duplicate trigger for: {}", shortcut_str);
+                return;
+            }
+        }
+        *last_trigger = Some((shortcut_str.clone(), now));
+        true
+    };
+
+    if !should_process {
+        return;
+    }
+
+    let shortcuts = app_state.settings.lock().unwrap().shortcuts.clone();
+    let (toggle_shortcut, hold_shortcut, cancel_shortcut) =
+        (shortcuts.toggle, shortcuts.hold, shortcuts.cancel);
+
+    // Handle cancel shortcut (only respond to Pressed state)
+    if shortcut_str == cancel_shortcut && state == ShortcutState::Pressed {
+        println!("Cancel shortcut triggered!");
+        
+        // If we're recording, cancel it
+        if cancel_recording(app_handle) {
+            println!("Cancelling current recording");
+        }
+        return;
+    }
+
+    // Original toggle shortcut handler
+    if shortcut_str == toggle_shortcut && state == ShortcutState::Pressed {
+        println!("Toggle shortcut matched!");
+
+        // Check if we're already recording - if so, stop
+        if stop_recording(app_handle) {
+            println!("Already recording, stopped");
+        } else {
+            // If not recording, start
+            println!("Not recording, starting");
+            start_recording(app_handle);
+        }
+    } else if shortcut_str == hold_shortcut {
+        // Original hold shortcut handler
+        println!("Hold shortcut matched! State: {:?}", state);
+
+        if state == ShortcutState::Pressed {
+            println!("Hold shortcut pressed");
+            if start_recording(app_handle) {
+                println!("Recording started");
+            }
+        } else if state == ShortcutState::Released {
+            println!("Hold shortcut released");
+            if stop_recording(app_handle) {
+                println!("Recording stopped");
+            }
+        }
+    }
+}
+
+// Start recording unless already recording. Returns false if it was.
+fn start_recording<R: Runtime>(app_handle: &AppHandle<R>) -> bool {
+    let app_state = app_handle.state::<AppState>();
+    if app_state.is_recording.swap(true, Ordering::SeqCst) {
+        return false;
+    }
+    // Reset cancelled state
+    app_state.is_cancelled.store(false, Ordering::SeqCst);
+    let _ = app_handle.emit("recording-status", true);
+
+    let app_handle_clone = app_handle.clone();
+    thread::spawn(move || {
+        if let Err(e) = record_audio_internal(app_handle_clone) {
+            eprintln!("Error recording audio: {}", e);
+        }
+    });
+    true
+}
+
+// Stop recording and transcribe it. Returns false if nothing was recording.
+fn stop_recording<R: Runtime>(app_handle: &AppHandle<R>) -> bool {
+    let app_state = app_handle.state::<AppState>();
+    if !app_state.is_recording.swap(false, Ordering::SeqCst) {
+        return false;
+    }
+    let _ = app_handle.emit("recording-status", false);
+    true
+}
+
+// Stop recording and throw it away. Returns false if nothing was recording.
+fn cancel_recording<R: Runtime>(app_handle: &AppHandle<R>) -> bool {
+    let app_state = app_handle.state::<AppState>();
+    if !app_state.is_recording.load(Ordering::SeqCst) {
+        return false;
+    }
+    app_state.is_cancelled.store(true, Ordering::SeqCst); // Mark as cancelled
+    app_state.is_recording.store(false, Ordering::SeqCst); // Stop recording
+    let _ = app_handle.emit("recording-status", false);
+    let _ = app_handle.emit("recording-cancelled", true);
+    true
+}
+
+
+// What became of one chunk of a recording
+enum ChunkOutcome {
+    Text(String),
+    // The audio is kept so the recording can go to the offline queue
+    Failed(TranscriptionError, Vec<u8>),
+    // The chunk's task broke before the provider answered, e.g. encoding
+    // failed or it panicked. Trying again later may work, so the recording is
+    // queued as after a network failure. Holds the chunk's 16 kHz samples.
+    Interrupted(String, Vec<f32>),
+}
+
+fn report_transcription_error<R: Runtime>(
+    app_handle: &AppHandle<R>,
+    error: &TranscriptionError,
+    provider: &str,
+) {
+    let _ = app_handle.emit(
+        "transcription-error",
+        json!({
+            "error": error,
+            "retryable": error.is_retryable(),
+            "provider": provider,
+        }),
+    );
+    let _ = app_handle.emit("error", format!("Transcription error: {}", error));
+}
+
+// Optimized record_audio_internal function
+fn record_audio_internal<R: Runtime>(app_handle: AppHandle<R>) -> Result<()> {
+    let state = app_handle.state::<AppState>();
+    let is_recording = state.is_recording.clone();
+    let is_cancelled = state.is_cancelled.clone();
+
+    // Create a single thread for audio processing
+    thread::spawn(move || {
+        // Set up runtime inside thread for async operations
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+
+        runtime.block_on(async {
+            // Initialize audio
+            let preferred_device = current_settings(&app_handle).input_device;
+            let device = match devices::select_input_device(preferred_device.as_deref()) {
+                Some(SelectedDevice::Preferred(device)) | Some(SelectedDevice::Default(device)) => {
+                    device
+                }
+                Some(SelectedDevice::Fallback(device)) => {
+                    let preferred = preferred_device.unwrap_or_default();
+                    println!("Preferred input device {} not found, using default", preferred);
+                    let _ = app_handle.emit(
+                        "input-device-fallback",
+                        json!({
+                            "preferred": preferred,
+                            "using": device.name().unwrap_or_default(),
+                        }),
+                    );
+                    device
+                }
+                None => {
+                    eprintln!("No input device available");
+                    let _ = app_handle.emit("error", "No microphone found");
+                    return;
+                }
+            };
+            println!("Using input device: {}", device.name().unwrap_or_default());
+
+            // If the microphone is unplugged mid-recording, stop and transcribe
+            // what was captured so far instead of hanging on a dead stream
+            let err_app_handle = app_handle.clone();
+            let err_is_recording = is_recording.clone();
+            let err_fn = move |err: cpal::StreamError| {
+                eprintln!("Stream error: {:?}", err);
+                if let cpal::StreamError::DeviceNotAvailable = err {
+                    if err_is_recording.swap(false, Ordering::SeqCst) {
+                        let _ = err_app_handle.emit("recording-status", false);
+                        let _ = err_app_handle
+                            .emit("error", "Microphone disconnected, recording stopped");
+                    }
+                }
+            };
+
+            // Captured samples are drained into the chunker every 100ms, so the
+            // capture buffer only ever holds a fraction of a second of audio
+            let capture = match Capture::start(&device, is_recording.clone(), err_fn) {
+                Ok(capture) => capture,
+                Err(e) => {
+                    eprintln!("Failed to start audio capture: {}", e);
+                    let _ = app_handle.emit("error", format!("Microphone error: {}", e));
+                    return;
+                }
+            };
+            let sample_rate = capture.sample_rate;
+
+            println!("Audio processing thread started");
+
+            let provider = match load_transcription_provider(&app_handle) {
+                Ok(provider) => provider,
+                Err(e) => {
+                    eprintln!("Transcription error: {}", e);
+                    is_recording.store(false, Ordering::SeqCst);
+                    let _ = app_handle.emit("recording-status", false);
+                    let _ = app_handle.emit("error", format!("Transcription error: {}", e));
+                    return;
+                }
+            };
+
+            // Chunks are transcribed as soon as they are cut. Their task handles
+            // go through this channel in recording order, each with a copy of
+            // the chunk in case the task never answers.
+            let (chunk_tx, mut chunk_rx) = tokio::sync::mpsc::unbounded_channel::<(
+                tokio::task::JoinHandle<Result<ChunkOutcome>>,
+                Vec<f32>,
+            )>();
+            let semaphore = Arc::new(tokio::sync::Semaphore::new(MAX_CONCURRENT_CHUNKS));
+            let vad_config = current_settings(&app_handle).vad;
+            let mut next_chunk_index = 0;
+            let mut spawn_chunk = |chunk: Vec<f32>| {
+                let provider = provider.clone();
+                let semaphore = semaphore.clone();
+                let vad_config = vad_config.clone();
+                let chunk_app = app_handle.clone();
+                let chunk_cancelled = is_cancelled.clone();
+                let index = next_chunk_index;
+                next_chunk_index += 1;
+                let kept = chunk.clone();
+                let handle = tokio::spawn(async move {
+                    // Drop silence before upload; chunks without speech aren't sent at all
+                    let chunk = if vad_config.enabled {
+                        match vad::trim_silence(&chunk, TARGET_SAMPLE_RATE, &vad_config) {
+                            Some(trimmed) => trimmed,
+                            None => {
+                                println!("No speech detected in chunk, skipping");
+                                return Ok(ChunkOutcome::Text(String::new()));
+                            }
+                        }
+                    } else {
+                        chunk
+                    };
+
+                    let _permit = semaphore.acquire_owned().await?;
+
+                    // Transient failures (timeouts, 429, 5xx) are retried with backoff
+                    let on_retry = |attempt: u32, error: &TranscriptionError, delay: Duration| {
+                        let _ = chunk_app.emit(
+                            "transcription-retry",
+                            json!({
+                                "chunk": index,
+                                "attempt": attempt,
+                                "delay_ms": delay.as_millis() as u64,
+                                "error": error,
+                            }),
+                        );
+                    };
+                    // Keep the audio of a failed chunk so the recording can be queued
+                    match retry::transcribe_chunk(
+                        provider.as_ref(),
+                        &chunk,
+                        &RetryPolicy::default(),
+                        &chunk_cancelled,
+                        on_retry,
+                    )
+                    .await
+                    {
+                        Ok(text) => Ok(ChunkOutcome::Text(text)),
+                        Err(error) => {
+                            let wav_data = audio::encode_wav(&chunk, TARGET_SAMPLE_RATE)?;
+                            Ok(ChunkOutcome::Failed(error, wav_data))
+                        }
+                    }
+                });
+                (handle, kept)
+            };
+
+            // Emit partial results in recording order, whatever order they finish
+            // in. Every chunk gets an outcome, so no audio is lost to one that broke.
+            let collector_app = app_handle.clone();
+            let collector_cancelled = is_cancelled.clone();
+            let collector = tokio::spawn(async move {
+                let mut outcomes: Vec<ChunkOutcome> = Vec::new();
+                while let Some((handle, chunk)) = chunk_rx.recv().await {
+                    let outcome = match handle.await {
+                        Ok(Ok(outcome)) => outcome,
+                        Ok(Err(e)) => ChunkOutcome::Interrupted(e.to_string(), chunk),
+                        Err(e) => ChunkOutcome::Interrupted(
+                            format!("Transcription task failed: {}", e),
+                            chunk,
+                        ),
+                    };
+                    if let ChunkOutcome::Text(text) = &outcome {
+                        if !collector_cancelled.load(Ordering::SeqCst) {
+                            let _ = collector_app.emit(
+                                "transcription-partial",
+                                json!({ "index": outcomes.len(), "text": text }),
+                            );
+                        }
+                    }
+                    outcomes.push(outcome);
+                }
+                outcomes
+            });
+
+            let mut resampler = match StreamResampler::new(sample_rate, TARGET_SAMPLE_RATE) {
+                Ok(resampler) => resampler,
+                Err(e) => {
+                    eprintln!("Error creating resampler: {}", e);
+                    is_recording.store(false, Ordering::SeqCst);
+                    let _ = app_handle.emit("recording-status", false);
+                    let _ = app_handle.emit("error", format!("Audio error: {}", e));
+                    return;
+                }
+            };
+            let mut chunker = SilenceChunker::new(ChunkerConfig::default(), TARGET_SAMPLE_RATE);
+            let mut total_samples = 0;
+
+            // Only hold on to the whole recording if the history or the archive keeps it
+            let history_options = current_settings(&app_handle).history;
+            let archive_options = current_settings(&app_handle).archive;
+            let keep_recording = history_options.keep_audio || archive_options.enabled;
+            let mut recorded: Vec<f32> = Vec::new();
+
+            // Loop until recording is stopped, cutting chunks as audio arrives
+            while is_recording.load(Ordering::SeqCst) {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+
+                let captured = capture.take();
+                let new_samples = resampler.push(&captured).unwrap_or_else(|e| {
+                    eprintln!("Error resampling audio: {}", e);
+                    Vec::new()
+                });
+                total_samples += new_samples.len();
+                if keep_recording {
+                    recorded.extend_from_slice(&new_samples);
+                }
+                for chunk in chunker.push(&new_samples) {
+                    println!("Chunk ready: {} samples", chunk.len());
+                    let _ = chunk_tx.send(spawn_chunk(chunk));
+                }
+            }
+
+            // Check if recording was cancelled
+            if is_cancelled.load(Ordering::SeqCst) {
+                println!("Recording was cancelled, skipping transcription");
+                collector.abort();
+                return;
+            }
+
+            println!("Recording stopped, processing remaining samples...");
+
+            let captured = capture.take();
+            let remaining = resampler
+                .push(&captured)
+                .and_then(|mut samples| {
+                    samples.extend(resampler.finish()?);
+                    Ok(samples)
+                })
+                .unwrap_or_else(|e| {
+                    eprintln!("Error resampling audio: {}", e);
+                    Vec::new()
+                });
+            total_samples += remaining.len();
+            if keep_recording {
+                recorded.extend_from_slice(&remaining);
+            }
+            for chunk in chunker.push(&remaining) {
+                let _ = chunk_tx.send(spawn_chunk(chunk));
+            }
+            if let Some(chunk) = chunker.finish() {
+                let _ = chunk_tx.send(spawn_chunk(chunk));
+            }
+            drop(chunk_tx);
+
+            println!("Total samples collected: {}", total_samples);
+
+            if total_samples == 0 {
+                println!("No samples were collected, skipping transcription.");
+                collector.abort();
+                return;
+            }
+
+            // Archive before transcribing so a failed or queued recording is kept too
+            let duration_secs = total_samples as f64 / TARGET_SAMPLE_RATE as f64;
+            let archived = if archive_options.enabled {
+                archive_recording(&app_handle, &archive_options, &recorded, duration_secs)
+            } else {
+                None
+            };
+
+            let outcomes = match collector.await {
+                Ok(outcomes) => outcomes,
+                Err(e) => {
+                    eprintln!("Transcription error: {}", e);
+                    let error = TranscriptionError::Other {
+                        message: format!("Transcription task failed: {}", e),
+                    };
+                    report_transcription_error(&app_handle, &error, provider.name());
+                    return;
+                }
+            };
+
+            // Skip typing if cancelled after transcription completed
+            if is_cancelled.load(Ordering::SeqCst) {
+                println!("Transcription completed but cancelled, not typing text");
+                return;
+            }
+
+            let failure = outcomes.iter().find_map(|outcome| match outcome {
+                ChunkOutcome::Failed(error, _) => Some((error.clone(), error.is_retryable())),
+                ChunkOutcome::Interrupted(message, _) => Some((
+                    TranscriptionError::Other {
+                        message: message.clone(),
+                    },
+                    true,
+                )),
+                ChunkOutcome::Text(_) => None,
+            });
+            if let Some((error, retry_later)) = failure {
+                eprintln!("Transcription error: {}", error);
+                // Don't lose a recording to a dropped connection; queue it instead
+                let queued = retry_later
+                    && queue_recording(
+                        &app_handle,
+                        outcomes,
+                        duration_secs,
+                        provider.name(),
+                        &error,
+                    );
+                if !queued {
+                    report_transcription_error(&app_handle, &error, provider.name());
+                }
+                return;
+            }
+
+            let parts: Vec<String> = outcomes
+                .into_iter()
+                .filter_map(|outcome| match outcome {
+                    ChunkOutcome::Text(text) => Some(text),
+                    ChunkOutcome::Failed(..) | ChunkOutcome::Interrupted(..) => None,
+                })
+                .collect();
+            let text = chunking::stitch(&parts);
+            if text.is_empty() {
+                println!("No speech detected, nothing to type");
+                let _ = app_handle.emit("no-speech-detected", true);
+                return;
+            }
+
+            println!("Transcription succeeded: {}", text);
+            if let Some(id) = &archived {
+                archive_transcript(&app_handle, &archive_options, id, &text, provider.name());
+            }
+            // The archive keeps what was said; the cleaned-up text is what gets typed
+            let text = prepare_dictation(&app_handle, text).await;
+            let recording_wav = if history_options.keep_audio {
+                audio::encode_wav(&recorded, TARGET_SAMPLE_RATE).ok()
+            } else {
+                None
+            };
+            let info = RecordingInfo {
+                provider: provider.name().to_string(),
+                duration_secs,
+                audio: recording_wav,
+            };
+            if text.is_empty() {
+                println!("Everything dictated was scratched, nothing to type");
+            } else {
+                deliver_transcription(&text, info, &app_handle);
+            }
+
+            // The provider is reachable again, so catch up on queued recordings
+            if pending_job_count(&app_handle) > 0 {
+                tauri::async_runtime::spawn(replay_pending_jobs(app_handle.clone()));
+            }
+        });
+    });
+
+    Ok(())
+}
+
+// Command to record audio
+#[tauri::command]
+fn record_audio(app_handle: AppHandle<Wry>) -> Result<(), String> {
+    // Stop if already recording, otherwise start
+    if !stop_recording(&app_handle) {
+        start_recording(&app_handle);
+    }
+    Ok(())
+}
+
+// Events streamed to control API clients
+const CONTROL_EVENTS: &[&str] = &[
+    "recording-status",
+    "recording-cancelled",
+    "transcription-partial",
+    "transcription",
+    "transcription-error",
+    "transcription-queued",
+    "no-speech-detected",
+    "error",
+];
+
+// Lets the control API drive recording the way the shortcuts do
+struct AppController<R: Runtime> {
+    app_handle: AppHandle<R>,
+}
+
+impl<R: Runtime> Controller for AppController<R> {
+    fn start(&self) {
+        start_recording(&self.app_handle);
+    }
+
+    fn stop(&self) {
+        stop_recording(&self.app_handle);
+    }
+
+    fn cancel(&self) {
+        cancel_recording(&self.app_handle);
+    }
+
+    fn status(&self) -> ControlStatus {
+        ControlStatus {
+            recording: self
+                .app_handle
+                .state::<AppState>()
+                .is_recording
+                .load(Ordering::SeqCst),
+            pending_jobs: pending_job_count(&self.app_handle),
+        }
+    }
+
+    fn last_transcript(&self) -> Option<LastTranscript> {
+        self.app_handle
+            .state::<AppState>()
+            .last_transcript
+            .lock()
+            .unwrap()
+            .clone()
+    }
+}
+
+// Pass the app's events on to control API clients
+fn forward_control_events<R: Runtime>(app_handle: &AppHandle<R>) {
+    for &name in CONTROL_EVENTS {
+        let forward_handle = app_handle.clone();
+        app_handle.listen_any(name, move |event| {
+            let payload = serde_json::from_str(event.payload()).unwrap_or(Value::Null);
+            forward_handle
+                .state::<AppState>()
+                .control
+                .publish(name, payload);
+        });
+    }
+}
+
+// Stop the control API servers and start the ones enabled in the settings
+fn restart_control_api<R: Runtime>(app_handle: &AppHandle<R>, options: &ControlOptions) {
+    let control = app_handle.state::<AppState>().control.clone();
+    control.shutdown();
+    if !options.socket_enabled && !options.http_enabled {
+        return;
+    }
+
+    let app_data_dir = match app_handle.path().app_data_dir() {
+        Ok(dir) => dir,
+        Err(e) => {
+            eprintln!("Failed to resolve app data directory: {}", e);
+            return;
+        }
+    };
+    let runtime = tauri::async_runtime::handle();
+
+    #[cfg(unix)]
+    if options.socket_enabled {
+        let path = control::socket_path(&app_data_dir);
+        match control.serve_socket(runtime.inner(), &path) {
+            Ok(()) => println!("Control API listening on {:?}", path),
+            Err(e) => {
+                eprintln!("Failed to start the control socket: {}", e);
+                let _ = app_handle.emit("error", format!("Control socket error: {}", e));
+            }
+        }
+    }
+
+    if options.http_enabled {
+        let started = control::load_or_create_token(&control::token_path(&app_data_dir))
+            .and_then(|token| control.serve_http(runtime.inner(), options.http_port, token));
+        match started {
+            Ok(()) => println!(
+                "Control API listening on http://127.0.0.1:{}",
+                options.http_port
+            ),
+            Err(e) => {
+                eprintln!("Failed to start the control API over HTTP: {}", e);
+                let _ = app_handle.emit("error", format!("Control API error: {}", e));
+            }
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ControlApiInfo {
+    // None when the socket is off or unsupported
+    socket_path: Option<String>,
+    // None when HTTP is off
+    http_url: Option<String>,
+    token_path: String,
+}
+
+// Command to show scripts where to reach the control API
+#[tauri::command]
+fn get_control_api_info(app_handle: AppHandle<Wry>) -> Result<ControlApiInfo, String> {
+    let options = current_settings(&app_handle).control;
+    let app_data_dir = app_handle
+        .path()
+        .app_data_dir()
+        .map_err(|e| e.to_string())?;
+    let socket_path = control::socket_path(&app_data_dir);
+    Ok(ControlApiInfo {
+        socket_path: (cfg!(unix) && options.socket_enabled)
+            .then(|| socket_path.to_string_lossy().to_string()),
+        http_url: options
+            .http_enabled
+            .then(|| format!("http://127.0.0.1:{}", options.http_port)),
+        token_path: control::token_path(&app_data_dir)
+            .to_string_lossy()
+            .to_string(),
+    })
+}
+
+// Command to replace the control API access token, e.g. after it leaked.
+// Returns the new token.
+#[tauri::command]
+fn regenerate_control_token(app_handle: AppHandle<Wry>) -> Result<String, String> {
+    let app_data_dir = app_handle
+        .path()
+        .app_data_dir()
+        .map_err(|e| e.to_string())?;
+    let token = control::regenerate_token(&control::token_path(&app_data_dir))
+        .map_err(|e| e.to_string())?;
+    // Connections using the old token are closed
+    restart_control_api(&app_handle, &current_settings(&app_handle).control);
+    Ok(token)
+}
+
+// Build the transcription provider selected in settings.dat
+fn load_transcription_provider<R: Runtime>(
+    app_handle: &AppHandle<R>,
+) -> Result<Arc<dyn TranscriptionProvider>> {
+    build_transcription_provider(app_handle, current_settings(app_handle).transcription)
+}
+
+fn build_transcription_provider<R: Runtime>(
+    app_handle: &AppHandle<R>,
+    mut settings: TranscriptionSettings,
+) -> Result<Arc<dyn TranscriptionProvider>> {
+    let models_dir = get_models_dir(app_handle)?;
+    if current_settings(app_handle).dictionary.prompt_hints {
+        if let Some(store) = &app_handle.state::<AppState>().dictionary {
+            let hints = dictionary::spelling_hints(&store.entries());
+            settings.options.prompt =
+                dictionary::prompt_with_hints(settings.options.prompt, &hints);
+        }
+    }
+    pipeline::build_provider(
+        settings,
+        |profile| get_api_key_for(app_handle, profile),
+        &models_dir,
+    )
+}
+
+// Everything between a transcript and the text that is typed, run through
+// the same steps as the command line tool with events for the frontend
+async fn prepare_dictation<R: Runtime>(app_handle: &AppHandle<R>, text: String) -> String {
+    let settings = current_settings(app_handle);
+    let templates_dir = match app_handle.path().app_data_dir() {
+        Ok(dir) => templates::templates_dir(&dir),
+        Err(e) => {
+            eprintln!("Failed to resolve app data directory: {}", e);
+            PathBuf::from("templates")
+        }
+    };
+    let app_state = app_handle.state::<AppState>();
+    let dictation = pipeline::Dictation::new(
+        &settings,
+        app_state.dictionary.as_deref(),
+        &templates_dir,
+        None,
+        |profile| get_api_key_for(app_handle, profile),
+    );
+    dictation
+        .finish(text, |event| emit_dictation_event(app_handle, event))
+        .await
+}
+
+fn emit_dictation_event<R: Runtime>(app_handle: &AppHandle<R>, event: DictationEvent) {
+    match event {
+        DictationEvent::PostProcessingStarted => {
+            let _ = app_handle.emit("post-processing-started", true);
+        }
+        DictationEvent::PostProcessingFailed(e) => {
+            let error = TranscriptionError::classify(e);
+            eprintln!("Post-processing failed, keeping the transcript: {}", error);
+            let _ = app_handle.emit(
+                "post-processing-failed",
+                json!({
+                    "error": error,
+                    "retryable": error.is_retryable(),
+                }),
+            );
+        }
+        DictationEvent::TemplateApplied { name, missing } => {
+            println!(
+                "Rendered report template {} ({} fields missing)",
+                name,
+                missing.len()
+            );
+            let _ = app_handle.emit(
+                "template-applied",
+                json!({ "template": name, "missing": missing }),
+            );
+        }
+        DictationEvent::TemplateFailed { name, error } => {
+            eprintln!("Failed to apply report template {}: {}", name, error);
+            let _ = app_handle.emit(
+                "template-failed",
+                json!({ "template": name, "error": error.to_string() }),
+            );
+        }
+    }
+}
+
+// Parser for the spoken commands of the configured language, or of the
+// language requested from the transcription provider
+fn voice_command_parser(settings: &Settings) -> VoiceCommandParser {
+    VoiceCommandParser::from_settings(
+        &settings.voice_commands,
+        settings.transcription.options.language.as_deref(),
+    )
+}
+
+// Folder of report templates, created with an example on first use
+fn template_store<R: Runtime>(app_handle: &AppHandle<R>) -> Result<TemplateStore> {
+    let app_data_dir = app_handle
+        .path()
+        .app_data_dir()
+        .map_err(|e| anyhow!("Failed to resolve app data directory: {}", e))?;
+    TemplateStore::open(&templates::templates_dir(&app_data_dir))
+}
+
+// Map a dictation onto a template's fields and render it. Model extraction
+// uses the post-processing model even if rewriting is turned off.
+async fn render_template<R: Runtime>(
+    app_handle: &AppHandle<R>,
+    template: &Template,
+    text: &str,
+) -> Result<RenderedReport> {
+    let settings = current_settings(app_handle);
+    let processor = match settings.templates.extraction {
+        FieldExtraction::SpokenLabels => None,
+        FieldExtraction::Model => Some(pipeline::build_post_processor(
+            settings.post_processing,
+            |profile| get_api_key_for(app_handle, profile),
+        )?),
+    };
+    templates::fill(
+        template,
+        text,
+        settings.templates.extraction,
+        processor.as_ref(),
+    )
+    .await
+}
+
+// Directory holding ggml models for the local provider
+fn get_models_dir<R: Runtime>(app_handle: &AppHandle<R>) -> Result<PathBuf> {
+    let app_data_dir = app_handle
+        .path()
+        .app_data_dir()
+        .map_err(|e| anyhow!("Failed to resolve app data directory: {}", e))?;
+    let models_dir = local_whisper::models_dir(&app_data_dir);
+    std::fs::create_dir_all(&models_dir)?;
+    Ok(models_dir)
+}
+
+#[derive(Serialize)]
+struct LocalModels {
+    directory: String,
+    models: Vec<String>,
+}
+
+// Command to list the ggml models available to the local provider
+#[tauri::command]
+fn list_local_models(app_handle: AppHandle<Wry>) -> Result<LocalModels, String> {
+    let models_dir = get_models_dir(&app_handle).map_err(|e| e.to_string())?;
+    Ok(LocalModels {
+        directory: models_dir.to_string_lossy().to_string(),
+        models: local_whisper::list_models(&models_dir),
+    })
+}
+
+// Command to get the transcription provider and request options
+#[tauri::command]
+fn get_transcription_settings(state: tauri::State<'_, AppState>) -> TranscriptionSettings {
+    state.settings.lock().unwrap().transcription.clone()
+}
+
+// Command to update the transcription provider and request options
+#[tauri::command]
+fn update_transcription_settings(
+    settings: TranscriptionSettings,
+    app_handle: AppHandle<Wry>,
+) -> Result<(), String> {
+    let updated = modify_settings(&app_handle, |current| current.transcription = settings)?;
+    println!(
+        "Transcription settings updated: provider={}, model={}",
+        updated.transcription.provider.as_str(),
+        updated.transcription.options.model
+    );
+    Ok(())
+}
+
+// Command to get the spoken command settings
+#[tauri::command]
+fn get_voice_command_settings(state: tauri::State<'_, AppState>) -> VoiceCommandSettings {
+    state.settings.lock().unwrap().voice_commands.clone()
+}
+
+// Command to turn spoken commands on or off and change their phrases
+#[tauri::command]
+fn update_voice_command_settings(
+    settings: VoiceCommandSettings,
+    app_handle: AppHandle<Wry>,
+) -> Result<(), String> {
+    let updated = modify_settings(&app_handle, |current| current.voice_commands = settings)?;
+    println!(
+        "Voice command settings updated: enabled={}, language={}",
+        updated.voice_commands.enabled,
+        updated
+            .voice_commands
+            .effective_language(updated.transcription.options.language.as_deref())
+    );
+    Ok(())
+}
+
+// Command to list the phrases currently recognized as commands
+#[tauri::command]
+fn list_voice_commands(state: tauri::State<'_, AppState>) -> Vec<VoicePhrase> {
+    let settings = state.settings.lock().unwrap().clone();
+    voice_command_parser(&settings).phrases()
+}
+
+// Command to get the snippets and whether they are expanded
+#[tauri::command]
+fn get_snippet_settings(state: tauri::State<'_, AppState>) -> SnippetSettings {
+    state.settings.lock().unwrap().snippets.clone()
+}
+
+// Command to replace the snippets
+#[tauri::command]
+fn update_snippet_settings(
+    settings: SnippetSettings,
+    app_handle: AppHandle<Wry>,
+) -> Result<(), String> {
+    let updated = modify_settings(&app_handle, |current| current.snippets = settings)?;
+    println!(
+        "Snippet settings updated: enabled={}, {} snippets",
+        updated.snippets.enabled,
+        updated.snippets.snippets.len()
+    );
+    Ok(())
+}
+
+// Command to get the post-processing model and system prompt
+#[tauri::command]
+fn get_post_processing_settings(state: tauri::State<'_, AppState>) -> PostProcessSettings {
+    state.settings.lock().unwrap().post_processing.clone()
+}
+
+// Command to update the post-processing model and system prompt
+#[tauri::command]
+fn update_post_processing_settings(
+    settings: PostProcessSettings,
+    app_handle: AppHandle<Wry>,
+) -> Result<(), String> {
+    let updated = modify_settings(&app_handle, |current| current.post_processing = settings)?;
+    println!(
+        "Post-processing settings updated: enabled={}, model={}",
+        updated.post_processing.enabled, updated.post_processing.model
+    );
+    Ok(())
+}
+
+// Command to list the report templates
+#[tauri::command]
+fn list_templates(app_handle: AppHandle<Wry>) -> Result<Vec<Template>, String> {
+    let store = template_store(&app_handle).map_err(|e| e.to_string())?;
+    store.list().map_err(|e| e.to_string())
+}
+
+// Command to get one report template
+#[tauri::command]
+fn get_template(name: String, app_handle: AppHandle<Wry>) -> Result<Option<Template>, String> {
+    let store = template_store(&app_handle).map_err(|e| e.to_string())?;
+    store.get(&name).map_err(|e| e.to_string())
+}
+
+// Command to create or replace a report template
+#[tauri::command]
+fn save_template(
+    name: String,
+    content: String,
+    app_handle: AppHandle<Wry>,
+) -> Result<Template, String> {
+    let store = template_store(&app_handle).map_err(|e| e.to_string())?;
+    let template = store.save(&name, &content).map_err(|e| e.to_string())?;
+    println!(
+        "Report template {} saved with fields: {}",
+        name,
+        template.fields.join(", ")
+    );
+    Ok(template)
+}
+
+// Command to delete a report template. Deleting the active one turns
+// templates off.
+#[tauri::command]
+fn delete_template(name: String, app_handle: AppHandle<Wry>) -> Result<bool, String> {
+    let store = template_store(&app_handle).map_err(|e| e.to_string())?;
+    let deleted = store.delete(&name).map_err(|e| e.to_string())?;
+    if current_settings(&app_handle).templates.active.as_deref() == Some(name.as_str()) {
+        modify_settings(&app_handle, |current| current.templates.active = None)?;
+    }
+    Ok(deleted)
+}
+
+// Command to choose the report template dictation is rendered into; None
+// inserts dictation as is
+#[tauri::command]
+fn set_active_template(name: Option<String>, app_handle: AppHandle<Wry>) -> Result<(), String> {
+    if let Some(name) = &name {
+        let store = template_store(&app_handle).map_err(|e| e.to_string())?;
+        if store.get(name).map_err(|e| e.to_string())?.is_none() {
+            return Err(format!("No report template named \"{}\"", name));
+        }
+    }
+    modify_settings(&app_handle, |current| {
+        current.templates.active = name.clone()
+    })?;
+    println!(
+        "Active report template: {}",
+        name.as_deref().unwrap_or("none")
+    );
+    Ok(())
+}
+
+// Command to render text, e.g. a history entry being exported, into a
+// report template. Defaults to the active template.
+#[tauri::command]
+async fn apply_template(
+    text: String,
+    name: Option<String>,
+    app_handle: AppHandle<Wry>,
+) -> Result<RenderedReport, String> {
+    let name = name
+        .or_else(|| current_settings(&app_handle).templates.active)
+        .ok_or_else(|| "No report template chosen".to_string())?;
+    let store = template_store(&app_handle).map_err(|e| e.to_string())?;
+    let template = store
+        .get(&name)
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("No report template named \"{}\"", name))?;
+    render_template(&app_handle, &template, &text)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+// Command to get the voice activity detection settings
+#[tauri::command]
+fn get_vad_config(state: tauri::State<'_, AppState>) -> VadConfig {
+    state.settings.lock().unwrap().vad.clone()
+}
+
+// Command to update the voice activity detection settings
+#[tauri::command]
+fn update_vad_config(config: VadConfig, app_handle: AppHandle<Wry>) -> Result<(), String> {
+    modify_settings(&app_handle, |settings| settings.vad = config)?;
+    Ok(())
+}
+
+// Details about a finished recording, stored with its text in the history
+struct RecordingInfo {
+    provider: String,
+    duration_secs: f64,
+    audio: Option<Vec<u8>>,
+}
+
+// Hand finished text to the UI, type it at the cursor and record it in the history
+fn deliver_transcription<R: Runtime>(text: &str, info: RecordingInfo, app_handle: &AppHandle<R>) {
+    // Send the text to UI
+    let _ = app_handle.emit("transcription", text);
+    let app_state = app_handle.state::<AppState>();
+    *app_state.last_transcript.lock().unwrap() = Some(LastTranscript {
+        text: text.to_string(),
+        created_at: archive::now_millis(),
+    });
+
+    // Type the text at the cursor position
+    let text_to_type = text.to_string();
+    let app_handle_clone = app_handle.clone();
+    thread::spawn(move || {
+        // Wait a moment before starting to type
+        thread::sleep(Duration::from_millis(0));
+
+        // Remember where the text went, so it can be recovered from the history
+        let target_app = frontmost_app_name();
+        
+        if let Err(e) = type_text_at_cursor(&text_to_type, &app_handle_clone) {
+            eprintln!("Error typing text: {}", e);
+        } else {
+            println!("Successfully typed text at cursor");
+        }
+
+        save_to_history(&text_to_type, &info, target_app.as_deref(), &app_handle_clone);
+    });
+}
+
+fn save_to_history<R: Runtime>(
+    text: &str,
+    info: &RecordingInfo,
+    target_app: Option<&str>,
+    app_handle: &AppHandle<R>,
+) {
+    let app_state = app_handle.state::<AppState>();
+    let history = match &app_state.history {
+        Some(history) => history,
+        None => return,
+    };
+    if !current_settings(app_handle).history.enabled {
+        return;
+    }
+
+    let entry = NewHistoryEntry {
+        text,
+        duration_secs: info.duration_secs,
+        provider: &info.provider,
+        target_app,
+        audio: info.audio.as_deref(),
+    };
+    match history.insert(entry) {
+        Ok(id) => {
+            let _ = app_handle.emit("history-updated", id);
+        }
+        Err(e) => eprintln!("Error saving transcription to history: {}", e),
+    }
+}
+
+fn history_store(state: &tauri::State<'_, AppState>) -> Result<Arc<HistoryStore>, String> {
+    state
+        .history
+        .clone()
+        .ok_or_else(|| "Transcription history is unavailable".to_string())
+}
+
+// Command to list history entries, pinned first then newest first
+#[tauri::command]
+fn list_history(
+    limit: Option<u32>,
+    offset: Option<u32>,
+    state: tauri::State<'_, AppState>,
+) -> Result<Vec<HistoryEntry>, String> {
+    history_store(&state)?
+        .list(limit.unwrap_or(50), offset.unwrap_or(0))
+        .map_err(|e| format!("Failed to read history: {}", e))
+}
+
+// Command to full-text search the history
+#[tauri::command]
+fn search_history(
+    query: String,
+    limit: Option<u32>,
+    state: tauri::State<'_, AppState>,
+) -> Result<Vec<HistoryEntry>, String> {
+    history_store(&state)?
+        .search(&query, limit.unwrap_or(50))
+        .map_err(|e| format!("Failed to search history: {}", e))
+}
+
+// Command to delete a history entry
+#[tauri::command]
+fn delete_history_entry(id: i64, state: tauri::State<'_, AppState>) -> Result<bool, String> {
+    history_store(&state)?
+        .delete(id)
+        .map_err(|e| format!("Failed to delete history entry: {}", e))
+}
+
+// Command to pin or unpin a history entry
+#[tauri::command]
+fn set_history_entry_pinned(
+    id: i64,
+    pinned: bool,
+    state: tauri::State<'_, AppState>,
+) -> Result<bool, String> {
+    history_store(&state)?
+        .set_pinned(id, pinned)
+        .map_err(|e| format!("Failed to update history entry: {}", e))
+}
+
+// Command to get the recorded audio of a history entry as WAV, for playback.
+// The bytes are sent as is rather than as a JSON array of numbers.
+#[tauri::command]
+fn get_history_audio(
+    id: i64,
+    state: tauri::State<'_, AppState>,
+) -> Result<tauri::ipc::Response, String> {
+    history_store(&state)?
+        .audio(id)
+        .map_err(|e| format!("Failed to read history audio: {}", e))?
+        .map(tauri::ipc::Response::new)
+        .ok_or_else(|| format!("No audio was kept for history entry {}", id))
+}
+
+// Command to get whether transcriptions (and their audio) are kept
+#[tauri::command]
+fn get_history_options(state: tauri::State<'_, AppState>) -> HistoryOptions {
+    state.settings.lock().unwrap().history.clone()
+}
+
+// Command to set whether transcriptions (and their audio) are kept
+#[tauri::command]
+fn update_history_options(
+    options: HistoryOptions,
+    app_handle: AppHandle<Wry>,
+) -> Result<(), String> {
+    modify_settings(&app_handle, |settings| settings.history = options)?;
+    Ok(())
+}
+
+fn dictionary_store(state: &tauri::State<'_, AppState>) -> Result<Arc<DictionaryStore>, String> {
+    state
+        .dictionary
+        .clone()
+        .ok_or_else(|| "The replacement dictionary is unavailable".to_string())
+}
+
+// Command to list the replacement dictionary in the order it is applied
+#[tauri::command]
+fn list_dictionary_entries(
+    state: tauri::State<'_, AppState>,
+) -> Result<Vec<DictionaryEntry>, String> {
+    Ok(dictionary_store(&state)?.entries())
+}
+
+// Command to add a replacement rule at the end of the dictionary
+#[tauri::command]
+fn add_dictionary_entry(
+    entry: DictionaryEntry,
+    state: tauri::State<'_, AppState>,
+) -> Result<DictionaryEntry, String> {
+    dictionary_store(&state)?
+        .add(entry)
+        .map_err(|e| e.to_string())
+}
+
+// Command to change a replacement rule; returns false if it no longer exists
+#[tauri::command]
+fn update_dictionary_entry(
+    entry: DictionaryEntry,
+    state: tauri::State<'_, AppState>,
+) -> Result<bool, String> {
+    dictionary_store(&state)?
+        .update(entry)
+        .map_err(|e| e.to_string())
+}
+
+// Command to remove a replacement rule
+#[tauri::command]
+fn delete_dictionary_entry(id: String, state: tauri::State<'_, AppState>) -> Result<bool, String> {
+    dictionary_store(&state)?
+        .delete(&id)
+        .map_err(|e| e.to_string())
+}
+
+// Command to import rules from a .csv or .json file, added to the dictionary
+// or replacing it. Returns how many were added.
+#[tauri::command]
+fn import_dictionary(
+    path: String,
+    replace_all: bool,
+    state: tauri::State<'_, AppState>,
+) -> Result<usize, String> {
+    let store = dictionary_store(&state)?;
+    let entries = dictionary::read_file(Path::new(&path))
+        .map_err(|e| format!("Failed to import {}: {}", path, e))?;
+    let added = store
+        .import(entries, replace_all)
+        .map_err(|e| e.to_string())?;
+    println!("Imported {} dictionary entries from {}", added, path);
+    Ok(added)
+}
+
+// Command to export the dictionary as a .csv or .json file
+#[tauri::command]
+fn export_dictionary(path: String, state: tauri::State<'_, AppState>) -> Result<(), String> {
+    let entries = dictionary_store(&state)?.entries();
+    dictionary::write_file(Path::new(&path), &entries)
+        .map_err(|e| format!("Failed to export {}: {}", path, e))
+}
+
+// Command to get whether the dictionary is applied and sent as prompt hints
+#[tauri::command]
+fn get_dictionary_options(state: tauri::State<'_, AppState>) -> DictionaryOptions {
+    state.settings.lock().unwrap().dictionary.clone()
+}
+
+// Command to set whether the dictionary is applied and sent as prompt hints
+#[tauri::command]
+fn update_dictionary_options(
+    options: DictionaryOptions,
+    app_handle: AppHandle<Wry>,
+) -> Result<(), String> {
+    modify_settings(&app_handle, |settings| settings.dictionary = options)?;
+    Ok(())
+}
+
+// Folder recordings are archived in
+fn archive_dir<R: Runtime>(app_handle: &AppHandle<R>, options: &ArchiveOptions) -> Result<PathBuf> {
+    match &options.folder {
+        Some(folder) => Ok(PathBuf::from(folder)),
+        None => {
+            let app_data_dir = app_handle
+                .path()
+                .app_data_dir()
+                .map_err(|e| anyhow!("Failed to resolve app data directory: {}", e))?;
+            Ok(app_data_dir.join("recordings"))
+        }
+    }
+}
+
+fn recording_archive<R: Runtime>(app_handle: &AppHandle<R>) -> Result<RecordingArchive, String> {
+    let options = current_settings(app_handle).archive;
+    let dir = archive_dir(app_handle, &options).map_err(|e| e.to_string())?;
+    Ok(RecordingArchive::new(&dir))
+}
+
+fn archived_transcript(
+    text: &str,
+    provider: &str,
+    settings: &TranscriptionSettings,
+) -> ArchivedTranscript {
+    ArchivedTranscript {
+        text: text.to_string(),
+        created_at: archive::now_millis(),
+        provider: provider.to_string(),
+        model: settings.options.model.clone(),
+        language: settings.options.language.clone(),
+    }
+}
+
+// Keep a finished recording in the archive folder before it is transcribed,
+// so recordings that fail or are queued can still be recovered. Returns the
+// id to add the transcript to.
+fn archive_recording<R: Runtime>(
+    app_handle: &AppHandle<R>,
+    options: &ArchiveOptions,
+    samples: &[f32],
+    duration_secs: f64,
+) -> Option<String> {
+    let saved = archive_dir(app_handle, options).and_then(|dir| {
+        let format = options.format.audio_format();
+        let encoded = encoding::encode(samples, TARGET_SAMPLE_RATE, format)?;
+        RecordingArchive::new(&dir).save(&encoded.data, &options.format, duration_secs)
+    });
+    let id = match saved {
+        Ok(recording) => {
+            println!("Recording archived as {}", recording.id);
+            let _ = app_handle.emit("recording-archived", &recording.id);
+            Some(recording.id)
+        }
+        Err(e) => {
+            eprintln!("Failed to archive recording: {}", e);
+            None
+        }
+    };
+    apply_archive_retention(app_handle, options);
+    id
+}
+
+// Add the transcript of a recording archived by `archive_recording`
+fn archive_transcript<R: Runtime>(
+    app_handle: &AppHandle<R>,
+    options: &ArchiveOptions,
+    id: &str,
+    text: &str,
+    provider: &str,
+) {
+    let transcript =
+        archived_transcript(text, provider, &current_settings(app_handle).transcription);
+    let result = archive_dir(app_handle, options)
+        .and_then(|dir| RecordingArchive::new(&dir).add_transcript(id, transcript));
+    if let Err(e) = result {
+        eprintln!("Failed to archive the transcript of {}: {}", id, e);
+    }
+}
+
+// Delete archived recordings that are past the age or size limit
+fn apply_archive_retention<R: Runtime>(app_handle: &AppHandle<R>, options: &ArchiveOptions) {
+    let max_age = options
+        .max_age_days
+        .map(|days| Duration::from_secs(days as u64 * 24 * 60 * 60));
+    let max_bytes = options.max_size_mb.map(|mb| mb * 1024 * 1024);
+    if max_age.is_none() && max_bytes.is_none() {
+        return;
+    }
+
+    let result = archive_dir(app_handle, options)
+        .and_then(|dir| RecordingArchive::new(&dir).apply_retention(max_age, max_bytes));
+    match result {
+        Ok(0) => (),
+        Ok(removed) => println!(
+            "Deleted {} archived recordings past the retention limits",
+            removed
+        ),
+        Err(e) => eprintln!("Failed to apply archive retention: {}", e),
+    }
+}
+
+// Command to get the recording archive options
+#[tauri::command]
+fn get_archive_options(state: tauri::State<'_, AppState>) -> ArchiveOptions {
+    state.settings.lock().unwrap().archive.clone()
+}
+
+// Command to update the recording archive options; new limits apply at once
+#[tauri::command]
+fn update_archive_options(
+    options: ArchiveOptions,
+    app_handle: AppHandle<Wry>,
+) -> Result<(), String> {
+    let settings = modify_settings(&app_handle, |settings| settings.archive = options)?;
+    apply_archive_retention(&app_handle, &settings.archive);
+    Ok(())
+}
+
+// Command to list archived recordings, newest first
+#[tauri::command]
+fn list_archived_recordings(app_handle: AppHandle<Wry>) -> Result<Vec<ArchivedRecording>, String> {
+    recording_archive(&app_handle)?
+        .list()
+        .map_err(|e| format!("Failed to read the recording archive: {}", e))
+}
+
+// Command to delete an archived recording and its transcripts
+#[tauri::command]
+fn delete_archived_recording(id: String, app_handle: AppHandle<Wry>) -> Result<bool, String> {
+    recording_archive(&app_handle)?
+        .delete(&id)
+        .map_err(|e| format!("Failed to delete archived recording: {}", e))
+}
+
+// Command to transcribe an archived recording again, with the given
+// transcription settings or the current ones. The new transcript is added to
+// the recording next to the earlier ones.
+#[tauri::command]
+async fn retranscribe_recording(
+    id: String,
+    transcription: Option<TranscriptionSettings>,
+    app_handle: AppHandle<Wry>,
+) -> Result<ArchivedTranscript, String> {
+    let archive = recording_archive(&app_handle)?;
+    let recording = archive
+        .get(&id)
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("No archived recording {}", id))?;
+    let extension = Path::new(&recording.audio_file)
+        .extension()
+        .and_then(|e| e.to_str());
+    let samples = archive
+        .audio(&recording)
+        .and_then(|data| audio::decode(data, extension))
+        .map_err(|e| format!("Failed to read archived audio: {}", e))?;
+
+    let mut transcription =
+        transcription.unwrap_or_else(|| current_settings(&app_handle).transcription);
+    transcription.options = transcription.options.normalized();
+    transcription
+        .options
+        .validate()
+        .map_err(|e| e.to_string())?;
+    let provider = build_transcription_provider(&app_handle, transcription.clone())
+        .map_err(|e| e.to_string())?;
+
+    let vad_config = current_settings(&app_handle).vad;
+    let cancelled = start_file_transcription(&app_handle, &id)?;
+    let result =
+        pipeline::transcribe_samples(provider.as_ref(), &samples, &vad_config, &cancelled).await;
+    finish_file_transcription(&app_handle, &id);
+    let text = result.map_err(|e| format!("Transcription error: {}", e))?;
+
+    let transcript = archived_transcript(&text, provider.name(), &transcription);
+    archive
+        .add_transcript(&id, transcript.clone())
+        .map_err(|e| format!("Failed to save transcript: {}", e))?;
+    Ok(transcript)
+}
+
+// Register a file transcription so it can be cancelled, and return its cancel
+// flag. `key` is the file path, or the id of an archived recording.
+fn start_file_transcription<R: Runtime>(
+    app_handle: &AppHandle<R>,
+    key: &str,
+) -> Result<Arc<AtomicBool>, String> {
+    let state = app_handle.state::<AppState>();
+    let mut running = state.file_transcriptions.lock().unwrap();
+    if running.contains_key(key) {
+        return Err(format!("{} is already being transcribed", key));
+    }
+    let cancelled = Arc::new(AtomicBool::new(false));
+    running.insert(key.to_string(), cancelled.clone());
+    Ok(cancelled)
+}
+
+fn finish_file_transcription<R: Runtime>(app_handle: &AppHandle<R>, key: &str) {
+    let state = app_handle.state::<AppState>();
+    state.file_transcriptions.lock().unwrap().remove(key);
+}
+
+// Command to cancel a running file transcription, by the file's path or the
+// archived recording's id. It stops before the next chunk is sent. Returns
+// false if nothing with that key is being transcribed.
+#[tauri::command]
+fn cancel_file_transcription(key: String, state: tauri::State<'_, AppState>) -> bool {
+    match state.file_transcriptions.lock().unwrap().get(&key) {
+        Some(cancelled) => {
+            cancelled.store(true, Ordering::SeqCst);
+            true
+        }
+        None => false,
+    }
+}
+
+// Command to transcribe an existing audio file, e.g. a voicemail or bodycam
+// recording. The text goes to the history like a recording's would, but is
+// returned rather than typed at the cursor.
+#[tauri::command]
+async fn transcribe_file(path: String, app_handle: AppHandle<Wry>) -> Result<String, String> {
+    transcribe_audio_file(&app_handle, PathBuf::from(path)).await
+}
+
+// Decode an audio file to 16 kHz mono and send it through the same chunking,
+// silence trimming, provider and dictation steps as a live recording
+async fn transcribe_audio_file<R: Runtime>(
+    app_handle: &AppHandle<R>,
+    path: PathBuf,
+) -> Result<String, String> {
+    let path_str = path.to_string_lossy().to_string();
+    let cancelled = start_file_transcription(app_handle, &path_str)?;
+    let _ = app_handle.emit("file-transcription-started", &path_str);
+
+    let result = async {
+        let decode_path = path.clone();
+        let samples =
+            tauri::async_runtime::spawn_blocking(move || audio::decode_file(&decode_path))
+                .await
+                .map_err(|e| e.to_string())?
+                .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let duration_secs = samples.len() as f64 / TARGET_SAMPLE_RATE as f64;
+        println!(
+            "Transcribing {} ({:.1} seconds of audio)",
+            path.display(),
+            duration_secs
+        );
+
+        let provider = load_transcription_provider(app_handle).map_err(|e| e.to_string())?;
+        let vad_config = current_settings(app_handle).vad;
+        let text =
+            pipeline::transcribe_samples(provider.as_ref(), &samples, &vad_config, &cancelled)
+                .await
+                .map_err(|e| format!("Transcription error: {}", e))?;
+        let text = prepare_dictation(app_handle, text).await;
+
+        if !text.is_empty() {
+            let info = RecordingInfo {
+                provider: provider.name().to_string(),
+                duration_secs,
+                audio: None,
+            };
+            save_to_history(&text, &info, None, app_handle);
+        }
+        Ok::<String, String>(text)
+    }
+    .await;
+    finish_file_transcription(app_handle, &path_str);
+
+    match &result {
+        Ok(text) => {
+            let _ = app_handle.emit(
+                "file-transcribed",
+                json!({ "path": path_str, "text": text }),
+            );
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            let _ = app_handle.emit(
+                "file-transcription-failed",
+                json!({ "path": path_str, "error": e }),
+            );
+        }
+    }
+    result
+}
+
+// How often queued recordings are retried in the background
+const QUEUE_REPLAY_INTERVAL: Duration = Duration::from_secs(60);
+
+// Save a recording whose transcription failed for a transient reason, e.g. no
+// network, so it can be replayed later. Returns false if it couldn't be saved.
+fn queue_recording<R: Runtime>(
+    app_handle: &AppHandle<R>,
+    outcomes: Vec<ChunkOutcome>,
+    duration_secs: f64,
+    provider: &str,
+    error: &TranscriptionError,
+) -> bool {
+    let queue = match &app_handle.state::<AppState>().queue {
+        Some(queue) => queue.clone(),
+        None => return false,
+    };
+
+    let chunks = outcomes
+        .into_iter()
+        .map(|outcome| match outcome {
+            ChunkOutcome::Text(text) => Ok(NewChunk::Text(text)),
+            ChunkOutcome::Failed(_, wav_data) => Ok(NewChunk::Audio(wav_data)),
+            ChunkOutcome::Interrupted(_, samples) => {
+                audio::encode_wav(&samples, TARGET_SAMPLE_RATE).map(NewChunk::Audio)
+            }
+        })
+        .collect::<Result<Vec<_>>>();
+    let chunks = match chunks {
+        Ok(chunks) => chunks,
+        Err(e) => {
+            eprintln!("Failed to queue recording: {}", e);
+            return false;
+        }
+    };
+    match queue.add(chunks, duration_secs, provider, error.clone()) {
+        Ok(job) => {
+            println!("Recording queued as job {} for a later retry", job.id);
+            let _ = app_handle.emit("transcription-queued", &job);
+            let _ = app_handle.emit(
+                "error",
+                "Transcription failed. The recording was saved and will be transcribed when the connection is back.",
+            );
+            pending_jobs_changed(app_handle);
+            true
+        }
+        Err(e) => {
+            eprintln!("Failed to queue recording: {}", e);
+            false
+        }
+    }
+}
+
+fn pending_job_count<R: Runtime>(app_handle: &AppHandle<R>) -> usize {
+    match &app_handle.state::<AppState>().queue {
+        Some(queue) => queue.count(),
+        None => 0,
+    }
+}
+
+// Tell the UI and the tray that jobs were added or removed
+fn pending_jobs_changed<R: Runtime>(app_handle: &AppHandle<R>) {
+    let _ = app_handle.emit("pending-jobs-updated", pending_job_count(app_handle));
+    refresh_tray_menu(app_handle);
+    update_tray_badge(app_handle);
+}
+
+// Transcribe one queued recording with the current provider. The text is
+// saved to the history rather than typed, since the cursor has long moved on.
+async fn replay_job<R: Runtime>(
+    app_handle: &AppHandle<R>,
+    id: &str,
+) -> Result<String, TranscriptionError> {
+    let unavailable = |message: String| TranscriptionError::Other { message };
+    let queue = app_handle
+        .state::<AppState>()
+        .queue
+        .clone()
+        .ok_or_else(|| unavailable("The offline queue is unavailable".to_string()))?;
+    let provider =
+        load_transcription_provider(app_handle).map_err(|e| TranscriptionError::classify(&e))?;
+    let provider_name = provider.name().to_string();
+
+    // Delivered while the queue still holds the job, so a replay started
+    // meanwhile finds it gone instead of delivering it again
+    let deliver = |job: PendingJob, text: String| async move {
+        if text.is_empty() {
+            return text;
+        }
+        let text = prepare_dictation(app_handle, text).await;
+        if !text.is_empty() {
+            let info = RecordingInfo {
+                provider: provider_name,
+                duration_secs: job.duration_secs,
+                audio: None,
+            };
+            save_to_history(&text, &info, None, app_handle);
+        }
+        text
+    };
+    let result = match queue
+        .replay(id, provider.as_ref(), &RetryPolicy::default(), deliver)
+        .await
+    {
+        Ok(Some(text)) => Ok(text),
+        Ok(None) => return Err(unavailable(format!("No queued job {}", id))),
+        Err(error) => Err(error),
+    };
+    match &result {
+        Ok(text) => {
+            println!("Queued job {} transcribed", id);
+            let _ = app_handle.emit("queued-job-completed", json!({ "id": id, "text": text }));
+        }
+        Err(error) => {
+            eprintln!("Queued job {} failed again: {}", id, error);
+            let _ = app_handle.emit(
+                "queued-job-failed",
+                json!({
+                    "id": id,
+                    "error": error,
+                    "retryable": error.is_retryable(),
+                }),
+            );
+        }
+    }
+    pending_jobs_changed(app_handle);
+    result
+}
+
+// Replay every queued recording, oldest first. Stops at the first network
+// failure, since the rest would fail the same way.
+async fn replay_pending_jobs<R: Runtime>(app_handle: AppHandle<R>) {
+    let queue = match &app_handle.state::<AppState>().queue {
+        Some(queue) => queue.clone(),
+        None => return,
+    };
+    let jobs = match queue.list() {
+        Ok(jobs) => jobs,
+        Err(e) => {
+            eprintln!("Failed to read the offline queue: {}", e);
+            return;
+        }
+    };
+
+    for job in jobs {
+        if let Err(TranscriptionError::Network { .. }) = replay_job(&app_handle, &job.id).await {
+            println!("Still offline, leaving the remaining jobs queued");
+            break;
+        }
+    }
+}
+
+// Retry queued recordings now and then, so they go out once the connection is back
+async fn replay_pending_jobs_periodically<R: Runtime>(app_handle: AppHandle<R>) {
+    loop {
+        // Leave the bandwidth to a recording in progress
+        let recording = app_handle
+            .state::<AppState>()
+            .is_recording
+            .load(Ordering::SeqCst);
+        if !recording && pending_job_count(&app_handle) > 0 {
+            replay_pending_jobs(app_handle.clone()).await;
+        }
+        tokio::time::sleep(QUEUE_REPLAY_INTERVAL).await;
+    }
+}
+
+fn job_queue(state: &tauri::State<'_, AppState>) -> Result<Arc<JobQueue>, String> {
+    state
+        .queue
+        .clone()
+        .ok_or_else(|| "The offline queue is unavailable".to_string())
+}
+
+// Command to list the recordings waiting to be transcribed, oldest first
+#[tauri::command]
+fn list_pending_jobs(state: tauri::State<'_, AppState>) -> Result<Vec<PendingJob>, String> {
+    job_queue(&state)?
+        .list()
+        .map_err(|e| format!("Failed to read the offline queue: {}", e))
+}
+
+// Command to transcribe a queued recording now; returns its text
+#[tauri::command]
+async fn retry_job(id: String, app_handle: AppHandle<Wry>) -> Result<String, String> {
+    replay_job(&app_handle, &id)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+// Command to delete a queued recording without transcribing it
+#[tauri::command]
+fn discard_job(id: String, app_handle: AppHandle<Wry>) -> Result<bool, String> {
+    let queue = job_queue(&app_handle.state::<AppState>())?;
+    let removed = queue
+        .remove(&id)
+        .map_err(|e| format!("Failed to discard queued job: {}", e))?;
+    if removed {
+        pending_jobs_changed(&app_handle);
+    }
+    Ok(removed)
+}
+
+// How many chunks of one recording may be transcribed at the same time
+const MAX_CONCURRENT_CHUNKS: usize = 3;
+
+fn save_preferred_input_device<R: Runtime>(
+    app_handle: &AppHandle<R>,
+    name: Option<&str>,
+) -> Result<(), String> {
+    let name = name.map(|n| n.to_string());
+    modify_settings(app_handle, |settings| settings.input_device = name)?;
+    Ok(())
+}
+
+// Command to list the available input devices and their supported configs
+#[tauri::command]
+fn list_input_devices() -> Vec<InputDeviceInfo> {
+    devices::list_input_devices()
+}
+
+// Command to get the preferred input device; None means the system default
+#[tauri::command]
+fn get_input_device(state: tauri::State<'_, AppState>) -> Option<String> {
+    state.settings.lock().unwrap().input_device.clone()
+}
+
+// Command to set the preferred input device, used from the next recording on
+#[tauri::command]
+fn set_input_device(name: Option<String>, app_handle: AppHandle<Wry>) -> Result<(), String> {
+    save_preferred_input_device(&app_handle, name.as_deref())
+}
+
+// Define event IDs for menu items
+const TRAY_ID: &str = "main-tray";
+const TRAY_RECORD_AUDIO: &str = "tray-record-audio";
+const TRAY_SHOW_WINDOW: &str = "tray-show-window";
+const TRAY_HIDE_WINDOW: &str = "tray-hide-window";
+const TRAY_QUIT: &str = "tray-quit";
+const TRAY_SEPARATOR: &str = "tray-separator";
+const TRAY_DEVICE_DEFAULT: &str = "tray-device-default";
+const TRAY_DEVICE_REFRESH: &str = "tray-device-refresh";
+const TRAY_DEVICE_PREFIX: &str = "tray-device:";
+const TRAY_RETRY_PENDING: &str = "tray-retry-pending";
+
+// Create a function to set up the tray menu for Tauri v2
+fn create_tray_menu<R: Runtime>(app_handle: &AppHandle<R>) -> Menu<R> {
+    // Create menu items - note we pass app_handle to build()
+    let record = MenuItemBuilder::with_id(TRAY_RECORD_AUDIO, "Record Audio")
+        .build(app_handle)
+        .unwrap();
+    let show = MenuItemBuilder::with_id(TRAY_SHOW_WINDOW, "Show Window")
+        .build(app_handle)
+        .unwrap();
+    let hide = MenuItemBuilder::with_id(TRAY_HIDE_WINDOW, "Hide Window")
+        .build(app_handle)
+        .unwrap();
+
+    // Create a separator - in v2 we use a MenuItemBuilder with an empty label
+    let separator = MenuItemBuilder::with_id(TRAY_SEPARATOR, "")
+        .build(app_handle)
+        .unwrap();
+
+    let quit = MenuItemBuilder::with_id(TRAY_QUIT, "Quit")
+        .build(app_handle)
+        .unwrap();
+
+    // Input device submenu, with the current choice checked
+    let preferred_device = current_settings(app_handle).input_device;
+    let mut device_menu = SubmenuBuilder::new(app_handle, "Input Device").item(
+        &CheckMenuItemBuilder::with_id(TRAY_DEVICE_DEFAULT, "System Default")
+            .checked(preferred_device.is_none())
+            .build(app_handle)
+            .unwrap(),
+    );
+    for name in devices::input_device_names() {
+        device_menu = device_menu.item(
+            &CheckMenuItemBuilder::with_id(format!("{}{}", TRAY_DEVICE_PREFIX, name), &name)
+                .checked(preferred_device.as_deref() == Some(name.as_str()))
+                .build(app_handle)
+                .unwrap(),
+        );
+    }
+    let device_menu = device_menu
+        .separator()
+        .item(
+            &MenuItemBuilder::with_id(TRAY_DEVICE_REFRESH, "Refresh Devices")
+                .build(app_handle)
+                .unwrap(),
+        )
+        .build()
+        .unwrap();
+
+    // Fixed: Pass app_handle as first argument and borrow the array
+    let menu = Menu::with_items(
+        app_handle,
+        &[&record, &device_menu, &show, &hide, &separator, &quit],
+    )
+    .unwrap();
+
+    // Offer to retry queued recordings while there are any
+    let pending = pending_job_count(app_handle);
+    if pending > 0 {
+        let retry = MenuItemBuilder::with_id(
+            TRAY_RETRY_PENDING,
+            format!("Retry Pending Transcriptions ({})", pending),
+        )
+        .build(app_handle)
+        .unwrap();
+        let _ = menu.insert(&retry, 1);
+    }
+    menu
+}
+
+// Rebuild the tray menu, e.g. after devices were plugged in or the selection changed
+fn refresh_tray_menu<R: Runtime>(app_handle: &AppHandle<R>) {
+    if let Some(tray) = app_handle.tray_by_id(TRAY_ID) {
+        if let Err(e) = tray.set_menu(Some(create_tray_menu(app_handle))) {
+            eprintln!("Failed to refresh tray menu: {}", e);
+        }
+    }
+}
+
+// Show the number of queued recordings beside the tray icon. Only macOS draws
+// a title there, so the tooltip carries it as well.
+fn update_tray_badge<R: Runtime>(app_handle: &AppHandle<R>) {
+    let tray = match app_handle.tray_by_id(TRAY_ID) {
+        Some(tray) => tray,
+        None => return,
+    };
+    let pending = pending_job_count(app_handle);
+    let (title, tooltip) = if pending > 0 {
+        (
+            Some(pending.to_string()),
+            format!("ReportBlitz ({} pending transcriptions)", pending),
+        )
+    } else {
+        (None, "ReportBlitz".to_string())
+    };
+    let _ = tray.set_title(title);
+    let _ = tray.set_tooltip(Some(tooltip));
+}
+
+// Set up the tray icon
+fn setup_system_tray(app_handle: &AppHandle<Wry>) -> Result<(), Box<dyn std::error::Error>> {
+    // Create the tray menu
+    let tray_menu = create_tray_menu(app_handle);
+
+    // Build the tray icon
+    TrayIconBuilder::with_id(TRAY_ID)
+        .menu(&tray_menu)
+        .icon_as_template(true)
+        .build(app_handle)?;
+    update_tray_badge(app_handle);
+
+    // Set up the menu event handler using on_menu_event
+    app_handle.on_menu_event(move |app, event| {
+        handle_tray_event(app, event);
+    });
+
+    Ok(())
+}
+
+// Handle menu events
+fn handle_tray_event(app: &AppHandle<Wry>, event: MenuEvent) {
+    match event.id.as_ref() {
+        TRAY_QUIT => {
+            std::process::exit(0);
+        }
+        TRAY_RECORD_AUDIO => {
+            // Call the record function
+            if let Err(e) = record_audio(app.clone()) {
+                eprintln!("Error starting recording from tray: {}", e);
+            }
+        }
+        TRAY_SHOW_WINDOW => {
+            if let Some(window) = app.get_webview_window("main") {
+                let _ = window.show();
+                let _ = window.set_focus();
+            }
+        }
+        TRAY_HIDE_WINDOW => {
+            if let Some(window) = app.get_webview_window("main") {
+                let _ = window.hide();
+            }
+        }
+        TRAY_DEVICE_DEFAULT => {
+            if let Err(e) = save_preferred_input_device(app, None) {
+                eprintln!("Error selecting input device from tray: {}", e);
+            }
+        }
+        TRAY_DEVICE_REFRESH => {
+            refresh_tray_menu(app);
+        }
+        TRAY_RETRY_PENDING => {
+            tauri::async_runtime::spawn(replay_pending_jobs(app.clone()));
+        }
+        id if id.starts_with(TRAY_DEVICE_PREFIX) => {
+            let name = &id[TRAY_DEVICE_PREFIX.len()..];
+            if let Err(e) = save_preferred_input_device(app, Some(name)) {
+                eprintln!("Error selecting input device from tray: {}", e);
+            }
+        }
+        _ => {}
+    }
+}
+
+
+fn type_text_at_cursor(text: &str, app_handle: &AppHandle<impl Runtime>) -> Result<()> {
+    // Check if strict mode is enabled
+    let settings = current_settings(app_handle);
+    let strict_mode = settings.strict_text_field_mode;
+    let mode = settings.insertion_mode;
+    
+    // If strict mode is enabled, try to detect if we're in a text field
+    if strict_mode && mode.uses_keyboard() && !is_likely_text_field() {
+        println!("Strict mode enabled and no text field detected, skipping text insertion");
+        
+        // Notify the user
+        let _ = app_handle.emit("text-field-detection", json!({
+            "detected": false,
+            "message": "No text field detected. Move your cursor to a text field and try again."
+        }));
+        
+        return Ok(());
+    }
+    
+    println!("Inserting text using {} mode", mode.as_str());
+    insertion::insert_text(text, mode)?;
+
+    if mode == InsertionMode::ClipboardOnly {
+        let _ = app_handle.emit("text-copied", text);
+    }
+    
+    Ok(())
+}
+
+// Command to get how transcribed text is inserted
+#[tauri::command]
+fn get_insertion_mode(state: tauri::State<'_, AppState>) -> InsertionMode {
+    state.settings.lock().unwrap().insertion_mode
+}
+
+// Command to set how transcribed text is inserted
+#[tauri::command]
+fn set_insertion_mode(mode: InsertionMode, app_handle: AppHandle<Wry>) -> Result<(), String> {
+    modify_settings(&app_handle, |settings| settings.insertion_mode = mode)?;
+    Ok(())
+}
+
+// Name of the application that currently has focus, if it can be determined
+fn frontmost_app_name() -> Option<String> {
+    #[cfg(target_os = "macos")]
+    let output = std::process::Command::new("osascript")
+        .arg("-e")
+        .arg(r#"
+            tell application "System Events"
+                return name of first application process whose frontmost is true
+            end tell
+        "#)
+        .output();
+
+    #[cfg(target_os = "windows")]
+    let output = std::process::Command::new("powershell")
+        .arg("-Command")
+        .arg("Get-Process | Where-Object {$_.MainWindowHandle -ne 0 -and $_.MainWindowTitle -ne ''} | Select-Object -First 1 | Select-Object -ExpandProperty ProcessName")
+        .output();
+
+    #[cfg(target_os = "linux")]
+    let output = std::process::Command::new("sh")
+        .arg("-c")
+        .arg("xdotool getwindowfocus getwindowname 2>/dev/null || echo ''")
+        .output();
+
+    #[cfg(not(any(target_os = "macos", target_os = "windows", target_os = "linux")))]
+    let output: std::io::Result<std::process::Output> =
+        Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "unsupported platform"));
+
+    output
+        .ok()
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        .filter(|name| !name.is_empty())
+}
+
+fn is_likely_text_field() -> bool {
+    // Platform-specific detection when possible
+    #[cfg(target_os = "macos")]
+    {
+        // On macOS, try to use AppleScript for more accurate detection
+        if let Ok(output) = std::process::Command::new("osascript")
+            .arg("-e")
+            .arg(r#"
+                tell application "System Events"
+                    set frontApp to name of first application process whose frontmost is true
+                    set frontAppPath to path of application file of first application process whose frontmost is true
+                    return frontApp & ":" & frontAppPath
+                end tell
+            "#)
+            .output()
+        {
+            let output_str = String::from_utf8_lossy(&output.stdout).to_lowercase();
+            
+            // Check for common text editors and applications
+            let common_text_apps = [
+                "textedit", "notes", "pages", "word", "sublime", 
+                "vscode", "visual studio code", "textmate", "terminal",
+                "iterm", "chrome", "safari", "firefox", "slack", "discord",
+                "outlook", "mail", "evernote", "notion", "google docs"
+            ];
+            
+            // Check if any of these apps are in the output
+            if common_text_apps.iter().any(|&app| output_str.contains(app)) {
+                return true;
+            }
+            
+            // If we get output but didn't match known apps, try one more check
+            // This attempts to determine if the focused element can accept text input
+            if let Ok(element_info) = std::process::Command::new("osascript")
+                .arg("-e")
+                .arg(r#"
+                    tell application "System Events"
+                        set focusedElement to focused object of first application process whose frontmost is true
+                        set elementRole to role of focusedElement
+                        return elementRole
+                    end tell
+                "#)
+                .output()
+            {
+                let element_role = String::from_utf8_lossy(&element_info.stdout).to_lowercase();
+                
+                // Common roles that can accept text
+                let text_roles = [
+                    "text field", "text area", "editor", "document", "sheet",
+                    "textfield", "textarea"
+                ];
+                
+                if text_roles.iter().any(|&role| element_role.contains(role)) {
+                    return true;
+                }
+            }
+        }
+    }
+    
+    #[cfg(target_os = "windows")]
+    {
+        // On Windows, we could use the Windows API to check the focused control
+        // This is a simplified version - in a full implementation you'd use winapi
+        // to call GetForegroundWindow and related APIs
+        
+        // For now, we'll use a basic approach with PowerShell
+        if let Ok(output) = std::process::Command::new("powershell")
+            .arg("-Command")
+            .arg("Get-Process | Where-Object {$_.MainWindowHandle -ne 0 -and $_.MainWindowTitle -ne ''} | Select-Object -First 1 | Select-Object -ExpandProperty ProcessName")
+            .output()
+        {
+            let app_name = String::from_utf8_lossy(&output.stdout).to_lowercase();
+            let common_text_apps = [
+                "notepad", "word", "excel", "outlook", "code", "sublime_text",
+                "chrome", "firefox", "edge", "teams", "slack", "discord",
+                "powershell", "cmd", "windowsterminal", "putty", "terminal"
+            ];
+            
+            if common_text_apps.iter().any(|&app| app_name.contains(app)) {
+                return true;
+            }
+        }
+    }
+    
+    #[cfg(target_os = "linux")]
+    {
+        // On Linux, we could use tools like xdotool or gdbus to check active windows
+        if let Ok(output) = std::process::Command::new("sh")
+            .arg("-c")
+            .arg("xdotool getwindowfocus getwindowname 2>/dev/null || echo ''")
+            .output()
+        {
+            let window_name = String::from_utf8_lossy(&output.stdout).to_lowercase();
+            let common_text_apps = [
+                "terminal", "gnome-terminal", "konsole", "xterm", "gedit",
+                "kate", "libreoffice", "firefox", "chrome", "chromium",
+                "code", "sublime", "atom", "emacs", "vim", "discord",
+                "slack", "telegram", "document", "editor", "text"
+            ];
+            
+            if common_text_apps.iter().any(|&app| window_name.contains(app)) {
+                return true;
+            }
+        }
+    }
+    
+    // If we couldn't detect with platform-specific methods, fall back to true
+    // to not block transcription - users can enable strict mode if needed
+    true
+}
+
+
+// Read settings.dat, moving settings saved by older versions into the current layout
+fn load_settings<R: Runtime>(app_handle: &AppHandle<R>) -> Settings {
+    let store = match app_handle.store("settings.dat") {
+        Ok(store) => store,
+        Err(e) => {
+            println!("Error accessing settings store: {}", e);
+            return Settings::default();
+        }
+    };
+
+    let (settings, upgraded) = settings::load(|key| store.get(key));
+    if upgraded {
+        store.set(SETTINGS_KEY, json!(settings));
+        for key in settings::LEGACY_KEYS {
+            store.delete(key);
+        }
+        match store.save() {
+            Ok(()) => println!("Settings upgraded to version {}", SETTINGS_VERSION),
+            Err(e) => eprintln!("Failed to save upgraded settings: {}", e),
+        }
+    }
+    settings
+}
+
+fn current_settings<R: Runtime>(app_handle: &AppHandle<R>) -> Settings {
+    app_handle.state::<AppState>().settings.lock().unwrap().clone()
+}
+
+// Change some settings and apply the result
+fn modify_settings<R: Runtime>(
+    app_handle: &AppHandle<R>,
+    change: impl FnOnce(&mut Settings),
+) -> Result<Settings, String> {
+    let state = app_handle.state::<AppState>();
+    let _write = state.settings_write.lock().unwrap();
+    let mut settings = current_settings(app_handle);
+    change(&mut settings);
+    save_settings(app_handle, settings)
+}
+
+// Replace every setting and apply the result
+fn apply_settings<R: Runtime>(
+    app_handle: &AppHandle<R>,
+    settings: Settings,
+) -> Result<Settings, String> {
+    let state = app_handle.state::<AppState>();
+    let _write = state.settings_write.lock().unwrap();
+    save_settings(app_handle, settings)
+}
+
+// Validate and save new settings, then re-register shortcuts and refresh the
+// tray if those changed. Returns the settings as saved. The caller holds
+// `settings_write`.
+fn save_settings<R: Runtime>(
+    app_handle: &AppHandle<R>,
+    settings: Settings,
+) -> Result<Settings, String> {
+    let previous = current_settings(app_handle);
+    let mut settings = settings.normalized();
+    // Never below the version the settings were loaded with
+    settings.version = previous.version.max(SETTINGS_VERSION);
+    settings.validate().map_err(|e| e.to_string())?;
+
+    let shortcuts_changed = settings.shortcuts != previous.shortcuts;
+    let device_changed = settings.input_device != previous.input_device;
+    let control_changed = settings.control != previous.control;
+
+    if shortcuts_changed {
+        let new = &settings.shortcuts;
+        if let Err(e) = update_global_shortcut(app_handle, &new.toggle, &new.hold, &new.cancel) {
+            // Put the old shortcuts back so the app stays usable
+            let old = &previous.shortcuts;
+            let _ = update_global_shortcut(app_handle, &old.toggle, &old.hold, &old.cancel);
+            return Err(format!("Failed to register shortcuts: {}", e));
+        }
+    }
+
+    let store = app_handle
+        .store("settings.dat")
+        .map_err(|e| format!("Failed to access settings store: {}", e))?;
+    store.set(
+        SETTINGS_KEY,
+        settings::to_stored(&settings, store.get(SETTINGS_KEY)),
+    );
+    store
+        .save()
+        .map_err(|e| format!("Failed to save settings: {}", e))?;
+
+    *app_handle.state::<AppState>().settings.lock().unwrap() = settings.clone();
+
+    if shortcuts_changed {
+        let _ = app_handle.emit(
+            "shortcuts-updated",
+            json!({
+                "toggle_shortcut": settings.shortcuts.toggle,
+                "hold_shortcut": settings.shortcuts.hold,
+                "cancel_shortcut": settings.shortcuts.cancel
+            }),
+        );
+    }
+    if device_changed {
+        println!("Preferred input device set to {:?}", settings.input_device);
+        let _ = app_handle.emit("input-device-changed", &settings.input_device);
+        refresh_tray_menu(app_handle);
+    }
+    if control_changed {
+        restart_control_api(app_handle, &settings.control);
+    }
+    let _ = app_handle.emit("settings-updated", &settings);
+
+    Ok(settings)
+}
+
+// Command to get every setting at once
+#[tauri::command]
+fn get_settings(state: tauri::State<'_, AppState>) -> Settings {
+    state.settings.lock().unwrap().clone()
+}
+
+// Command to replace every setting at once; returns the settings as saved
+#[tauri::command]
+fn update_settings(settings: Settings, app_handle: AppHandle<Wry>) -> Result<Settings, String> {
+    apply_settings(&app_handle, settings)
+}
+
+// Update the run function to load settings
+#[cfg_attr(mobile, tauri::mobile_entry_point)]
+pub fn run() -> Result<(), Box<dyn std::error::Error>> {
+    let app = tauri::Builder::default()
+        .plugin(http_init())
+        .plugin(shell_init())
+        .plugin(opener_init())
+        .plugin(tauri_plugin_store::Builder::default().build())
+        .on_page_load(|webview, payload| {
+            // Warn in the UI about a key shipped inside the app once it can listen
+            if !matches!(payload.event(), PageLoadEvent::Finished) {
+                return;
+            }
+            if let Some(state) = webview.try_state::<AppState>() {
+                if let Some(path) = &state.bundled_env_file {
+                    let _ = webview.emit(
+                        "bundled-api-key-detected",
+                        json!({
+                            "path": path,
+                            "message": "An API key was bundled with this app. It is ignored; revoke it and enter your own key in the settings."
+                        }),
+                    );
+                }
+            }
+        })
+        .setup(move |app| {
+            #[cfg(desktop)]
+            {
+                let app_handle = app.handle();
+
+                // Initialize settings store
+                let _ = app_handle.store("settings.dat");
+
+                // Open the credential store and move any plain-text API key into it
+                let credentials: Option<Arc<dyn CredentialStore>> =
+                    match app_handle.path().app_data_dir() {
+                        Ok(dir) => match credentials::open(&app.config().identifier, &dir) {
+                            Ok(store) => {
+                                println!("Using {} for secrets", store.name());
+                                Some(Arc::from(store))
+                            }
+                            Err(e) => {
+                                eprintln!("Failed to open credential store: {}", e);
+                                None
+                            }
+                        },
+                        Err(e) => {
+                            eprintln!("Failed to resolve app data directory: {}", e);
+                            None
+                        }
+                    };
+                if let Some(credentials) = &credentials {
+                    migrate_plaintext_api_key(&app_handle, credentials.as_ref());
+                }
+
+                let bundled_env_file = find_bundled_env_file();
+                if let Some(path) = &bundled_env_file {
+                    eprintln!(
+                        "Warning: an API key was bundled with the app at {:?}. It is ignored; revoke it and enter your own key in the settings.",
+                        path
+                    );
+                }
+
+                // Open the transcription history database
+                let history = match app_handle.path().app_data_dir() {
+                    Ok(dir) => match HistoryStore::open(&dir.join("history.db")) {
+                        Ok(history) => Some(Arc::new(history)),
+                        Err(e) => {
+                            eprintln!("Failed to open history database: {}", e);
+                            None
+                        }
+                    },
+                    Err(e) => {
+                        eprintln!("Failed to resolve app data directory: {}", e);
+                        None
+                    }
+                };
+
+                // Open the queue of recordings waiting to be transcribed
+                let queue = match app_handle.path().app_data_dir() {
+                    Ok(dir) => match JobQueue::open(&dir.join("queue")) {
+                        Ok(queue) => Some(Arc::new(queue)),
+                        Err(e) => {
+                            eprintln!("Failed to open offline queue: {}", e);
+                            None
+                        }
+                    },
+                    Err(e) => {
+                        eprintln!("Failed to resolve app data directory: {}", e);
+                        None
+                    }
+                };
+
+                // Load the replacement dictionary
+                let dictionary = match app_handle.path().app_data_dir() {
+                    Ok(dir) => {
+                        match DictionaryStore::open(&dir.join(dictionary::DICTIONARY_FILE)) {
+                            Ok(dictionary) => Some(Arc::new(dictionary)),
+                            Err(e) => {
+                                eprintln!("Failed to load the replacement dictionary: {}", e);
+                                None
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        eprintln!("Failed to resolve app data directory: {}", e);
+                        None
+                    }
+                };
+
+                // Load the saved settings, upgrading older settings.dat layouts
+                let settings = load_settings(&app_handle);
+                let shortcuts = settings.shortcuts.clone();
+                println!(
+                    "Loaded shortcuts - toggle: {}, hold: {}, cancel: {}",
+                    shortcuts.toggle, shortcuts.hold, shortcuts.cancel
+                );
+
+                // Scripts and hardware buttons can drive recording through this
+                let control = Arc::new(ControlApi::new(Arc::new(AppController {
+                    app_handle: app_handle.clone(),
+                })));
+                let control_options = settings.control.clone();
+
+                app.manage(AppState {
+                    settings: Arc::new(Mutex::new(settings)),
+                    settings_write: Arc::new(Mutex::new(())),
+                    is_recording: Arc::new(AtomicBool::new(false)),
+                    is_cancelled: Arc::new(AtomicBool::new(false)), // Track cancellation state
+                    last_trigger: Arc::new(Mutex::new(None)),
+                    credentials,
+                    bundled_env_file,
+                    history,
+                    queue,
+                    dictionary,
+                    last_transcript: Arc::new(Mutex::new(None)),
+                    control,
+                    file_transcriptions: Arc::new(Mutex::new(HashMap::new())),
+                });
+
+                // Try to get API key from the credential store, environment or .env
+                if let Ok(api_key) = get_api_key(&app_handle) {
+                    if !api_key.is_empty() {
+                        println!("API key initialized successfully");
+                    } else {
+                        println!(
+                            "No API key found. Please enter it in the settings."
+                        );
+                    }
+                }
+
+                // Set up system tray (after the state, which the menu reads)
+                if let Err(e) = setup_system_tray(&app_handle) {
+                    eprintln!("Failed to set up system tray: {}", e);
+                }
+
+                // Clear out archived recordings that expired while the app was closed
+                let retention_handle = app_handle.clone();
+                thread::spawn(move || {
+                    let options = current_settings(&retention_handle).archive;
+                    apply_archive_retention(&retention_handle, &options);
+                });
+
+                // Send recordings queued while offline once the connection is back
+                tauri::async_runtime::spawn(replay_pending_jobs_periodically(app_handle.clone()));
+
+                // Start the local control API
+                forward_control_events(app_handle);
+                restart_control_api(app_handle, &control_options);
+
+                let handle_clone = app_handle.clone();
+                app_handle.plugin(
+                    tauri_plugin_global_shortcut::Builder::new()
+                        .with_handler(move |_app, shortcut, event| {
+                            handle_shortcut(&handle_clone, shortcut, event.state());
+                        })
+                        .build(),
+                )?;
+                
+                // Register shortcuts from storage
+                if let Err(e) = update_global_shortcut(
+                    &app_handle,
+                    &shortcuts.toggle,
+                    &shortcuts.hold,
+                    &shortcuts.cancel
+                ) {
+                    eprintln!("Failed to register global shortcuts: {}", e);
+                }
+
+                // Configure window to hide instead of close
+                if let Some(window) = app_handle.get_webview_window("main") {
+                    // Use a new variable to avoid moving the window
+                    let window_clone = window.clone();
+                    let drop_app_handle = app_handle.clone();
+                    window.on_window_event(move |event| match event {
+                        tauri::WindowEvent::CloseRequested { api, .. } => {
+                            // Hide window instead of closing
+                            let _ = window_clone.hide();
+                            api.prevent_close();
+                        }
+                        // Audio files dropped on the window are transcribed one by one
+                        tauri::WindowEvent::DragDrop(tauri::DragDropEvent::Drop { paths, .. }) => {
+                            let app_handle = drop_app_handle.clone();
+                            let paths = paths.clone();
+                            tauri::async_runtime::spawn(async move {
+                                for path in paths {
+                                    let _ = transcribe_audio_file(&app_handle, path).await;
+                                }
+                            });
+                        }
+                        _ => (),
+                    });
+                }
+            }
+            Ok(())
+        })
+        .invoke_handler(tauri::generate_handler![
+            get_shortcut_config,
+            update_shortcut_config,
+            record_audio,
+            toggle_strict_text_field_mode,
+            get_strict_text_field_mode,
+            get_settings,
+            update_settings,
+            set_api_key,
+            clear_api_key,
+            has_api_key,
+            validate_api_key,
+            list_credential_profiles,
+            add_credential_profile,
+            remove_credential_profile,
+            set_active_credential_profile,
+            get_transcription_settings,
+            update_transcription_settings,
+            get_voice_command_settings,
+            update_voice_command_settings,
+            list_voice_commands,
+            get_snippet_settings,
+            update_snippet_settings,
+            get_post_processing_settings,
+            update_post_processing_settings,
+            list_templates,
+            get_template,
+            save_template,
+            delete_template,
+            set_active_template,
+            apply_template,
+            list_local_models,
+            list_history,
+            search_history,
+            delete_history_entry,
+            set_history_entry_pinned,
+            get_history_audio,
+            get_history_options,
+            update_history_options,
+            list_dictionary_entries,
+            add_dictionary_entry,
+            update_dictionary_entry,
+            delete_dictionary_entry,
+            import_dictionary,
+            export_dictionary,
+            get_dictionary_options,
+            update_dictionary_options,
+            list_input_devices,
+            get_input_device,
+            set_input_device,
+            get_vad_config,
+            update_vad_config,
+            get_insertion_mode,
+            set_insertion_mode,
+            list_pending_jobs,
+            retry_job,
+            discard_job,
+            get_archive_options,
+            update_archive_options,
+            list_archived_recordings,
+            delete_archived_recording,
+            retranscribe_recording,
+            transcribe_file,
+            cancel_file_transcription,
+            get_control_api_info,
+            regenerate_control_token
+        ])
+        .build(tauri::generate_context!())?;
+
+    app.run(|app_handle, event| {
+        // Remove the control socket so the next start doesn't find a stale one
+        if let tauri::RunEvent::Exit = event {
+            if let Some(state) = app_handle.try_state::<AppState>() {
+                state.control.shutdown();
+            }
+        }
+    });
+    Ok(())
+}
\ No newline at end of file
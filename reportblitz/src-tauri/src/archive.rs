@@ -0,0 +1,332 @@
+//archive.rs
+// Recordings kept on disk with their transcripts, for checking reports
+// against what was actually said. Each recording is an audio file plus a JSON
+// file of the same name in the archive folder.
+use crate::encoding::AudioFormat;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Archived audio has to be readable for re-transcription, so only lossless
+// formats are offered
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ArchiveFormat {
+    #[default]
+    Wav,
+    Flac,
+}
+
+impl ArchiveFormat {
+    pub fn audio_format(&self) -> AudioFormat {
+        match self {
+            ArchiveFormat::Wav => AudioFormat::Wav,
+            ArchiveFormat::Flac => AudioFormat::Flac,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        self.audio_format().extension()
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ArchivedRecording {
+    pub id: String,
+    // Unix time in milliseconds
+    pub created_at: i64,
+    pub duration_secs: f64,
+    // Name of the audio file in the archive folder
+    pub audio_file: String,
+    // The original transcript first, then any re-transcriptions. Empty if the
+    // recording couldn't be transcribed, e.g. while offline.
+    pub transcripts: Vec<ArchivedTranscript>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ArchivedTranscript {
+    pub text: String,
+    pub created_at: i64,
+    pub provider: String,
+    pub model: String,
+    pub language: Option<String>,
+}
+
+pub struct RecordingArchive {
+    dir: PathBuf,
+}
+
+impl RecordingArchive {
+    pub fn new(dir: &Path) -> Self {
+        Self {
+            dir: dir.to_path_buf(),
+        }
+    }
+
+    // Save a recording before it is transcribed, so it is kept even if that
+    // fails; the transcript is added once there is one
+    pub fn save(
+        &self,
+        audio: &[u8],
+        format: &ArchiveFormat,
+        duration_secs: f64,
+    ) -> Result<ArchivedRecording> {
+        fs::create_dir_all(&self.dir)?;
+        let created_at = now_millis();
+        // The random part keeps two recordings saved in the same millisecond apart
+        let id = format!("recording-{}-{}", created_at, uuid::Uuid::new_v4().simple());
+        let audio_file = format!("{}.{}", id, format.extension());
+        fs::write(self.dir.join(&audio_file), audio)?;
+
+        let recording = ArchivedRecording {
+            id,
+            created_at,
+            duration_secs,
+            audio_file,
+            transcripts: Vec::new(),
+        };
+        self.write_metadata(&recording)?;
+        Ok(recording)
+    }
+
+    // Newest first
+    pub fn list(&self) -> Result<Vec<ArchivedRecording>> {
+        if !self.dir.exists() {
+            return Ok(Vec::new());
+        }
+        let mut recordings = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+            match read_metadata(&path) {
+                Ok(recording) => recordings.push(recording),
+                Err(e) => eprintln!("Skipping unreadable archive entry {:?}: {}", path, e),
+            }
+        }
+        recordings.sort_by_key(|recording| std::cmp::Reverse(recording.created_at));
+        Ok(recordings)
+    }
+
+    pub fn get(&self, id: &str) -> Result<Option<ArchivedRecording>> {
+        let path = self.metadata_path(id)?;
+        if !path.exists() {
+            return Ok(None);
+        }
+        Ok(Some(read_metadata(&path)?))
+    }
+
+    pub fn audio(&self, recording: &ArchivedRecording) -> Result<Vec<u8>> {
+        Ok(fs::read(self.dir.join(&recording.audio_file))?)
+    }
+
+    pub fn add_transcript(&self, id: &str, transcript: ArchivedTranscript) -> Result<()> {
+        let mut recording = self
+            .get(id)?
+            .ok_or_else(|| anyhow!("No archived recording {}", id))?;
+        recording.transcripts.push(transcript);
+        self.write_metadata(&recording)
+    }
+
+    // Returns false if there was no such recording
+    pub fn delete(&self, id: &str) -> Result<bool> {
+        let recording = match self.get(id)? {
+            Some(recording) => recording,
+            None => return Ok(false),
+        };
+        remove_if_exists(&self.dir.join(&recording.audio_file))?;
+        remove_if_exists(&self.metadata_path(id)?)?;
+        Ok(true)
+    }
+
+    // Delete recordings older than `max_age`, then the oldest ones until the
+    // archive fits in `max_bytes`. Returns how many were deleted.
+    pub fn apply_retention(
+        &self,
+        max_age: Option<Duration>,
+        max_bytes: Option<u64>,
+    ) -> Result<usize> {
+        let now = now_millis();
+        let mut removed = 0;
+        let mut kept_bytes = 0;
+
+        // Newest first, so whatever is over the size limit is the oldest
+        for recording in self.list()? {
+            let expired = max_age
+                .map(|age| now - recording.created_at > age.as_millis() as i64)
+                .unwrap_or(false);
+            let size = fs::metadata(self.dir.join(&recording.audio_file))
+                .map(|m| m.len())
+                .unwrap_or_default();
+            let over_size = max_bytes
+                .map(|limit| kept_bytes + size > limit)
+                .unwrap_or(false);
+
+            if expired || over_size {
+                self.delete(&recording.id)?;
+                removed += 1;
+            } else {
+                kept_bytes += size;
+            }
+        }
+        Ok(removed)
+    }
+
+    fn write_metadata(&self, recording: &ArchivedRecording) -> Result<()> {
+        let path = self.metadata_path(&recording.id)?;
+        // Replace the file in one step so a crash can't leave half of it
+        let tmp_path = path.with_extension("json.tmp");
+        fs::write(&tmp_path, serde_json::to_vec_pretty(recording)?)?;
+        fs::rename(&tmp_path, &path)?;
+        Ok(())
+    }
+
+    // Ids come from the frontend, so make sure they can't point outside the folder
+    fn metadata_path(&self, id: &str) -> Result<PathBuf> {
+        if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+            return Err(anyhow!("Invalid recording id \"{}\"", id));
+        }
+        Ok(self.dir.join(format!("{}.json", id)))
+    }
+}
+
+pub fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or_default()
+}
+
+fn read_metadata(path: &Path) -> Result<ArchivedRecording> {
+    Ok(serde_json::from_slice(&fs::read(path)?)?)
+}
+
+fn remove_if_exists(path: &Path) -> Result<()> {
+    match fs::remove_file(path) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+
+    const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+    fn transcript(text: &str) -> ArchivedTranscript {
+        ArchivedTranscript {
+            text: text.to_string(),
+            created_at: now_millis(),
+            provider: "OpenAI".to_string(),
+            model: "whisper-1".to_string(),
+            language: None,
+        }
+    }
+
+    // Save a recording of `size` bytes that is `age` old
+    fn save_aged(archive: &RecordingArchive, size: usize, age: Duration) -> String {
+        let mut recording = archive
+            .save(&vec![0; size], &ArchiveFormat::Wav, 1.0)
+            .unwrap();
+        recording.created_at -= age.as_millis() as i64;
+        archive.write_metadata(&recording).unwrap();
+        recording.id
+    }
+
+    fn ids(archive: &RecordingArchive) -> Vec<String> {
+        archive.list().unwrap().into_iter().map(|r| r.id).collect()
+    }
+
+    #[test]
+    fn recordings_saved_at_once_get_their_own_ids() {
+        let dir = TempDir::new();
+        let archive = RecordingArchive::new(dir.path());
+        let first = archive.save(b"first", &ArchiveFormat::Wav, 1.0).unwrap();
+        let second = archive.save(b"second", &ArchiveFormat::Flac, 1.0).unwrap();
+        assert_ne!(first.id, second.id);
+        assert_eq!(archive.list().unwrap().len(), 2);
+        assert_eq!(archive.audio(&first).unwrap(), b"first");
+        assert_eq!(archive.audio(&second).unwrap(), b"second");
+        assert!(second.audio_file.ends_with(".flac"));
+    }
+
+    #[test]
+    fn transcripts_are_added_to_a_saved_recording() {
+        let dir = TempDir::new();
+        let archive = RecordingArchive::new(dir.path());
+        let recording = archive.save(b"audio", &ArchiveFormat::Wav, 2.5).unwrap();
+        assert!(recording.transcripts.is_empty());
+
+        archive
+            .add_transcript(&recording.id, transcript("first"))
+            .unwrap();
+        archive
+            .add_transcript(&recording.id, transcript("again"))
+            .unwrap();
+        let texts: Vec<String> = archive
+            .get(&recording.id)
+            .unwrap()
+            .unwrap()
+            .transcripts
+            .into_iter()
+            .map(|t| t.text)
+            .collect();
+        assert_eq!(texts, vec!["first", "again"]);
+        assert!(archive
+            .add_transcript("recording-0", transcript("x"))
+            .is_err());
+    }
+
+    #[test]
+    fn ids_cannot_leave_the_archive_folder() {
+        let dir = TempDir::new();
+        let archive = RecordingArchive::new(dir.path());
+        assert!(archive.get("../settings").is_err());
+        assert!(archive.delete("").is_err());
+    }
+
+    #[test]
+    fn retention_deletes_recordings_past_the_maximum_age() {
+        let dir = TempDir::new();
+        let archive = RecordingArchive::new(dir.path());
+        let new = save_aged(&archive, 10, Duration::ZERO);
+        let recent = save_aged(&archive, 10, 2 * DAY);
+        let old = save_aged(&archive, 10, 10 * DAY);
+        let old_audio = dir.join(&archive.get(&old).unwrap().unwrap().audio_file);
+
+        assert_eq!(archive.apply_retention(Some(5 * DAY), None).unwrap(), 1);
+        assert_eq!(ids(&archive), vec![new, recent]);
+        assert!(!old_audio.exists());
+    }
+
+    #[test]
+    fn retention_deletes_the_oldest_recordings_over_the_maximum_size() {
+        let dir = TempDir::new();
+        let archive = RecordingArchive::new(dir.path());
+        let oldest = save_aged(&archive, 1000, 3 * DAY);
+        let older = save_aged(&archive, 1000, 2 * DAY);
+        let newest = save_aged(&archive, 1000, DAY);
+
+        assert_eq!(archive.apply_retention(None, Some(2500)).unwrap(), 1);
+        assert_eq!(ids(&archive), vec![newest.clone(), older]);
+        assert!(archive.get(&oldest).unwrap().is_none());
+
+        assert_eq!(archive.apply_retention(None, Some(1000)).unwrap(), 1);
+        assert_eq!(ids(&archive), vec![newest]);
+    }
+
+    #[test]
+    fn retention_without_limits_keeps_everything() {
+        let dir = TempDir::new();
+        let archive = RecordingArchive::new(dir.path());
+        save_aged(&archive, 1000, 400 * DAY);
+        assert_eq!(archive.apply_retention(None, None).unwrap(), 0);
+        assert_eq!(archive.list().unwrap().len(), 1);
+    }
+}
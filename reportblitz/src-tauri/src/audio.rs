@@ -0,0 +1,343 @@
+//audio.rs
+// Sample buffer helpers shared by live recording and transcription.
+use anyhow::{anyhow, Result};
+use rubato::{FftFixedIn, Resampler};
+use std::path::Path;
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{DecoderOptions, CODEC_TYPE_NULL};
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::{MediaSource, MediaSourceStream};
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+// Encode mono f32 samples as a 16-bit PCM WAV file in memory
+pub fn encode_wav(samples: &[f32], sample_rate: u32) -> Result<Vec<u8>> {
+    let spec = hound::WavSpec {
+        channels: 1,
+        sample_rate,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+
+    let mut cursor = std::io::Cursor::new(Vec::new());
+    let mut writer = hound::WavWriter::new(&mut cursor, spec)?;
+    for &sample in samples {
+        writer.write_sample((sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16)?;
+    }
+    writer.finalize()?;
+
+    Ok(cursor.into_inner())
+}
+
+// Decode an in-memory audio file to mono f32 samples at TARGET_SAMPLE_RATE.
+// `extension` is a hint for picking the container.
+pub fn decode(data: Vec<u8>, extension: Option<&str>) -> Result<Vec<f32>> {
+    decode_source(Box::new(std::io::Cursor::new(data)), extension)
+}
+
+// Decode an audio file on disk (WAV, MP3, M4A/AAC, FLAC or Ogg Vorbis) to mono
+// f32 samples at TARGET_SAMPLE_RATE, reading it as it goes
+pub fn decode_file(path: &Path) -> Result<Vec<f32>> {
+    let file = std::fs::File::open(path)?;
+    decode_source(Box::new(file), path.extension().and_then(|e| e.to_str()))
+}
+
+fn decode_source(source: Box<dyn MediaSource>, extension: Option<&str>) -> Result<Vec<f32>> {
+    let mut hint = Hint::new();
+    if let Some(extension) = extension {
+        hint.with_extension(extension);
+    }
+    let stream = MediaSourceStream::new(source, Default::default());
+    let mut format = symphonia::default::get_probe()
+        .format(
+            &hint,
+            stream,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )
+        .map_err(|e| anyhow!("Unsupported audio file: {}", e))?
+        .format;
+
+    let track = format
+        .tracks()
+        .iter()
+        .find(|track| track.codec_params.codec != CODEC_TYPE_NULL)
+        .ok_or_else(|| anyhow!("The file has no audio track"))?;
+    let track_id = track.id;
+    let track_rate = track.codec_params.sample_rate;
+    let mut decoder = symphonia::default::get_codecs()
+        .make(&track.codec_params, &DecoderOptions::default())
+        .map_err(|e| anyhow!("Unsupported audio codec: {}", e))?;
+
+    // Downmix and resample packet by packet, so an hour of 48 kHz stereo
+    // never has to sit in memory at full size
+    let mut resampler: Option<StreamResampler> = None;
+    let mut mono = Vec::new();
+    let mut out = Vec::new();
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(SymphoniaError::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                break
+            }
+            Err(e) => return Err(e.into()),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            // A damaged packet only loses a few milliseconds, keep going
+            Err(SymphoniaError::DecodeError(e)) => {
+                eprintln!("Skipping undecodable audio packet: {}", e);
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let spec = *decoded.spec();
+        let mut buffer = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
+        buffer.copy_interleaved_ref(decoded);
+
+        let resampler = match &mut resampler {
+            Some(resampler) => resampler,
+            None => resampler.insert(StreamResampler::new(
+                track_rate.unwrap_or(spec.rate),
+                TARGET_SAMPLE_RATE,
+            )?),
+        };
+        mono.clear();
+        push_downmixed(&mut mono, buffer.samples(), spec.channels.count(), |s| s);
+        out.extend(resampler.push(&mono)?);
+    }
+
+    if let Some(resampler) = resampler {
+        out.extend(resampler.finish()?);
+    }
+    Ok(out)
+}
+
+// Whisper models are trained on 16 kHz mono audio, so everything is converted
+// to this before encoding
+pub const TARGET_SAMPLE_RATE: u32 = 16000;
+
+const RESAMPLER_CHUNK: usize = 1024;
+
+// Average interleaved frames down to one channel, converting each sample to f32
+pub fn push_downmixed<T: Copy>(
+    out: &mut Vec<f32>,
+    data: &[T],
+    channels: usize,
+    to_f32: impl Fn(T) -> f32,
+) {
+    if channels <= 1 {
+        out.extend(data.iter().map(|&s| to_f32(s)));
+        return;
+    }
+    out.extend(
+        data.chunks_exact(channels)
+            .map(|frame| frame.iter().map(|&s| to_f32(s)).sum::<f32>() / channels as f32),
+    );
+}
+
+// Band-limited streaming resampler for mono audio. Samples can be pushed in
+// any amount; output comes out in fixed-size blocks as input accumulates.
+pub struct StreamResampler {
+    resampler: Option<FftFixedIn<f32>>,
+    ratio: f64,
+    pending: Vec<f32>,
+    input_frames: usize,
+    output_frames: usize,
+    // Leading frames of filter delay still to be dropped
+    delay_left: usize,
+}
+
+impl StreamResampler {
+    pub fn new(from_rate: u32, to_rate: u32) -> Result<Self> {
+        let resampler = if from_rate == to_rate {
+            None
+        } else {
+            Some(FftFixedIn::<f32>::new(
+                from_rate as usize,
+                to_rate as usize,
+                RESAMPLER_CHUNK,
+                2,
+                1,
+            )?)
+        };
+        let delay_left = resampler.as_ref().map(|r| r.output_delay()).unwrap_or(0);
+        Ok(Self {
+            resampler,
+            ratio: to_rate as f64 / from_rate as f64,
+            pending: Vec::new(),
+            input_frames: 0,
+            output_frames: 0,
+            delay_left,
+        })
+    }
+
+    pub fn push(&mut self, samples: &[f32]) -> Result<Vec<f32>> {
+        let resampler = match &mut self.resampler {
+            Some(resampler) => resampler,
+            None => return Ok(samples.to_vec()),
+        };
+
+        self.input_frames += samples.len();
+        self.pending.extend_from_slice(samples);
+
+        let mut out = Vec::new();
+        let mut offset = 0;
+        while self.pending.len() - offset >= resampler.input_frames_next() {
+            let needed = resampler.input_frames_next();
+            let processed = resampler.process(&[&self.pending[offset..offset + needed]], None)?;
+            offset += needed;
+            append_trimmed(&processed[0], &mut self.delay_left, &mut out);
+        }
+        self.pending.drain(..offset);
+
+        self.output_frames += out.len();
+        Ok(out)
+    }
+
+    // Flush the remaining input and the filter tail
+    pub fn finish(mut self) -> Result<Vec<f32>> {
+        let resampler = match &mut self.resampler {
+            Some(resampler) => resampler,
+            None => return Ok(Vec::new()),
+        };
+
+        let expected = (self.input_frames as f64 * self.ratio).round() as usize;
+        let mut out = Vec::new();
+
+        if !self.pending.is_empty() {
+            let processed = resampler.process_partial(Some(&[&self.pending[..]]), None)?;
+            append_trimmed(&processed[0], &mut self.delay_left, &mut out);
+        }
+
+        // Each empty call pushes out one more block of the delayed tail
+        let mut flushes = 0;
+        while self.output_frames + out.len() < expected && flushes < 4 {
+            let processed = resampler.process_partial::<&[f32]>(None, None)?;
+            append_trimmed(&processed[0], &mut self.delay_left, &mut out);
+            flushes += 1;
+        }
+
+        out.truncate(expected.saturating_sub(self.output_frames));
+        Ok(out)
+    }
+}
+
+fn append_trimmed(block: &[f32], delay_left: &mut usize, out: &mut Vec<f32>) {
+    let skip = (*delay_left).min(block.len());
+    *delay_left -= skip;
+    out.extend_from_slice(&block[skip..]);
+}
+
+// Resample a complete mono buffer in one go
+pub fn resample(samples: &[f32], from_rate: u32, to_rate: u32) -> Result<Vec<f32>> {
+    let mut resampler = StreamResampler::new(from_rate, to_rate)?;
+    let mut out = resampler.push(samples)?;
+    out.extend(resampler.finish()?);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tone(seconds: f32, rate: u32) -> Vec<f32> {
+        let len = (seconds * rate as f32) as usize;
+        (0..len)
+            .map(|i| 0.5 * (2.0 * std::f32::consts::PI * 440.0 * i as f32 / rate as f32).sin())
+            .collect()
+    }
+
+    // Push the samples in pieces the size of a capture callback
+    fn stream(samples: &[f32], from_rate: u32, block: usize) -> Vec<f32> {
+        let mut resampler = StreamResampler::new(from_rate, TARGET_SAMPLE_RATE).unwrap();
+        let mut out = Vec::new();
+        for piece in samples.chunks(block) {
+            out.extend(resampler.push(piece).unwrap());
+        }
+        out.extend(resampler.finish().unwrap());
+        out
+    }
+
+    #[test]
+    fn streaming_48khz_gives_the_expected_length() {
+        let samples = tone(2.5, 48_000);
+        for block in [1, 441, 480, 4096] {
+            assert_eq!(
+                stream(&samples, 48_000, block).len(),
+                40_000,
+                "block {}",
+                block
+            );
+        }
+    }
+
+    #[test]
+    fn streaming_matches_one_shot_resampling() {
+        let samples = tone(1.3, 48_000);
+        let whole = resample(&samples, 48_000, TARGET_SAMPLE_RATE).unwrap();
+        let streamed = stream(&samples, 48_000, 480);
+        assert_eq!(streamed.len(), whole.len());
+        for (a, b) in streamed.iter().zip(&whole) {
+            assert!((a - b).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn resampled_tone_lines_up_with_the_original() {
+        // With the filter delay dropped, the output is the same tone sampled
+        // at 16 kHz, apart from the edges. The filter may leave a fraction of
+        // a sample of delay; a whole sample would be off by about 0.086.
+        for rate in [44_100, 48_000, 22_050] {
+            let out = resample(&tone(1.0, rate), rate, TARGET_SAMPLE_RATE).unwrap();
+            let expected = tone(1.0, TARGET_SAMPLE_RATE);
+            assert_eq!(out.len(), expected.len(), "{} Hz", rate);
+            for i in 200..out.len() - 200 {
+                assert!(
+                    (out[i] - expected[i]).abs() < 0.05,
+                    "{} Hz, sample {}: {} vs {}",
+                    rate,
+                    i,
+                    out[i],
+                    expected[i]
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn same_rate_passes_samples_through() {
+        let samples = tone(0.5, TARGET_SAMPLE_RATE);
+        assert_eq!(stream(&samples, TARGET_SAMPLE_RATE, 480), samples);
+    }
+
+    #[test]
+    fn short_input_is_flushed_by_finish() {
+        let samples = tone(0.01, 48_000);
+        let out = stream(&samples, 48_000, 480);
+        assert_eq!(out.len(), 160);
+    }
+
+    #[test]
+    fn frames_are_downmixed_to_their_average() {
+        let mut out = Vec::new();
+        push_downmixed(&mut out, &[0.5f32, 0.1, -1.0, 1.0], 2, |s| s);
+        assert_eq!(out, vec![0.3, 0.0]);
+
+        let mut out = Vec::new();
+        push_downmixed(&mut out, &[i16::MAX, 0, 0, 0, 0, 0], 3, |s| {
+            s as f32 / i16::MAX as f32
+        });
+        assert_eq!(out.len(), 2);
+        assert!((out[0] - 1.0 / 3.0).abs() < 1e-6);
+        assert_eq!(out[1], 0.0);
+
+        let mut out = vec![0.25];
+        push_downmixed(&mut out, &[0.5f32, -0.5], 1, |s| s);
+        assert_eq!(out, vec![0.25, 0.5, -0.5]);
+    }
+}
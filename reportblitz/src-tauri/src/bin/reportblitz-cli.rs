@@ -0,0 +1,464 @@
+//reportblitz-cli.rs
+// Command line access to the ReportBlitz pipeline, for batch work on machines
+// without a display. It reads the settings, API keys and history of the
+// desktop app from the app data directory.
+use anyhow::{anyhow, Result};
+use clap::{Parser, Subcommand, ValueEnum};
+use reportblitz_lib::audio::{self, TARGET_SAMPLE_RATE};
+use reportblitz_lib::credentials::{self, CredentialStore};
+use reportblitz_lib::devices;
+use reportblitz_lib::dictionary::{self, DictionaryStore};
+use reportblitz_lib::history::{HistoryEntry, HistoryStore, NewHistoryEntry};
+use reportblitz_lib::local_whisper;
+use reportblitz_lib::pipeline::{self, Dictation, DictationEvent};
+use reportblitz_lib::settings::Settings;
+use reportblitz_lib::templates;
+use reportblitz_lib::transcription::{ProviderKind, TranscriptionProvider};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+// History entries are read from the database this many at a time
+const EXPORT_PAGE_SIZE: u32 = 500;
+
+#[derive(Parser)]
+#[command(
+    name = "reportblitz-cli",
+    version,
+    about = "Transcribe audio with the ReportBlitz pipeline"
+)]
+struct Cli {
+    /// Directory with settings.dat, history.db and models; defaults to the app's
+    #[arg(long, global = true)]
+    data_dir: Option<PathBuf>,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Transcribe audio files (WAV, MP3, M4A, FLAC, Ogg Vorbis) into .txt files
+    Transcribe {
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+        /// Where to write the transcripts; defaults to next to each file
+        #[arg(long)]
+        output_dir: Option<PathBuf>,
+        /// Skip files that already have a transcript
+        #[arg(long)]
+        skip_existing: bool,
+        #[command(flatten)]
+        transcription: TranscriptionArgs,
+    },
+    /// Record from a microphone and transcribe the recording
+    Record {
+        /// How long to record, in seconds; Ctrl+C stops early
+        #[arg(long)]
+        duration: f64,
+        /// Input device name as listed by `devices`; defaults to the app's choice
+        #[arg(long)]
+        device: Option<String>,
+        /// Write the transcript to this file instead of printing it
+        #[arg(long)]
+        output: Option<PathBuf>,
+        /// Also save the recording as a WAV file
+        #[arg(long)]
+        save_audio: Option<PathBuf>,
+        #[command(flatten)]
+        transcription: TranscriptionArgs,
+    },
+    /// List input devices
+    Devices,
+    /// Work with the transcription history
+    History {
+        #[command(subcommand)]
+        command: HistoryCommand,
+    },
+    /// Import or export the replacement dictionary
+    Dictionary {
+        #[command(subcommand)]
+        command: DictionaryCommand,
+    },
+}
+
+#[derive(clap::Args)]
+struct TranscriptionArgs {
+    /// Override the provider: openai, openai_compatible or local
+    #[arg(long)]
+    provider: Option<String>,
+    /// Override the model
+    #[arg(long)]
+    model: Option<String>,
+    /// Override the language, e.g. "en"
+    #[arg(long)]
+    language: Option<String>,
+    /// Also save transcripts to the history
+    #[arg(long)]
+    history: bool,
+    /// Skip the dictionary, spoken commands, snippets, post-processing and the report template
+    #[arg(long)]
+    raw: bool,
+    /// Render transcripts into this report template instead of the active one
+    #[arg(long)]
+    template: Option<String>,
+}
+
+#[derive(Subcommand)]
+enum HistoryCommand {
+    /// Write every history entry as CSV or JSON
+    Export {
+        #[arg(long, value_enum, default_value_t = ExportFormat::Csv)]
+        format: ExportFormat,
+        /// Output file; defaults to standard output
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+enum DictionaryCommand {
+    /// Add the rules in a .csv or .json file to the dictionary
+    Import {
+        file: PathBuf,
+        /// Replace the dictionary instead of adding to it
+        #[arg(long)]
+        replace: bool,
+    },
+    /// Write the dictionary to a .csv or .json file
+    Export { file: PathBuf },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ExportFormat {
+    Csv,
+    Json,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let cli = Cli::parse();
+    let data_dir = match cli.data_dir {
+        Some(dir) => dir,
+        None => pipeline::app_data_dir()
+            .ok_or_else(|| anyhow!("Could not find the app data directory"))?,
+    };
+
+    match cli.command {
+        Command::Transcribe {
+            files,
+            output_dir,
+            skip_existing,
+            transcription,
+        } => transcribe_files(&data_dir, files, output_dir, skip_existing, transcription).await,
+        Command::Record {
+            duration,
+            device,
+            output,
+            save_audio,
+            transcription,
+        } => {
+            record(
+                &data_dir,
+                duration,
+                device,
+                output,
+                save_audio,
+                transcription,
+            )
+            .await
+        }
+        Command::Devices => {
+            for device in devices::list_input_devices() {
+                let marker = if device.is_default { " (default)" } else { "" };
+                println!("{}{}", device.name, marker);
+            }
+            Ok(())
+        }
+        Command::History {
+            command: HistoryCommand::Export { format, output },
+        } => export_history(&data_dir, format, output),
+        Command::Dictionary { command } => {
+            let store = DictionaryStore::open(&data_dir.join(dictionary::DICTIONARY_FILE))?;
+            match command {
+                DictionaryCommand::Import { file, replace } => {
+                    let added = store.import(dictionary::read_file(&file)?, replace)?;
+                    println!("Imported {} entries", added);
+                }
+                DictionaryCommand::Export { file } => {
+                    dictionary::write_file(&file, &store.entries())?;
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+// Settings and provider for one run, with command line overrides applied
+struct Session {
+    settings: Settings,
+    provider: Arc<dyn TranscriptionProvider>,
+    dictation: Dictation,
+    history: Option<HistoryStore>,
+}
+
+fn open_session(data_dir: &Path, args: &TranscriptionArgs) -> Result<Session> {
+    let mut settings = pipeline::load_settings_file(&data_dir.join("settings.dat"))?;
+    let transcription = &mut settings.transcription;
+    if let Some(provider) = &args.provider {
+        transcription.provider = ProviderKind::parse(provider)
+            .ok_or_else(|| anyhow!("Unknown provider \"{}\"", provider))?;
+    }
+    if let Some(model) = &args.model {
+        transcription.options.model = model.clone();
+    }
+    if let Some(language) = &args.language {
+        transcription.options.language = Some(language.clone());
+    }
+    transcription.options = transcription.options.clone().normalized();
+    transcription.options.validate()?;
+
+    let dictionary_store = DictionaryStore::open(&data_dir.join(dictionary::DICTIONARY_FILE))?;
+    if settings.dictionary.prompt_hints {
+        let hints = dictionary::spelling_hints(&dictionary_store.entries());
+        let options = &mut settings.transcription.options;
+        options.prompt = dictionary::prompt_with_hints(options.prompt.take(), &hints);
+    }
+    let credentials: Option<Box<dyn CredentialStore>> =
+        match credentials::open(pipeline::APP_IDENTIFIER, data_dir) {
+            Ok(store) => Some(store),
+            Err(e) => {
+                eprintln!("Failed to open credential store: {}", e);
+                None
+            }
+        };
+    let profile = settings.credential_profiles.active.clone();
+    let api_key = |name: Option<&str>| {
+        let name = name.unwrap_or(&profile);
+        Ok(pipeline::resolve_api_key(credentials.as_deref(), name))
+    };
+    let provider = pipeline::build_provider(
+        settings.transcription.clone(),
+        api_key,
+        &local_whisper::models_dir(data_dir),
+    )?;
+    // --raw leaves out every step after transcription except an explicitly
+    // chosen template
+    let mut steps = settings.clone();
+    if args.raw {
+        steps.dictionary.enabled = false;
+        steps.voice_commands.enabled = false;
+        steps.snippets.enabled = false;
+        steps.post_processing.enabled = false;
+        steps.templates.active = None;
+    }
+    let dictation = Dictation::new(
+        &steps,
+        Some(&dictionary_store),
+        &templates::templates_dir(data_dir),
+        args.template.as_deref(),
+        api_key,
+    );
+    // Better to stop than to write a batch of transcripts without a step
+    dictation.check()?;
+
+    let history = if args.history {
+        Some(HistoryStore::open(&data_dir.join("history.db"))?)
+    } else {
+        None
+    };
+    Ok(Session {
+        settings,
+        provider,
+        dictation,
+        history,
+    })
+}
+
+impl Session {
+    // Setting `cancelled` stops before the next chunk is sent
+    async fn transcribe(&self, samples: &[f32], cancelled: &AtomicBool) -> Result<String> {
+        let text = pipeline::transcribe_samples(
+            self.provider.as_ref(),
+            samples,
+            &self.settings.vad,
+            cancelled,
+        )
+        .await?;
+        let text = self.dictation.finish(text, print_dictation_event).await;
+        if let (Some(history), false) = (&self.history, text.is_empty()) {
+            history.insert(NewHistoryEntry {
+                text: &text,
+                duration_secs: samples.len() as f64 / TARGET_SAMPLE_RATE as f64,
+                provider: self.provider.name(),
+                target_app: None,
+                audio: None,
+            })?;
+        }
+        Ok(text)
+    }
+}
+
+fn print_dictation_event(event: DictationEvent) {
+    match event {
+        DictationEvent::PostProcessingStarted => {}
+        // The raw transcript is kept rather than failing the file
+        DictationEvent::PostProcessingFailed(e) => {
+            eprintln!("Post-processing failed, keeping the raw transcript: {}", e)
+        }
+        DictationEvent::TemplateApplied { missing, .. } => {
+            if !missing.is_empty() {
+                eprintln!("Not dictated: {}", missing.join(", "));
+            }
+        }
+        DictationEvent::TemplateFailed { error, .. } => {
+            eprintln!("Failed to apply the report template: {}", error)
+        }
+    }
+}
+
+async fn transcribe_files(
+    data_dir: &Path,
+    files: Vec<PathBuf>,
+    output_dir: Option<PathBuf>,
+    skip_existing: bool,
+    args: TranscriptionArgs,
+) -> Result<()> {
+    let session = open_session(data_dir, &args)?;
+    if let Some(dir) = &output_dir {
+        fs::create_dir_all(dir)?;
+    }
+
+    // Ctrl+C finishes the chunk being sent and skips the rest
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let ctrl_c_cancelled = cancelled.clone();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            eprintln!("Cancelling");
+            ctrl_c_cancelled.store(true, Ordering::SeqCst);
+        }
+    });
+
+    let mut failed = 0;
+    for file in &files {
+        if cancelled.load(Ordering::SeqCst) {
+            return Err(anyhow!("Cancelled"));
+        }
+        // call.2024-01-01.mp3 becomes call.2024-01-01.txt, not call.txt
+        let stem = file.file_stem().unwrap_or(file.as_os_str());
+        let output = output_dir
+            .as_deref()
+            .or(file.parent())
+            .unwrap_or(Path::new("."))
+            .join(format!("{}.txt", stem.to_string_lossy()));
+        if skip_existing && output.exists() {
+            eprintln!("Skipping {}, {} exists", file.display(), output.display());
+            continue;
+        }
+
+        let result = async {
+            let path = file.clone();
+            let samples = tokio::task::spawn_blocking(move || audio::decode_file(&path)).await??;
+            let text = session.transcribe(&samples, &cancelled).await?;
+            fs::write(&output, text)?;
+            Ok::<(), anyhow::Error>(())
+        }
+        .await;
+        match result {
+            Ok(()) => eprintln!("Transcribed {} to {}", file.display(), output.display()),
+            Err(e) => {
+                eprintln!("Failed to transcribe {}: {}", file.display(), e);
+                failed += 1;
+            }
+        }
+    }
+
+    if failed > 0 {
+        return Err(anyhow!("{} of {} files failed", failed, files.len()));
+    }
+    Ok(())
+}
+
+async fn record(
+    data_dir: &Path,
+    duration: f64,
+    device: Option<String>,
+    output: Option<PathBuf>,
+    save_audio: Option<PathBuf>,
+    args: TranscriptionArgs,
+) -> Result<()> {
+    let duration = Duration::try_from_secs_f64(duration)
+        .ok()
+        .filter(|duration| !duration.is_zero())
+        .ok_or_else(|| anyhow!("The duration must be a positive number of seconds"))?;
+    let session = open_session(data_dir, &args)?;
+    let device = device.or_else(|| session.settings.input_device.clone());
+
+    // The first Ctrl+C ends the recording early, a second one cancels its
+    // transcription
+    let stop = Arc::new(AtomicBool::new(false));
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let (ctrl_c_stop, ctrl_c_cancelled) = (stop.clone(), cancelled.clone());
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            ctrl_c_stop.store(true, Ordering::SeqCst);
+        }
+        if tokio::signal::ctrl_c().await.is_ok() {
+            eprintln!("Cancelling");
+            ctrl_c_cancelled.store(true, Ordering::SeqCst);
+        }
+    });
+
+    let samples =
+        tokio::task::spawn_blocking(move || pipeline::record(device.as_deref(), duration, &stop))
+            .await??;
+    eprintln!(
+        "Recorded {:.1} seconds",
+        samples.len() as f64 / TARGET_SAMPLE_RATE as f64
+    );
+    if let Some(path) = &save_audio {
+        fs::write(path, audio::encode_wav(&samples, TARGET_SAMPLE_RATE)?)?;
+    }
+
+    let text = session.transcribe(&samples, &cancelled).await?;
+    match output {
+        Some(path) => fs::write(path, text)?,
+        None => println!("{}", text),
+    }
+    Ok(())
+}
+
+fn export_history(data_dir: &Path, format: ExportFormat, output: Option<PathBuf>) -> Result<()> {
+    let history = HistoryStore::open(&data_dir.join("history.db"))?;
+    let mut entries: Vec<HistoryEntry> = Vec::new();
+    loop {
+        let page = history.list(EXPORT_PAGE_SIZE, entries.len() as u32)?;
+        let done = page.len() < EXPORT_PAGE_SIZE as usize;
+        entries.extend(page);
+        if done {
+            break;
+        }
+    }
+
+    let mut out: Box<dyn Write> = match output {
+        Some(path) => Box::new(fs::File::create(path)?),
+        None => Box::new(std::io::stdout().lock()),
+    };
+    match format {
+        ExportFormat::Json => {
+            serde_json::to_writer_pretty(&mut out, &entries)?;
+            writeln!(out)?;
+        }
+        ExportFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(out);
+            for entry in &entries {
+                writer.serialize(entry)?;
+            }
+            writer.flush()?;
+        }
+    }
+    Ok(())
+}
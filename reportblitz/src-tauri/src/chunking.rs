@@ -0,0 +1,245 @@
+//chunking.rs
+// Splits a live sample stream into chunks at silence boundaries so long
+// recordings can be transcribed while they are still being captured.
+
+#[derive(Clone, Debug)]
+pub struct ChunkerConfig {
+    // Never cut before a chunk is this long
+    pub min_chunk_secs: f32,
+    // Force a cut at the quietest point if no silence shows up by this length
+    pub max_chunk_secs: f32,
+    // How long a pause has to be before we cut inside it
+    pub min_silence_secs: f32,
+    // RMS level below which a frame counts as silence
+    pub silence_threshold: f32,
+    pub frame_secs: f32,
+}
+
+impl Default for ChunkerConfig {
+    fn default() -> Self {
+        Self {
+            min_chunk_secs: 15.0,
+            max_chunk_secs: 45.0,
+            min_silence_secs: 0.6,
+            silence_threshold: 0.01,
+            frame_secs: 0.03,
+        }
+    }
+}
+
+pub struct SilenceChunker {
+    frame_len: usize,
+    min_frames: usize,
+    max_frames: usize,
+    silence_frames: usize,
+    threshold: f32,
+    buffer: Vec<f32>,
+    // RMS of every complete frame currently in `buffer`
+    frame_rms: Vec<f32>,
+}
+
+impl SilenceChunker {
+    pub fn new(config: ChunkerConfig, sample_rate: u32) -> Self {
+        let frame_len = ((sample_rate as f32 * config.frame_secs) as usize).max(1);
+        let frames = |secs: f32| ((secs / config.frame_secs).ceil() as usize).max(1);
+        let min_frames = frames(config.min_chunk_secs);
+        Self {
+            frame_len,
+            min_frames,
+            max_frames: frames(config.max_chunk_secs).max(min_frames + 1),
+            silence_frames: frames(config.min_silence_secs),
+            threshold: config.silence_threshold,
+            buffer: Vec::new(),
+            frame_rms: Vec::new(),
+        }
+    }
+
+    // Add newly captured samples and return any chunks that are now complete
+    pub fn push(&mut self, samples: &[f32]) -> Vec<Vec<f32>> {
+        self.buffer.extend_from_slice(samples);
+
+        while (self.frame_rms.len() + 1) * self.frame_len <= self.buffer.len() {
+            let start = self.frame_rms.len() * self.frame_len;
+            let frame = &self.buffer[start..start + self.frame_len];
+            let rms = (frame.iter().map(|s| s * s).sum::<f32>() / frame.len() as f32).sqrt();
+            self.frame_rms.push(rms);
+        }
+
+        let mut chunks = Vec::new();
+        while let Some(cut_frame) = self.find_cut() {
+            let rest = self.buffer.split_off(cut_frame * self.frame_len);
+            chunks.push(std::mem::replace(&mut self.buffer, rest));
+            self.frame_rms.drain(..cut_frame);
+        }
+        chunks
+    }
+
+    // Whatever is left once recording stops
+    pub fn finish(self) -> Option<Vec<f32>> {
+        if self.buffer.is_empty() {
+            None
+        } else {
+            Some(self.buffer)
+        }
+    }
+
+    // Frame index to cut at, if the buffer holds a complete chunk
+    fn find_cut(&self) -> Option<usize> {
+        if self.frame_rms.len() < self.min_frames {
+            return None;
+        }
+
+        // Cut in the middle of the first long enough pause past the minimum length
+        let end = self.frame_rms.len().min(self.max_frames);
+        let mut run = 0;
+        for i in self.min_frames..end {
+            if self.frame_rms[i] < self.threshold {
+                run += 1;
+                if run >= self.silence_frames {
+                    return Some(i + 1 - run / 2);
+                }
+            } else {
+                run = 0;
+            }
+        }
+
+        // No pause in time; fall back to the quietest frame so chunks stay bounded
+        if self.frame_rms.len() >= self.max_frames {
+            return (self.min_frames..self.max_frames)
+                .min_by(|&a, &b| self.frame_rms[a].total_cmp(&self.frame_rms[b]));
+        }
+
+        None
+    }
+}
+
+// Join chunk transcripts back into a single text
+pub fn stitch(parts: &[String]) -> String {
+    parts
+        .iter()
+        .map(|part| part.trim())
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 16_000;
+
+    fn config() -> ChunkerConfig {
+        ChunkerConfig {
+            min_chunk_secs: 2.0,
+            max_chunk_secs: 5.0,
+            min_silence_secs: 0.3,
+            silence_threshold: 0.01,
+            frame_secs: 0.03,
+        }
+    }
+
+    // Sections of (seconds, amplitude) of a 440 Hz tone
+    fn clip(sections: &[(f32, f32)]) -> Vec<f32> {
+        let mut samples = Vec::new();
+        for &(secs, amplitude) in sections {
+            let len = (RATE as f32 * secs) as usize;
+            samples.extend((0..len).map(|i| {
+                amplitude * (2.0 * std::f32::consts::PI * 440.0 * i as f32 / RATE as f32).sin()
+            }));
+        }
+        samples
+    }
+
+    fn secs(samples: usize) -> f32 {
+        samples as f32 / RATE as f32
+    }
+
+    // Feed the samples in pieces the size of a capture callback
+    fn run(chunker: &mut SilenceChunker, samples: &[f32]) -> Vec<Vec<f32>> {
+        samples
+            .chunks(480)
+            .flat_map(|block| chunker.push(block))
+            .collect()
+    }
+
+    #[test]
+    fn chunks_are_cut_inside_a_pause() {
+        let mut chunker = SilenceChunker::new(config(), RATE);
+        let samples = clip(&[(3.0, 0.5), (1.0, 0.0), (2.0, 0.5)]);
+        let chunks = run(&mut chunker, &samples);
+
+        assert_eq!(chunks.len(), 1);
+        let cut = secs(chunks[0].len());
+        assert!(cut > 3.0 && cut < 4.0, "cut at {}s", cut);
+        // Both sides of the cut are silent
+        assert!(chunks[0][chunks[0].len() - 100..].iter().all(|s| *s == 0.0));
+        let rest = chunker.finish().unwrap();
+        assert!(rest[..100].iter().all(|s| *s == 0.0));
+        assert_eq!(chunks[0].len() + rest.len(), samples.len());
+    }
+
+    #[test]
+    fn pauses_before_the_minimum_length_are_not_cut() {
+        let mut chunker = SilenceChunker::new(config(), RATE);
+        let samples = clip(&[(1.0, 0.5), (1.0, 0.0), (1.5, 0.5)]);
+        assert!(run(&mut chunker, &samples).is_empty());
+        assert_eq!(chunker.finish().unwrap().len(), samples.len());
+    }
+
+    #[test]
+    fn chunks_never_exceed_the_maximum_length() {
+        let mut chunker = SilenceChunker::new(config(), RATE);
+        // No pause at all, with one slightly quieter stretch
+        let samples = clip(&[(3.0, 0.5), (0.2, 0.3), (9.0, 0.5)]);
+        let chunks = run(&mut chunker, &samples);
+
+        // At most 5 seconds each, so 12.2 seconds take at least two cuts
+        assert!(chunks.len() >= 2);
+        for chunk in &chunks {
+            let len = secs(chunk.len());
+            assert!((2.0..=5.0).contains(&len), "chunk of {}s", len);
+        }
+        // The first cut lands in the quieter stretch
+        let cut = secs(chunks[0].len());
+        assert!((3.0..=3.2).contains(&cut), "cut at {}s", cut);
+
+        let rest = chunker.finish().unwrap();
+        let total: usize = chunks.iter().map(Vec::len).sum::<usize>() + rest.len();
+        assert_eq!(total, samples.len());
+    }
+
+    #[test]
+    fn finish_returns_the_remaining_audio() {
+        let mut chunker = SilenceChunker::new(config(), RATE);
+        let samples = clip(&[(0.5, 0.5)]);
+        assert!(chunker.push(&samples).is_empty());
+        assert_eq!(chunker.finish(), Some(samples));
+
+        let chunker = SilenceChunker::new(config(), RATE);
+        assert_eq!(chunker.finish(), None);
+    }
+
+    #[test]
+    fn stitch_joins_parts_with_single_spaces() {
+        let parts = vec![
+            " The suspect fled north. ".to_string(),
+            "".to_string(),
+            "\nHe was wearing a red jacket.".to_string(),
+            "   ".to_string(),
+        ];
+        assert_eq!(
+            stitch(&parts),
+            "The suspect fled north. He was wearing a red jacket."
+        );
+        assert_eq!(stitch(&[]), "");
+    }
+
+    #[test]
+    fn stitch_keeps_words_repeated_across_a_join() {
+        // Chunks are cut in pauses and don't overlap, so a word at the end of
+        // one and the start of the next was really said twice
+        let parts = vec!["He said no".to_string(), "no, not again".to_string()];
+        assert_eq!(stitch(&parts), "He said no no, not again");
+    }
+}
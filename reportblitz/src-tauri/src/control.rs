@@ -0,0 +1,779 @@
+//control.rs
+// Local control API, so foot pedals, Stream Deck scripts and window manager
+// keybindings can drive recording where global shortcuts don't work, e.g. on
+// Wayland. A Unix socket takes one command per line; an optional HTTP server
+// on 127.0.0.1 takes the same commands with an access token. Both can stream
+// the app's events.
+use crate::credentials;
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::OsRng;
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::fs;
+use std::net::Ipv4Addr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::runtime::Handle;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::task::{AbortHandle, JoinSet};
+
+pub const DEFAULT_HTTP_PORT: u16 = 47813;
+
+// The socket is made inside a directory only the user can enter
+const SOCKET_DIR: &str = "reportblitz";
+const SOCKET_NAME: &str = "control.sock";
+const TOKEN_FILE: &str = "control-token";
+// Event stream clients that fall this far behind miss the oldest events
+const EVENT_BUFFER: usize = 64;
+const MAX_REQUEST_HEAD: u64 = 8 * 1024;
+// Idle HTTP event streams send a comment this often, which also notices
+// clients that went away
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ControlCommand {
+    Start,
+    Stop,
+    Toggle,
+    // Stop and throw the recording away
+    Cancel,
+    Status,
+    LastTranscript,
+}
+
+impl ControlCommand {
+    pub fn parse(command: &str) -> Option<Self> {
+        match command.trim().to_ascii_lowercase().as_str() {
+            "start" => Some(ControlCommand::Start),
+            "stop" => Some(ControlCommand::Stop),
+            "toggle" => Some(ControlCommand::Toggle),
+            "cancel" => Some(ControlCommand::Cancel),
+            "status" => Some(ControlCommand::Status),
+            "last-transcript" | "last_transcript" => Some(ControlCommand::LastTranscript),
+            _ => None,
+        }
+    }
+
+    // Whether the command changes anything; over HTTP those must be POSTed
+    fn is_action(&self) -> bool {
+        !matches!(
+            self,
+            ControlCommand::Status | ControlCommand::LastTranscript
+        )
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct ControlStatus {
+    pub recording: bool,
+    // Recordings waiting in the offline queue
+    pub pending_jobs: usize,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct LastTranscript {
+    pub text: String,
+    // Unix time in milliseconds
+    pub created_at: i64,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct ControlEvent {
+    pub event: String,
+    pub payload: Value,
+}
+
+// What the app does when a command arrives. Starting while recording, or
+// stopping while not, does nothing.
+pub trait Controller: Send + Sync {
+    fn start(&self);
+    fn stop(&self);
+    fn cancel(&self);
+    fn status(&self) -> ControlStatus;
+    fn last_transcript(&self) -> Option<LastTranscript>;
+}
+
+// The response to a command: the status afterwards, or the last transcript
+// (null if there is none yet)
+pub fn execute(controller: &dyn Controller, command: ControlCommand) -> Value {
+    match command {
+        ControlCommand::Start => controller.start(),
+        ControlCommand::Stop => controller.stop(),
+        ControlCommand::Toggle => {
+            if controller.status().recording {
+                controller.stop()
+            } else {
+                controller.start()
+            }
+        }
+        ControlCommand::Cancel => controller.cancel(),
+        ControlCommand::Status => (),
+        ControlCommand::LastTranscript => return json!(controller.last_transcript()),
+    }
+    json!(controller.status())
+}
+
+// Where the socket goes: the user's runtime directory if there is one, which
+// only they can reach and which is cleared at logout, else the app data dir
+pub fn socket_path(app_data_dir: &Path) -> PathBuf {
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir).join(SOCKET_DIR).join(SOCKET_NAME),
+        _ => app_data_dir.join(SOCKET_DIR).join(SOCKET_NAME),
+    }
+}
+
+// Scripts read the HTTP access token from this file
+pub fn token_path(app_data_dir: &Path) -> PathBuf {
+    app_data_dir.join(TOKEN_FILE)
+}
+
+pub fn load_or_create_token(path: &Path) -> Result<String> {
+    if let Ok(token) = fs::read_to_string(path) {
+        let token = token.trim();
+        if !token.is_empty() {
+            return Ok(token.to_string());
+        }
+    }
+    regenerate_token(path)
+}
+
+// Replace the access token, e.g. after it leaked
+pub fn regenerate_token(path: &Path) -> Result<String> {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    let token: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    credentials::write_private(path, token.as_bytes())?;
+    Ok(token)
+}
+
+struct Server {
+    // Aborting the accept loop also drops its connections
+    task: AbortHandle,
+    // Removed when the server stops
+    socket: Option<PathBuf>,
+}
+
+pub struct ControlApi {
+    controller: Arc<dyn Controller>,
+    events: broadcast::Sender<ControlEvent>,
+    servers: Mutex<Vec<Server>>,
+}
+
+impl ControlApi {
+    pub fn new(controller: Arc<dyn Controller>) -> Self {
+        let (events, _) = broadcast::channel(EVENT_BUFFER);
+        Self {
+            controller,
+            events,
+            servers: Mutex::new(Vec::new()),
+        }
+    }
+
+    // Send an event to every client streaming events
+    pub fn publish(&self, event: &str, payload: Value) {
+        // Fails only when nobody is listening
+        let _ = self.events.send(ControlEvent {
+            event: event.to_string(),
+            payload,
+        });
+    }
+
+    // Stop every server and close their connections
+    pub fn shutdown(&self) {
+        for server in self.servers.lock().unwrap().drain(..) {
+            server.task.abort();
+            if let Some(path) = server.socket {
+                let _ = fs::remove_file(path);
+            }
+        }
+    }
+
+    // Listen on a Unix socket at `path`. Its directory is made private to the
+    // user before binding, since the socket is reachable from the moment it
+    // exists and some systems ignore the socket's own permissions.
+    #[cfg(unix)]
+    pub fn serve_socket(&self, runtime: &Handle, path: &Path) -> Result<()> {
+        use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+        use std::os::unix::net::{UnixListener, UnixStream};
+
+        if let Some(parent) = path.parent() {
+            fs::DirBuilder::new()
+                .recursive(true)
+                .mode(0o700)
+                .create(parent)?;
+            // It may have been made earlier with other permissions
+            fs::set_permissions(parent, fs::Permissions::from_mode(0o700))?;
+        }
+        if path.exists() {
+            // A socket left behind by a crash can go, a live one can't
+            if UnixStream::connect(path).is_ok() {
+                return Err(anyhow!("{:?} is in use by another instance", path));
+            }
+            fs::remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path)?;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+        listener.set_nonblocking(true)?;
+
+        let controller = self.controller.clone();
+        let events = self.events.clone();
+        let task = runtime.spawn(async move {
+            let listener = match tokio::net::UnixListener::from_std(listener) {
+                Ok(listener) => listener,
+                Err(e) => {
+                    eprintln!("Control socket error: {}", e);
+                    return;
+                }
+            };
+            let mut connections = JoinSet::new();
+            loop {
+                match listener.accept().await {
+                    Ok((stream, _)) => {
+                        let controller = controller.clone();
+                        let events = events.clone();
+                        connections.spawn(async move {
+                            if let Err(e) = serve_socket_client(stream, controller, events).await {
+                                eprintln!("Control socket client error: {}", e);
+                            }
+                        });
+                    }
+                    Err(e) => {
+                        eprintln!("Control socket error: {}", e);
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                    }
+                }
+                while connections.try_join_next().is_some() {}
+            }
+        });
+
+        self.servers.lock().unwrap().push(Server {
+            task: task.abort_handle(),
+            socket: Some(path.to_path_buf()),
+        });
+        Ok(())
+    }
+
+    // Listen for HTTP on 127.0.0.1. Every request needs an
+    // `Authorization: Bearer <token>` header.
+    pub fn serve_http(&self, runtime: &Handle, port: u16, token: String) -> Result<()> {
+        let listener = std::net::TcpListener::bind((Ipv4Addr::LOCALHOST, port))
+            .map_err(|e| anyhow!("Can't listen on port {}: {}", port, e))?;
+        listener.set_nonblocking(true)?;
+
+        let controller = self.controller.clone();
+        let events = self.events.clone();
+        let token: Arc<str> = token.into();
+        let task = runtime.spawn(async move {
+            let listener = match tokio::net::TcpListener::from_std(listener) {
+                Ok(listener) => listener,
+                Err(e) => {
+                    eprintln!("Control API error: {}", e);
+                    return;
+                }
+            };
+            let mut connections = JoinSet::new();
+            loop {
+                match listener.accept().await {
+                    Ok((stream, _)) => {
+                        let controller = controller.clone();
+                        let events = events.clone();
+                        let token = token.clone();
+                        connections.spawn(async move {
+                            if let Err(e) =
+                                serve_http_client(stream, controller, events, &token).await
+                            {
+                                eprintln!("Control API client error: {}", e);
+                            }
+                        });
+                    }
+                    Err(e) => {
+                        eprintln!("Control API error: {}", e);
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                    }
+                }
+                while connections.try_join_next().is_some() {}
+            }
+        });
+
+        self.servers.lock().unwrap().push(Server {
+            task: task.abort_handle(),
+            socket: None,
+        });
+        Ok(())
+    }
+}
+
+impl Drop for ControlApi {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+// One command per line, answered with one line of JSON. `events` turns the
+// connection into a stream of events, one JSON object per line.
+#[cfg(unix)]
+async fn serve_socket_client(
+    stream: tokio::net::UnixStream,
+    controller: Arc<dyn Controller>,
+    events: broadcast::Sender<ControlEvent>,
+) -> Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    while let Some(line) = lines.next_line().await? {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if line.eq_ignore_ascii_case("events") {
+            return stream_events(&mut writer, events.subscribe(), false).await;
+        }
+        let response = match ControlCommand::parse(line) {
+            Some(command) => execute(controller.as_ref(), command),
+            None => json!({ "error": format!("Unknown command \"{}\"", line) }),
+        };
+        writer
+            .write_all(format!("{}\n", response).as_bytes())
+            .await?;
+    }
+    Ok(())
+}
+
+struct HttpRequest {
+    method: String,
+    path: String,
+    authorization: Option<String>,
+}
+
+// Read the request line and headers; bodies aren't used
+async fn read_request_head(reader: impl AsyncRead + Unpin) -> Result<HttpRequest> {
+    let mut reader = BufReader::new(reader.take(MAX_REQUEST_HEAD));
+    let mut line = String::new();
+    reader.read_line(&mut line).await?;
+    let mut parts = line.split_whitespace();
+    let (method, target) = match (parts.next(), parts.next()) {
+        (Some(method), Some(target)) => (method.to_string(), target.to_string()),
+        _ => return Err(anyhow!("Malformed request line")),
+    };
+    let path = target.split('?').next().unwrap_or_default().to_string();
+
+    let mut authorization = None;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).await? == 0 {
+            return Err(anyhow!("Request head is incomplete or too long"));
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("authorization") {
+                authorization = Some(value.trim().to_string());
+            }
+        }
+    }
+
+    Ok(HttpRequest {
+        method,
+        path,
+        authorization,
+    })
+}
+
+// POST /start, /stop, /toggle and /cancel; GET /status, /last-transcript and
+// /events, which is a stream of server-sent events
+async fn serve_http_client(
+    stream: tokio::net::TcpStream,
+    controller: Arc<dyn Controller>,
+    events: broadcast::Sender<ControlEvent>,
+    token: &str,
+) -> Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let request = match read_request_head(reader).await {
+        Ok(request) => request,
+        Err(e) => {
+            let body = json!({ "error": e.to_string() });
+            return write_response(&mut writer, "400 Bad Request", &body).await;
+        }
+    };
+
+    let authorized = request
+        .authorization
+        .as_deref()
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(|given| constant_time_eq(given.trim().as_bytes(), token.as_bytes()))
+        .unwrap_or(false);
+    if !authorized {
+        let body = json!({ "error": "Missing or wrong access token" });
+        return write_response(&mut writer, "401 Unauthorized", &body).await;
+    }
+
+    let name = request.path.trim_matches('/');
+    if name == "events" {
+        if request.method != "GET" {
+            let body = json!({ "error": "Use GET for events" });
+            return write_response(&mut writer, "405 Method Not Allowed", &body).await;
+        }
+        let head = "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n";
+        writer.write_all(head.as_bytes()).await?;
+        return stream_events(&mut writer, events.subscribe(), true).await;
+    }
+
+    let command = match ControlCommand::parse(name) {
+        Some(command) => command,
+        None => {
+            let body = json!({ "error": format!("Unknown command \"{}\"", name) });
+            return write_response(&mut writer, "404 Not Found", &body).await;
+        }
+    };
+    let expected_method = if command.is_action() { "POST" } else { "GET" };
+    if request.method != expected_method {
+        let body = json!({ "error": format!("Use {} for {}", expected_method, name) });
+        return write_response(&mut writer, "405 Method Not Allowed", &body).await;
+    }
+
+    let body = execute(controller.as_ref(), command);
+    write_response(&mut writer, "200 OK", &body).await
+}
+
+async fn write_response(
+    writer: &mut (impl AsyncWrite + Unpin),
+    status: &str,
+    body: &Value,
+) -> Result<()> {
+    let body = body.to_string();
+    let head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        body.len()
+    );
+    writer.write_all(head.as_bytes()).await?;
+    writer.write_all(body.as_bytes()).await?;
+    writer.shutdown().await?;
+    Ok(())
+}
+
+// Write events until the client goes away, as JSON lines or as server-sent
+// events whose data is the same JSON
+async fn stream_events(
+    writer: &mut (impl AsyncWrite + Unpin),
+    mut events: broadcast::Receiver<ControlEvent>,
+    sse: bool,
+) -> Result<()> {
+    let mut keep_alive = tokio::time::interval(KEEP_ALIVE_INTERVAL);
+    keep_alive.tick().await;
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                Ok(event) => {
+                    let json = serde_json::to_string(&event)?;
+                    let message = if sse {
+                        format!("data: {}\n\n", json)
+                    } else {
+                        format!("{}\n", json)
+                    };
+                    writer.write_all(message.as_bytes()).await?;
+                }
+                Err(RecvError::Lagged(missed)) => {
+                    eprintln!("Control API client fell behind and missed {} events", missed);
+                }
+                Err(RecvError::Closed) => return Ok(()),
+            },
+            _ = keep_alive.tick(), if sse => writer.write_all(b": keep-alive\n\n").await?,
+        }
+    }
+}
+
+// Compare tokens without leaking how much of them matched through timing
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+
+    const TOKEN: &str = "secret-token";
+
+    #[derive(Default)]
+    struct FakeController {
+        recording: Mutex<bool>,
+        cancelled: Mutex<bool>,
+    }
+
+    impl Controller for FakeController {
+        fn start(&self) {
+            *self.recording.lock().unwrap() = true;
+        }
+
+        fn stop(&self) {
+            *self.recording.lock().unwrap() = false;
+        }
+
+        fn cancel(&self) {
+            *self.recording.lock().unwrap() = false;
+            *self.cancelled.lock().unwrap() = true;
+        }
+
+        fn status(&self) -> ControlStatus {
+            ControlStatus {
+                recording: *self.recording.lock().unwrap(),
+                pending_jobs: 2,
+            }
+        }
+
+        fn last_transcript(&self) -> Option<LastTranscript> {
+            None
+        }
+    }
+
+    // Send one raw HTTP request to a client handler and return the response
+    async fn http(controller: Arc<FakeController>, request: &str) -> String {
+        let listener = tokio::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .await
+            .unwrap();
+        let address = listener.local_addr().unwrap();
+        let (events, _) = broadcast::channel(EVENT_BUFFER);
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            serve_http_client(stream, controller, events, TOKEN).await
+        });
+
+        let mut client = tokio::net::TcpStream::connect(address).await.unwrap();
+        client.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+        server.await.unwrap().unwrap();
+        response
+    }
+
+    fn request(method: &str, path: &str, authorization: Option<&str>) -> String {
+        let header = authorization
+            .map(|value| format!("Authorization: {}\r\n", value))
+            .unwrap_or_default();
+        format!(
+            "{} {} HTTP/1.1\r\nHost: 127.0.0.1\r\n{}\r\n",
+            method, path, header
+        )
+    }
+
+    fn bearer() -> String {
+        format!("Bearer {}", TOKEN)
+    }
+
+    fn status_line(response: &str) -> &str {
+        response.lines().next().unwrap_or_default()
+    }
+
+    fn body(response: &str) -> Value {
+        let (_, body) = response.split_once("\r\n\r\n").unwrap();
+        serde_json::from_str(body).unwrap()
+    }
+
+    #[test]
+    fn commands_are_parsed_regardless_of_case_and_spacing() {
+        assert_eq!(ControlCommand::parse("start"), Some(ControlCommand::Start));
+        assert_eq!(
+            ControlCommand::parse("  TOGGLE\n"),
+            Some(ControlCommand::Toggle)
+        );
+        assert_eq!(
+            ControlCommand::parse("last-transcript"),
+            Some(ControlCommand::LastTranscript)
+        );
+        assert_eq!(
+            ControlCommand::parse("last_transcript"),
+            Some(ControlCommand::LastTranscript)
+        );
+        assert_eq!(ControlCommand::parse("record"), None);
+        assert_eq!(ControlCommand::parse(""), None);
+        assert!(ControlCommand::Cancel.is_action());
+        assert!(!ControlCommand::Status.is_action());
+    }
+
+    #[test]
+    fn commands_answer_with_the_status_afterwards() {
+        let controller = FakeController::default();
+        let response = execute(&controller, ControlCommand::Toggle);
+        assert_eq!(response, json!({ "recording": true, "pending_jobs": 2 }));
+        assert_eq!(
+            execute(&controller, ControlCommand::Toggle)["recording"],
+            false
+        );
+
+        execute(&controller, ControlCommand::Start);
+        assert_eq!(
+            execute(&controller, ControlCommand::Cancel)["recording"],
+            false
+        );
+        assert!(*controller.cancelled.lock().unwrap());
+        assert_eq!(
+            execute(&controller, ControlCommand::LastTranscript),
+            Value::Null
+        );
+    }
+
+    #[tokio::test]
+    async fn request_heads_are_parsed() {
+        let head = "POST /toggle?source=pedal HTTP/1.1\r\nHost: x\r\nauthorization:  Bearer abc \r\n\r\nignored body";
+        let request = read_request_head(head.as_bytes()).await.unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/toggle");
+        assert_eq!(request.authorization.as_deref(), Some("Bearer abc"));
+
+        let request = read_request_head(&b"GET /status HTTP/1.1\r\n\r\n"[..])
+            .await
+            .unwrap();
+        assert_eq!(request.authorization, None);
+
+        assert!(read_request_head(&b"\r\n\r\n"[..]).await.is_err());
+        // Cut off before the blank line that ends the headers
+        assert!(
+            read_request_head(&b"GET /status HTTP/1.1\r\nHost: x\r\n"[..])
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn requests_without_the_right_token_are_refused() {
+        let controller = Arc::new(FakeController::default());
+        for authorization in [
+            None,
+            Some("Bearer wrong-token".to_string()),
+            Some(format!("Bearer {}x", TOKEN)),
+            Some(format!("Basic {}", TOKEN)),
+            Some(TOKEN.to_string()),
+        ] {
+            let response = http(
+                controller.clone(),
+                &request("POST", "/start", authorization.as_deref()),
+            )
+            .await;
+            assert_eq!(
+                status_line(&response),
+                "HTTP/1.1 401 Unauthorized",
+                "{:?}",
+                authorization
+            );
+        }
+        assert!(!*controller.recording.lock().unwrap());
+    }
+
+    #[tokio::test]
+    async fn authorized_requests_run_commands() {
+        let controller = Arc::new(FakeController::default());
+        let response = http(
+            controller.clone(),
+            &request("POST", "/start", Some(&bearer())),
+        )
+        .await;
+        assert_eq!(status_line(&response), "HTTP/1.1 200 OK");
+        assert_eq!(body(&response)["recording"], true);
+
+        let response = http(
+            controller.clone(),
+            &request("GET", "/status", Some(&bearer())),
+        )
+        .await;
+        assert_eq!(body(&response)["recording"], true);
+    }
+
+    #[tokio::test]
+    async fn http_methods_and_paths_are_checked() {
+        let controller = Arc::new(FakeController::default());
+        let response = http(
+            controller.clone(),
+            &request("GET", "/start", Some(&bearer())),
+        )
+        .await;
+        assert_eq!(status_line(&response), "HTTP/1.1 405 Method Not Allowed");
+        assert!(!*controller.recording.lock().unwrap());
+
+        let response = http(
+            controller.clone(),
+            &request("POST", "/events", Some(&bearer())),
+        )
+        .await;
+        assert_eq!(status_line(&response), "HTTP/1.1 405 Method Not Allowed");
+
+        let response = http(
+            controller.clone(),
+            &request("POST", "/record", Some(&bearer())),
+        )
+        .await;
+        assert_eq!(status_line(&response), "HTTP/1.1 404 Not Found");
+
+        let response = http(controller, "nonsense\r\n\r\n").await;
+        assert_eq!(status_line(&response), "HTTP/1.1 400 Bad Request");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn socket_clients_send_one_command_per_line() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = TempDir::new();
+        let path = dir.join("control").join("control.sock");
+        let api = ControlApi::new(Arc::new(FakeController::default()));
+        api.serve_socket(&Handle::current(), &path).unwrap();
+        // Only the current user can reach the socket
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&dir.join("control")), 0o700);
+        assert_eq!(mode(&path), 0o600);
+
+        let stream = tokio::net::UnixStream::connect(&path).await.unwrap();
+        let (reader, mut writer) = stream.into_split();
+        writer
+            .write_all(b"toggle\n\nbogus\nstatus\n")
+            .await
+            .unwrap();
+        let mut lines = BufReader::new(reader).lines();
+        let mut responses = Vec::new();
+        for _ in 0..3 {
+            let line = lines.next_line().await.unwrap().unwrap();
+            responses.push(serde_json::from_str::<Value>(&line).unwrap());
+        }
+        assert_eq!(responses[0]["recording"], true);
+        assert_eq!(responses[1]["error"], "Unknown command \"bogus\"");
+        assert_eq!(responses[2]["recording"], true);
+
+        // A second instance can't take over a live socket
+        let other = ControlApi::new(Arc::new(FakeController::default()));
+        assert!(other.serve_socket(&Handle::current(), &path).is_err());
+
+        api.shutdown();
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn tokens_are_kept_until_regenerated() {
+        let dir = TempDir::new();
+        let path = token_path(&dir.join("data"));
+        let token = load_or_create_token(&path).unwrap();
+        assert_eq!(token.len(), 64);
+        assert_eq!(load_or_create_token(&path).unwrap(), token);
+
+        let replaced = regenerate_token(&path).unwrap();
+        assert_ne!(replaced, token);
+        assert_eq!(load_or_create_token(&path).unwrap(), replaced);
+
+        fs::write(&path, "  \n").unwrap();
+        assert_ne!(load_or_create_token(&path).unwrap(), "");
+    }
+
+    #[test]
+    fn tokens_are_compared_in_full() {
+        assert!(constant_time_eq(b"abc", b"abc"));
+        assert!(!constant_time_eq(b"abc", b"abd"));
+        assert!(!constant_time_eq(b"abc", b"abcd"));
+        assert!(!constant_time_eq(b"", b"abc"));
+    }
+}
@@ -0,0 +1,362 @@
+//credentials.rs
+// Secret storage. Secrets go to the platform credential store (Keychain,
+// Windows Credential Manager, Secret Service) and fall back to an encrypted
+// file when that isn't available, e.g. on Linux without a Secret Service, or
+// when the "keyring" feature is left out of the build.
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use anyhow::{anyhow, Result};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+pub const OPENAI_API_KEY: &str = "openai_api_key";
+
+// API keys belong to named profiles, e.g. a personal and a team key
+pub const DEFAULT_PROFILE: &str = "default";
+
+const KEY_FILE: &str = "credentials.key";
+const SECRETS_FILE: &str = "credentials.enc";
+const NONCE_LEN: usize = 12;
+
+// Name of a profile's API key in the credential store. The default profile
+// uses the name keys were saved under before there were profiles.
+pub fn api_key_name(profile: &str) -> String {
+    if profile == DEFAULT_PROFILE {
+        OPENAI_API_KEY.to_string()
+    } else {
+        format!("{}:{}", OPENAI_API_KEY, profile)
+    }
+}
+
+// Environment variable that can supply a profile's API key, e.g.
+// OPENAI_API_KEY for the default profile and OPENAI_API_KEY_TEAM for "team"
+pub fn api_key_env_var(profile: &str) -> String {
+    if profile == DEFAULT_PROFILE {
+        "OPENAI_API_KEY".to_string()
+    } else {
+        format!(
+            "OPENAI_API_KEY_{}",
+            profile.to_uppercase().replace('-', "_")
+        )
+    }
+}
+
+pub fn validate_profile_name(name: &str) -> Result<()> {
+    if name.is_empty() || name.len() > 32 {
+        return Err(anyhow!("Profile names must be 1 to 32 characters long"));
+    }
+    if !name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(anyhow!(
+            "Profile names may only contain letters, digits, '-' and '_'"
+        ));
+    }
+    Ok(())
+}
+
+pub trait CredentialStore: Send + Sync {
+    // Short name used in logs
+    fn name(&self) -> &'static str;
+
+    fn get(&self, key: &str) -> Result<Option<String>>;
+
+    fn set(&self, key: &str, secret: &str) -> Result<()>;
+
+    // Deleting a secret that doesn't exist is not an error
+    fn delete(&self, key: &str) -> Result<()>;
+}
+
+// Use the platform credential store if it works, otherwise the encrypted file
+#[cfg(feature = "keyring")]
+pub fn open(service: &str, fallback_dir: &Path) -> Result<Box<dyn CredentialStore>> {
+    if let Some(store) = KeyringStore::open(service) {
+        return Ok(Box::new(store));
+    }
+    eprintln!("Platform credential store unavailable, using an encrypted file instead");
+    Ok(Box::new(EncryptedFileStore::open(fallback_dir)?))
+}
+
+// Built without platform credential stores; only the encrypted file is left
+#[cfg(not(feature = "keyring"))]
+pub fn open(_service: &str, fallback_dir: &Path) -> Result<Box<dyn CredentialStore>> {
+    Ok(Box::new(EncryptedFileStore::open(fallback_dir)?))
+}
+
+#[cfg(feature = "keyring")]
+pub struct KeyringStore {
+    service: String,
+}
+
+#[cfg(feature = "keyring")]
+impl KeyringStore {
+    // Returns None if the platform store can't be reached
+    pub fn open(service: &str) -> Option<Self> {
+        let store = Self {
+            service: service.to_string(),
+        };
+        // Reading a missing entry only succeeds with NoEntry if the backend works
+        match store.entry("probe").and_then(|entry| entry.get_password()) {
+            Ok(_) | Err(keyring::Error::NoEntry) => Some(store),
+            Err(e) => {
+                eprintln!("Platform credential store error: {}", e);
+                None
+            }
+        }
+    }
+
+    fn entry(&self, key: &str) -> keyring::Result<keyring::Entry> {
+        keyring::Entry::new(&self.service, key)
+    }
+}
+
+#[cfg(feature = "keyring")]
+impl CredentialStore for KeyringStore {
+    fn name(&self) -> &'static str {
+        "platform credential store"
+    }
+
+    fn get(&self, key: &str) -> Result<Option<String>> {
+        match self.entry(key)?.get_password() {
+            Ok(secret) => Ok(Some(secret)),
+            Err(keyring::Error::NoEntry) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn set(&self, key: &str, secret: &str) -> Result<()> {
+        self.entry(key)?.set_password(secret)?;
+        Ok(())
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        match self.entry(key)?.delete_credential() {
+            Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+// Secrets kept as one AES-256-GCM encrypted JSON map. The key is random and
+// stored beside it in a file only the current user can read, which keeps the
+// secrets out of plain text and away from other accounts on the machine.
+pub struct EncryptedFileStore {
+    path: PathBuf,
+    cipher: Aes256Gcm,
+    // Serialises read-modify-write cycles
+    lock: Mutex<()>,
+}
+
+impl EncryptedFileStore {
+    pub fn open(dir: &Path) -> Result<Self> {
+        fs::create_dir_all(dir)?;
+
+        let key_path = dir.join(KEY_FILE);
+        let key = if key_path.exists() {
+            let key = fs::read(&key_path)?;
+            if key.len() != 32 {
+                return Err(anyhow!("Credential key file {:?} is corrupt", key_path));
+            }
+            key
+        } else {
+            let key = Aes256Gcm::generate_key(OsRng).to_vec();
+            write_private(&key_path, &key)?;
+            key
+        };
+
+        Ok(Self {
+            path: dir.join(SECRETS_FILE),
+            cipher: Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key)),
+            lock: Mutex::new(()),
+        })
+    }
+
+    fn read_all(&self) -> Result<BTreeMap<String, String>> {
+        if !self.path.exists() {
+            return Ok(BTreeMap::new());
+        }
+
+        let data = fs::read(&self.path)?;
+        if data.len() < NONCE_LEN {
+            return Err(anyhow!("Credential file {:?} is corrupt", self.path));
+        }
+        let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+        let plaintext = self
+            .cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| anyhow!("Failed to decrypt credential file {:?}", self.path))?;
+        Ok(serde_json::from_slice(&plaintext)?)
+    }
+
+    fn write_all(&self, secrets: &BTreeMap<String, String>) -> Result<()> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let plaintext = serde_json::to_vec(secrets)?;
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, plaintext.as_ref())
+            .map_err(|_| anyhow!("Failed to encrypt credentials"))?;
+
+        let mut data = nonce.to_vec();
+        data.extend_from_slice(&ciphertext);
+
+        // Write to a temporary file first so a crash can't leave half a file
+        let tmp_path = self.path.with_extension("tmp");
+        write_private(&tmp_path, &data)?;
+        fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+}
+
+impl CredentialStore for EncryptedFileStore {
+    fn name(&self) -> &'static str {
+        "encrypted credential file"
+    }
+
+    fn get(&self, key: &str) -> Result<Option<String>> {
+        let _guard = self.lock.lock().unwrap();
+        Ok(self.read_all()?.remove(key))
+    }
+
+    fn set(&self, key: &str, secret: &str) -> Result<()> {
+        let _guard = self.lock.lock().unwrap();
+        let mut secrets = self.read_all()?;
+        secrets.insert(key.to_string(), secret.to_string());
+        self.write_all(&secrets)
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        let _guard = self.lock.lock().unwrap();
+        let mut secrets = self.read_all()?;
+        if secrets.remove(key).is_some() {
+            self.write_all(&secrets)?;
+        }
+        Ok(())
+    }
+}
+
+// Create or replace a file that only the current user can read
+pub fn write_private(path: &Path, data: &[u8]) -> Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let mut file = options.open(path)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+
+    #[test]
+    fn secrets_survive_reopening_the_store() {
+        let dir = TempDir::new();
+        let store = EncryptedFileStore::open(dir.path()).unwrap();
+        assert_eq!(store.get(OPENAI_API_KEY).unwrap(), None);
+        store.set(OPENAI_API_KEY, "sk-personal").unwrap();
+        store.set(&api_key_name("team"), "sk-team").unwrap();
+        store.set(OPENAI_API_KEY, "sk-replaced").unwrap();
+
+        let reopened = EncryptedFileStore::open(dir.path()).unwrap();
+        assert_eq!(
+            reopened.get(OPENAI_API_KEY).unwrap().as_deref(),
+            Some("sk-replaced")
+        );
+        assert_eq!(
+            reopened.get("openai_api_key:team").unwrap().as_deref(),
+            Some("sk-team")
+        );
+
+        reopened.delete(OPENAI_API_KEY).unwrap();
+        reopened.delete("never-saved").unwrap();
+        assert_eq!(reopened.get(OPENAI_API_KEY).unwrap(), None);
+        assert_eq!(
+            store.get("openai_api_key:team").unwrap().as_deref(),
+            Some("sk-team")
+        );
+    }
+
+    #[test]
+    fn secrets_are_not_stored_in_plain_text() {
+        let dir = TempDir::new();
+        let store = EncryptedFileStore::open(dir.path()).unwrap();
+        store.set(OPENAI_API_KEY, "sk-plain-text-check").unwrap();
+        let data = fs::read(dir.join(SECRETS_FILE)).unwrap();
+        let needle = b"sk-plain-text-check";
+        assert!(!data.windows(needle.len()).any(|window| window == needle));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn key_and_secrets_are_private_to_the_user() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = TempDir::new();
+        let store = EncryptedFileStore::open(dir.path()).unwrap();
+        store.set(OPENAI_API_KEY, "sk-test").unwrap();
+        for file in [KEY_FILE, SECRETS_FILE] {
+            let mode = fs::metadata(dir.join(file)).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600, "{}", file);
+        }
+    }
+
+    #[test]
+    fn a_different_key_fails_to_decrypt_cleanly() {
+        let dir = TempDir::new();
+        let store = EncryptedFileStore::open(dir.path()).unwrap();
+        store.set(OPENAI_API_KEY, "sk-test").unwrap();
+
+        // As if the key file had been replaced, e.g. restored from another machine
+        write_private(&dir.join(KEY_FILE), &[7; 32]).unwrap();
+        let store = EncryptedFileStore::open(dir.path()).unwrap();
+        let error = store.get(OPENAI_API_KEY).unwrap_err();
+        assert!(error.to_string().contains("Failed to decrypt"), "{}", error);
+        // Nothing is overwritten with a fresh map
+        assert!(store.set("other", "value").is_err());
+    }
+
+    #[test]
+    fn corrupt_files_are_reported() {
+        let dir = TempDir::new();
+        write_private(&dir.join(KEY_FILE), b"too short").unwrap();
+        assert!(EncryptedFileStore::open(dir.path()).is_err());
+
+        let dir = TempDir::new();
+        let store = EncryptedFileStore::open(dir.path()).unwrap();
+        fs::write(dir.join(SECRETS_FILE), b"short").unwrap();
+        assert!(store.get(OPENAI_API_KEY).is_err());
+
+        // A tampered file fails authentication
+        let dir = TempDir::new();
+        let store = EncryptedFileStore::open(dir.path()).unwrap();
+        store.set(OPENAI_API_KEY, "sk-test").unwrap();
+        let mut data = fs::read(dir.join(SECRETS_FILE)).unwrap();
+        let last = data.len() - 1;
+        data[last] ^= 1;
+        fs::write(dir.join(SECRETS_FILE), &data).unwrap();
+        assert!(store.get(OPENAI_API_KEY).is_err());
+    }
+
+    #[test]
+    fn profile_names_map_to_keys_and_variables() {
+        assert_eq!(api_key_name(DEFAULT_PROFILE), OPENAI_API_KEY);
+        assert_eq!(api_key_name("team"), "openai_api_key:team");
+        assert_eq!(api_key_env_var(DEFAULT_PROFILE), "OPENAI_API_KEY");
+        assert_eq!(api_key_env_var("night-shift"), "OPENAI_API_KEY_NIGHT_SHIFT");
+
+        assert!(validate_profile_name("night-shift_2").is_ok());
+        for name in ["", "has space", "a:b", &"x".repeat(33)] {
+            assert!(validate_profile_name(name).is_err(), "{:?}", name);
+        }
+    }
+}
@@ -0,0 +1,151 @@
+//devices.rs
+// Input device discovery. Devices are identified by name, which is what gets
+// persisted as the preferred microphone.
+use cpal::traits::{DeviceTrait, HostTrait};
+use serde::Serialize;
+
+#[derive(Serialize, Clone, Debug)]
+pub struct InputDeviceConfig {
+    pub channels: u16,
+    pub min_sample_rate: u32,
+    pub max_sample_rate: u32,
+    pub sample_format: String,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct InputDeviceInfo {
+    pub name: String,
+    pub is_default: bool,
+    pub configs: Vec<InputDeviceConfig>,
+}
+
+pub fn default_input_device_name() -> Option<String> {
+    cpal::default_host()
+        .default_input_device()
+        .and_then(|device| device.name().ok())
+}
+
+// Every input device the host currently reports, with its supported configs
+pub fn list_input_devices() -> Vec<InputDeviceInfo> {
+    let host = cpal::default_host();
+    let default_name = default_input_device_name();
+
+    let devices = match host.input_devices() {
+        Ok(devices) => devices,
+        Err(e) => {
+            eprintln!("Error enumerating input devices: {}", e);
+            return Vec::new();
+        }
+    };
+
+    devices
+        .filter_map(|device| {
+            let name = device.name().ok()?;
+            let configs = device
+                .supported_input_configs()
+                .map(|configs| {
+                    configs
+                        .map(|c| InputDeviceConfig {
+                            channels: c.channels(),
+                            min_sample_rate: c.min_sample_rate().0,
+                            max_sample_rate: c.max_sample_rate().0,
+                            sample_format: format!("{:?}", c.sample_format()),
+                        })
+                        .collect()
+                })
+                .unwrap_or_default();
+            Some(InputDeviceInfo {
+                is_default: default_name.as_deref() == Some(name.as_str()),
+                name,
+                configs,
+            })
+        })
+        .collect()
+}
+
+// Names only, for menus
+pub fn input_device_names() -> Vec<String> {
+    match cpal::default_host().input_devices() {
+        Ok(devices) => devices.filter_map(|device| device.name().ok()).collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+pub enum SelectedDevice {
+    Preferred(cpal::Device),
+    // The preferred device is missing (e.g. unplugged), so the default is used
+    Fallback(cpal::Device),
+    Default(cpal::Device),
+}
+
+// Find the preferred input device by name, falling back to the system default
+pub fn select_input_device(preferred: Option<&str>) -> Option<SelectedDevice> {
+    let host = cpal::default_host();
+
+    if let Some(preferred) = preferred {
+        let found = host.input_devices().ok().and_then(|mut devices| {
+            devices.find(|device| device.name().map(|n| n == preferred).unwrap_or(false))
+        });
+        if let Some(device) = found {
+            return Some(SelectedDevice::Preferred(device));
+        }
+        return host.default_input_device().map(SelectedDevice::Fallback);
+    }
+
+    host.default_input_device().map(SelectedDevice::Default)
+}
+
+// Sample formats the capture code can read. cpal may add more.
+pub fn is_capturable(format: cpal::SampleFormat) -> bool {
+    use cpal::SampleFormat::*;
+    matches!(
+        format,
+        I8 | I16 | I32 | I64 | U8 | U16 | U32 | U64 | F32 | F64
+    )
+}
+
+// Choose a capture config. Any channel count and rate works since audio is
+// downmixed and resampled afterwards, but a native 16 kHz config with the
+// fewest channels saves that work when the device has one.
+pub fn pick_input_config(device: &cpal::Device) -> anyhow::Result<cpal::SupportedStreamConfig> {
+    let target = cpal::SampleRate(crate::audio::TARGET_SAMPLE_RATE);
+    let configs: Vec<cpal::SupportedStreamConfigRange> = device
+        .supported_input_configs()
+        .map(|configs| configs.collect())
+        .unwrap_or_default();
+
+    let native = configs
+        .iter()
+        .filter(|c| c.min_sample_rate().0 <= target.0 && c.max_sample_rate().0 >= target.0)
+        .filter(|c| is_capturable(c.sample_format()))
+        .min_by_key(|c| c.channels())
+        .map(|c| c.with_sample_rate(target));
+    if let Some(config) = native {
+        return Ok(config);
+    }
+
+    match device.default_input_config() {
+        Ok(config) if is_capturable(config.sample_format()) => return Ok(config),
+        Ok(_) => {}
+        Err(e) => eprintln!("No default input config: {}", e),
+    }
+    if let Some(config) = configs.iter().find(|c| is_capturable(c.sample_format())) {
+        return Ok(config.with_max_sample_rate());
+    }
+
+    let mut formats: Vec<String> = Vec::new();
+    for config in &configs {
+        let format = format!("{:?}", config.sample_format());
+        if !formats.contains(&format) {
+            formats.push(format);
+        }
+    }
+    Err(anyhow::anyhow!(
+        "The microphone offers no sample format that can be recorded (it offers: {})",
+        if formats.is_empty() {
+            "none".to_string()
+        } else {
+            formats.join(", ")
+        }
+    ))
+}
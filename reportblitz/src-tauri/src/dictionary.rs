@@ -0,0 +1,551 @@
+//dictionary.rs
+// The user's replacement dictionary: find/replace rules applied to every
+// transcript, for names of facilities, case codes and other words the
+// transcription keeps getting wrong. Stored as dictionary.json in the app data
+// directory and exchanged with other installs as CSV or JSON.
+use anyhow::{anyhow, Result};
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use uuid::Uuid;
+
+pub const DICTIONARY_FILE: &str = "dictionary.json";
+
+// Whisper only reads the last 224 tokens of a prompt, so keep hints short
+const MAX_HINTS_LEN: usize = 600;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchKind {
+    // The text anywhere, even inside a longer word
+    Literal,
+    // The text only where it isn't part of a longer word
+    #[default]
+    WholeWord,
+    // A regular expression; the replacement can use $1 or ${name}
+    Regex,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct DictionaryEntry {
+    // Assigned when the entry is added
+    pub id: String,
+    pub find: String,
+    pub replace: String,
+    pub kind: MatchKind,
+    pub case_sensitive: bool,
+    pub enabled: bool,
+}
+
+impl Default for DictionaryEntry {
+    fn default() -> Self {
+        Self {
+            id: String::new(),
+            find: String::new(),
+            replace: String::new(),
+            kind: MatchKind::default(),
+            case_sensitive: false,
+            enabled: true,
+        }
+    }
+}
+
+impl DictionaryEntry {
+    fn pattern(&self) -> Result<Regex> {
+        let pattern = match self.kind {
+            MatchKind::Literal => regex::escape(&self.find),
+            MatchKind::Regex => self.find.clone(),
+            MatchKind::WholeWord => {
+                // \b only makes sense next to a word character; "St. Mary's"
+                // has none after the period
+                let is_word = |c: Option<char>| c.is_some_and(|c| c.is_alphanumeric() || c == '_');
+                let start = if is_word(self.find.chars().next()) {
+                    r"\b"
+                } else {
+                    ""
+                };
+                let end = if is_word(self.find.chars().next_back()) {
+                    r"\b"
+                } else {
+                    ""
+                };
+                format!("{}{}{}", start, regex::escape(&self.find), end)
+            }
+        };
+        RegexBuilder::new(&pattern)
+            .case_insensitive(!self.case_sensitive)
+            .build()
+            .map_err(|e| anyhow!("Invalid pattern \"{}\": {}", self.find, e))
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.find.is_empty() {
+            return Err(anyhow!("A dictionary entry needs text to find"));
+        }
+        self.pattern()?;
+        Ok(())
+    }
+}
+
+// The enabled entries, ready to apply
+pub struct Dictionary {
+    rules: Vec<(Regex, String)>,
+}
+
+impl Dictionary {
+    // Entries are applied in order, each to the result of the one before
+    pub fn new(entries: &[DictionaryEntry]) -> Result<Self> {
+        let mut rules = Vec::new();
+        for entry in entries.iter().filter(|entry| entry.enabled) {
+            // Only regex replacements may refer to groups; elsewhere a $ is a $
+            let replace = match entry.kind {
+                MatchKind::Regex => entry.replace.clone(),
+                _ => entry.replace.replace('$', "$$"),
+            };
+            rules.push((entry.pattern()?, replace));
+        }
+        Ok(Self { rules })
+    }
+
+    pub fn apply(&self, text: &str) -> String {
+        let mut text = text.to_string();
+        for (pattern, replace) in &self.rules {
+            text = pattern.replace_all(&text, replace.as_str()).into_owned();
+        }
+        text
+    }
+}
+
+// Spellings to send as the transcription prompt: the replacements of the
+// enabled literal and whole-word entries, without repeats
+pub fn spelling_hints(entries: &[DictionaryEntry]) -> Vec<String> {
+    let mut hints: Vec<String> = Vec::new();
+    for entry in entries {
+        let hint = entry.replace.trim();
+        if entry.enabled
+            && entry.kind != MatchKind::Regex
+            && !hint.is_empty()
+            && !hints.iter().any(|h| h == hint)
+        {
+            hints.push(hint.to_string());
+        }
+    }
+    hints
+}
+
+// The prompt with spelling hints added, cut short at a hint boundary once
+// it gets long
+pub fn prompt_with_hints(prompt: Option<String>, hints: &[String]) -> Option<String> {
+    let mut listed = String::new();
+    for hint in hints {
+        if listed.len() + hint.len() + 2 > MAX_HINTS_LEN {
+            break;
+        }
+        if !listed.is_empty() {
+            listed.push_str(", ");
+        }
+        listed.push_str(hint);
+    }
+    match (prompt, listed.is_empty()) {
+        (prompt, true) => prompt,
+        (Some(prompt), false) => Some(format!("{} {}.", prompt, listed)),
+        (None, false) => Some(format!("{}.", listed)),
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExchangeFormat {
+    Csv,
+    Json,
+}
+
+impl ExchangeFormat {
+    // Picked by file extension
+    pub fn from_path(path: &Path) -> Result<Self> {
+        match path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_lowercase())
+            .as_deref()
+        {
+            Some("csv") => Ok(Self::Csv),
+            Some("json") => Ok(Self::Json),
+            _ => Err(anyhow!(
+                "Dictionaries can be imported and exported as .csv or .json files"
+            )),
+        }
+    }
+}
+
+// An entry as it is exchanged with other installs, without its id
+#[derive(Serialize, Deserialize)]
+struct ExchangeRow {
+    find: String,
+    #[serde(default)]
+    replace: String,
+    #[serde(default)]
+    kind: MatchKind,
+    #[serde(default)]
+    case_sensitive: bool,
+    #[serde(default = "enabled_by_default")]
+    enabled: bool,
+}
+
+fn enabled_by_default() -> bool {
+    true
+}
+
+// Read entries from a CSV file with a header row (find, replace, kind,
+// case_sensitive, enabled; only find is required) or a JSON array
+pub fn read_file(path: &Path) -> Result<Vec<DictionaryEntry>> {
+    let rows: Vec<ExchangeRow> = match ExchangeFormat::from_path(path)? {
+        ExchangeFormat::Csv => csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_path(path)?
+            .deserialize()
+            .collect::<Result<_, _>>()?,
+        ExchangeFormat::Json => serde_json::from_str(&fs::read_to_string(path)?)?,
+    };
+    let entries: Vec<DictionaryEntry> = rows
+        .into_iter()
+        .map(|row| DictionaryEntry {
+            id: String::new(),
+            find: row.find,
+            replace: row.replace,
+            kind: row.kind,
+            case_sensitive: row.case_sensitive,
+            enabled: row.enabled,
+        })
+        .collect();
+    for (i, entry) in entries.iter().enumerate() {
+        entry
+            .validate()
+            .map_err(|e| anyhow!("Entry {}: {}", i + 1, e))?;
+    }
+    Ok(entries)
+}
+
+pub fn write_file(path: &Path, entries: &[DictionaryEntry]) -> Result<()> {
+    let rows = entries.iter().map(|entry| ExchangeRow {
+        find: entry.find.clone(),
+        replace: entry.replace.clone(),
+        kind: entry.kind,
+        case_sensitive: entry.case_sensitive,
+        enabled: entry.enabled,
+    });
+    match ExchangeFormat::from_path(path)? {
+        ExchangeFormat::Csv => {
+            let mut writer = csv::Writer::from_path(path)?;
+            for row in rows {
+                writer.serialize(row)?;
+            }
+            writer.flush()?;
+        }
+        ExchangeFormat::Json => {
+            let rows: Vec<ExchangeRow> = rows.collect();
+            fs::write(path, serde_json::to_string_pretty(&rows)?)?;
+        }
+    }
+    Ok(())
+}
+
+pub struct DictionaryStore {
+    path: PathBuf,
+    entries: Mutex<Vec<DictionaryEntry>>,
+}
+
+impl DictionaryStore {
+    // Starts empty if the file doesn't exist yet
+    pub fn open(path: &Path) -> Result<Self> {
+        let entries = if path.exists() {
+            serde_json::from_str(&fs::read_to_string(path)?)?
+        } else {
+            Vec::new()
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            entries: Mutex::new(entries),
+        })
+    }
+
+    pub fn entries(&self) -> Vec<DictionaryEntry> {
+        self.entries.lock().unwrap().clone()
+    }
+
+    pub fn dictionary(&self) -> Result<Dictionary> {
+        Dictionary::new(&self.entries.lock().unwrap())
+    }
+
+    pub fn add(&self, mut entry: DictionaryEntry) -> Result<DictionaryEntry> {
+        entry.validate()?;
+        entry.id = Uuid::new_v4().to_string();
+        self.modify(|entries| entries.push(entry.clone()))?;
+        Ok(entry)
+    }
+
+    // Returns false if there is no entry with that id
+    pub fn update(&self, entry: DictionaryEntry) -> Result<bool> {
+        entry.validate()?;
+        self.modify(
+            |entries| match entries.iter_mut().find(|e| e.id == entry.id) {
+                Some(existing) => {
+                    *existing = entry;
+                    true
+                }
+                None => false,
+            },
+        )
+    }
+
+    pub fn delete(&self, id: &str) -> Result<bool> {
+        self.modify(|entries| {
+            let count = entries.len();
+            entries.retain(|e| e.id != id);
+            entries.len() < count
+        })
+    }
+
+    // Add imported entries, or replace every entry with them. Entries whose
+    // find text and kind are already present are skipped. Returns how many
+    // were added.
+    pub fn import(&self, imported: Vec<DictionaryEntry>, replace_all: bool) -> Result<usize> {
+        for entry in &imported {
+            entry.validate()?;
+        }
+        self.modify(|entries| {
+            if replace_all {
+                entries.clear();
+            }
+            let mut added = 0;
+            for mut entry in imported {
+                let duplicate = entries
+                    .iter()
+                    .any(|e| e.find == entry.find && e.kind == entry.kind);
+                if !duplicate {
+                    entry.id = Uuid::new_v4().to_string();
+                    entries.push(entry);
+                    added += 1;
+                }
+            }
+            added
+        })
+    }
+
+    // Change a copy of the entries and keep it once it is saved
+    fn modify<T>(&self, change: impl FnOnce(&mut Vec<DictionaryEntry>) -> T) -> Result<T> {
+        let mut entries = self.entries.lock().unwrap();
+        let mut changed = entries.clone();
+        let result = change(&mut changed);
+        self.save(&changed)?;
+        *entries = changed;
+        Ok(result)
+    }
+
+    // Write to a temporary file first so a crash can't leave half a dictionary
+    fn save(&self, entries: &[DictionaryEntry]) -> Result<()> {
+        let tmp = self.path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_string_pretty(entries)?)?;
+        fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+
+    fn entry(find: &str, replace: &str, kind: MatchKind) -> DictionaryEntry {
+        DictionaryEntry {
+            find: find.to_string(),
+            replace: replace.to_string(),
+            kind,
+            ..Default::default()
+        }
+    }
+
+    fn apply(entries: &[DictionaryEntry], text: &str) -> String {
+        Dictionary::new(entries).unwrap().apply(text)
+    }
+
+    #[test]
+    fn whole_words_only_match_whole_words() {
+        let entries = [entry("gen", "General", MatchKind::WholeWord)];
+        assert_eq!(apply(&entries, "mercy gen today"), "mercy General today");
+        assert_eq!(apply(&entries, "generic gentle"), "generic gentle");
+        assert_eq!(apply(&entries, "gen."), "General.");
+    }
+
+    #[test]
+    fn whole_words_may_start_or_end_with_punctuation() {
+        let entries = [entry("st. marys", "St. Mary's", MatchKind::WholeWord)];
+        assert_eq!(apply(&entries, "at st. marys now"), "at St. Mary's now");
+    }
+
+    #[test]
+    fn literals_match_inside_words() {
+        let entries = [entry("colour", "color", MatchKind::Literal)];
+        assert_eq!(apply(&entries, "discoloured"), "discolored");
+    }
+
+    #[test]
+    fn matching_ignores_case_unless_case_sensitive() {
+        let entries = [entry("acme", "ACME Corp", MatchKind::WholeWord)];
+        assert_eq!(apply(&entries, "Acme and ACME"), "ACME Corp and ACME Corp");
+
+        let entries = [DictionaryEntry {
+            case_sensitive: true,
+            ..entry("acme", "ACME Corp", MatchKind::WholeWord)
+        }];
+        assert_eq!(apply(&entries, "Acme and acme"), "Acme and ACME Corp");
+    }
+
+    #[test]
+    fn regex_replacements_use_groups() {
+        let entries = [entry(
+            r"case (\d+) dash (\d+)",
+            "CASE-$1-$2",
+            MatchKind::Regex,
+        )];
+        assert_eq!(
+            apply(&entries, "see Case 12 dash 345 for details"),
+            "see CASE-12-345 for details"
+        );
+    }
+
+    #[test]
+    fn dollar_signs_are_literal_outside_regex_entries() {
+        let entries = [
+            entry("five bucks", "$5", MatchKind::WholeWord),
+            entry("fee", "$1 fee", MatchKind::Literal),
+        ];
+        assert_eq!(apply(&entries, "five bucks fee"), "$5 $1 fee");
+    }
+
+    #[test]
+    fn entries_apply_in_order_and_disabled_ones_are_skipped() {
+        let entries = [
+            entry("a", "b", MatchKind::WholeWord),
+            entry("b", "c", MatchKind::WholeWord),
+            DictionaryEntry {
+                enabled: false,
+                ..entry("c", "d", MatchKind::WholeWord)
+            },
+        ];
+        assert_eq!(apply(&entries, "a b c"), "c c c");
+    }
+
+    #[test]
+    fn invalid_entries_are_rejected() {
+        assert!(entry("", "x", MatchKind::WholeWord).validate().is_err());
+        assert!(entry("(unclosed", "x", MatchKind::Regex)
+            .validate()
+            .is_err());
+        assert!(entry("(unclosed", "x", MatchKind::Literal)
+            .validate()
+            .is_ok());
+    }
+
+    #[test]
+    fn spelling_hints_skip_regex_disabled_and_repeated_entries() {
+        let entries = [
+            entry("mercy gen", "Mercy General", MatchKind::WholeWord),
+            entry("mercy general", "Mercy General", MatchKind::WholeWord),
+            entry(r"\d+", "N", MatchKind::Regex),
+            DictionaryEntry {
+                enabled: false,
+                ..entry("x", "Off", MatchKind::Literal)
+            },
+        ];
+        assert_eq!(spelling_hints(&entries), vec!["Mercy General"]);
+        assert_eq!(
+            prompt_with_hints(
+                Some("Police report.".to_string()),
+                &spelling_hints(&entries)
+            ),
+            Some("Police report. Mercy General.".to_string())
+        );
+        assert_eq!(prompt_with_hints(None, &[]), None);
+    }
+
+    fn exchanged_entries() -> Vec<DictionaryEntry> {
+        vec![
+            entry(
+                "mercy gen",
+                "Mercy General, \"East\" wing",
+                MatchKind::WholeWord,
+            ),
+            DictionaryEntry {
+                case_sensitive: true,
+                enabled: false,
+                ..entry(r"case (\d+)", "CASE-$1", MatchKind::Regex)
+            },
+            entry("colour", "color", MatchKind::Literal),
+        ]
+    }
+
+    #[test]
+    fn csv_round_trip() {
+        let dir = TempDir::new();
+        let path = dir.join("rules.csv");
+        write_file(&path, &exchanged_entries()).unwrap();
+        assert_eq!(read_file(&path).unwrap(), exchanged_entries());
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let dir = TempDir::new();
+        let path = dir.join("rules.JSON");
+        write_file(&path, &exchanged_entries()).unwrap();
+        assert_eq!(read_file(&path).unwrap(), exchanged_entries());
+    }
+
+    #[test]
+    fn csv_import_needs_only_find() {
+        let dir = TempDir::new();
+        let path = dir.join("rules.csv");
+        fs::write(&path, "find,replace\n mercy gen , Mercy General\n").unwrap();
+        assert_eq!(
+            read_file(&path).unwrap(),
+            vec![entry("mercy gen", "Mercy General", MatchKind::WholeWord)]
+        );
+    }
+
+    #[test]
+    fn import_reports_the_invalid_entry() {
+        let dir = TempDir::new();
+        let path = dir.join("rules.json");
+        fs::write(&path, r#"[{"find": "ok"}, {"find": "(", "kind": "regex"}]"#).unwrap();
+        let error = read_file(&path).unwrap_err().to_string();
+        assert!(error.starts_with("Entry 2:"), "{}", error);
+
+        let dir = TempDir::new();
+        let path = dir.join("rules.txt");
+        assert!(write_file(&path, &exchanged_entries()).is_err());
+    }
+
+    #[test]
+    fn store_saves_and_skips_duplicate_imports() {
+        let dir = TempDir::new();
+        let path = dir.join(DICTIONARY_FILE);
+        let store = DictionaryStore::open(&path).unwrap();
+        let added = store
+            .add(entry("mercy gen", "Mercy General", MatchKind::WholeWord))
+            .unwrap();
+        assert!(!added.id.is_empty());
+
+        let imported = store.import(exchanged_entries(), false).unwrap();
+        assert_eq!(imported, 2);
+
+        let reopened = DictionaryStore::open(&path).unwrap();
+        assert_eq!(reopened.entries(), store.entries());
+        assert_eq!(reopened.entries().len(), 3);
+
+        assert!(reopened.delete(&added.id).unwrap());
+        assert!(!reopened.delete(&added.id).unwrap());
+        assert_eq!(store.import(exchanged_entries(), true).unwrap(), 3);
+    }
+}
@@ -0,0 +1,152 @@
+//encoding.rs
+// Formats recordings are uploaded in. Every server reads WAV; FLAC is
+// lossless at roughly half the size and Ogg/Opus is a fraction of that, which
+// keeps long takes under upload limits and speeds up slow links.
+use crate::audio;
+use crate::flac;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AudioFormat {
+    #[default]
+    Wav,
+    Flac,
+    Opus,
+}
+
+impl AudioFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            AudioFormat::Wav => "wav",
+            AudioFormat::Flac => "flac",
+            AudioFormat::Opus => "ogg",
+        }
+    }
+
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            AudioFormat::Wav => "audio/wav",
+            AudioFormat::Flac => "audio/flac",
+            AudioFormat::Opus => "audio/ogg",
+        }
+    }
+
+    // Whether this build can encode the format
+    pub fn is_available(&self) -> bool {
+        *self != AudioFormat::Opus || cfg!(feature = "opus")
+    }
+}
+
+pub struct EncodedAudio {
+    pub data: Vec<u8>,
+    pub format: AudioFormat,
+}
+
+// Encode mono samples in the given format, or as WAV if this build can't
+// produce it
+pub fn encode(samples: &[f32], sample_rate: u32, format: AudioFormat) -> Result<EncodedAudio> {
+    let format = if format.is_available() {
+        format
+    } else {
+        eprintln!(
+            "{:?} encoding is not available in this build, using WAV",
+            format
+        );
+        AudioFormat::Wav
+    };
+
+    let data = match format {
+        AudioFormat::Wav => audio::encode_wav(samples, sample_rate)?,
+        AudioFormat::Flac => flac::encode_flac(samples, sample_rate)?,
+        AudioFormat::Opus => encode_opus(samples, sample_rate)?,
+    };
+    Ok(EncodedAudio { data, format })
+}
+
+// Encode mono samples as an Ogg/Opus file (RFC 7845) in memory
+#[cfg(feature = "opus")]
+fn encode_opus(samples: &[f32], sample_rate: u32) -> Result<Vec<u8>> {
+    use audiopus::coder::Encoder;
+    use audiopus::{Application, Bitrate, Channels, SampleRate};
+    use ogg::writing::{PacketWriteEndInfo, PacketWriter};
+
+    // Ogg Opus positions are counted at 48 kHz whatever the input rate
+    const GRANULE_RATE: u64 = 48000;
+    const FRAME_MS: usize = 20;
+    const SERIAL: u32 = 1;
+    const MAX_PACKET: usize = 4000;
+
+    let rate = SampleRate::try_from(sample_rate as i32)
+        .map_err(|_| anyhow!("Opus does not support a sample rate of {}", sample_rate))?;
+    let mut encoder = Encoder::new(rate, Channels::Mono, Application::Voip)?;
+    // Plenty for intelligible speech at about a tenth of the WAV size
+    encoder.set_bitrate(Bitrate::BitsPerSecond(24000))?;
+
+    let scale = GRANULE_RATE / sample_rate as u64;
+    let lookahead = encoder.lookahead()? as usize;
+    let pre_skip = lookahead as u64 * scale;
+
+    let mut writer = PacketWriter::new(Vec::new());
+
+    let mut head = Vec::with_capacity(19);
+    head.extend_from_slice(b"OpusHead");
+    head.push(1); // version
+    head.push(1); // channels
+    head.extend_from_slice(&(pre_skip as u16).to_le_bytes());
+    head.extend_from_slice(&sample_rate.to_le_bytes());
+    head.extend_from_slice(&0i16.to_le_bytes()); // output gain
+    head.push(0); // channel mapping family
+    writer.write_packet(
+        head.into_boxed_slice(),
+        SERIAL,
+        PacketWriteEndInfo::EndPage,
+        0,
+    )?;
+
+    let vendor = b"ReportBlitz";
+    let mut tags = Vec::new();
+    tags.extend_from_slice(b"OpusTags");
+    tags.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+    tags.extend_from_slice(vendor);
+    tags.extend_from_slice(&0u32.to_le_bytes()); // no user comments
+    writer.write_packet(
+        tags.into_boxed_slice(),
+        SERIAL,
+        PacketWriteEndInfo::EndPage,
+        0,
+    )?;
+
+    // The encoder's look-ahead delays its output, so keep feeding silence
+    // until every real sample has come out. The last granule position tells
+    // decoders where the audio actually ends.
+    let frame_len = sample_rate as usize * FRAME_MS / 1000;
+    let frame_count = (samples.len() + lookahead).div_ceil(frame_len).max(1);
+    let final_granule = pre_skip + samples.len() as u64 * scale;
+
+    let mut frame = vec![0.0f32; frame_len];
+    let mut packet = vec![0u8; MAX_PACKET];
+    for i in 0..frame_count {
+        let start = (i * frame_len).min(samples.len());
+        let end = ((i + 1) * frame_len).min(samples.len());
+        frame.fill(0.0);
+        frame[..end - start].copy_from_slice(&samples[start..end]);
+
+        let len = encoder.encode_float(&frame, &mut packet)?;
+        let granule = (((i + 1) * frame_len) as u64 * scale).min(final_granule);
+        let end_info = if i + 1 == frame_count {
+            PacketWriteEndInfo::EndStream
+        } else {
+            PacketWriteEndInfo::NormalPacket
+        };
+        writer.write_packet(packet[..len].into(), SERIAL, end_info, granule)?;
+    }
+
+    Ok(writer.into_inner())
+}
+
+#[cfg(not(feature = "opus"))]
+fn encode_opus(_samples: &[f32], _sample_rate: u32) -> Result<Vec<u8>> {
+    Err(anyhow!("Opus encoding is not available in this build"))
+}
@@ -0,0 +1,495 @@
+//flac.rs
+// A small FLAC encoder for mono 16-bit audio. It uses the fixed predictors
+// and Rice coded residuals, which typically halves the size of speech
+// compared to WAV while staying lossless.
+use anyhow::{anyhow, Result};
+
+const BLOCK_SIZE: usize = 4096;
+const BITS_PER_SAMPLE: u32 = 16;
+const MAX_FIXED_ORDER: usize = 4;
+const MAX_PARTITION_ORDER: u32 = 6;
+// Rice parameters are 4 bits wide and 15 is reserved for escapes
+const MAX_RICE_PARAM: u32 = 14;
+
+// Encode mono f32 samples as a FLAC file in memory
+pub fn encode_flac(samples: &[f32], sample_rate: u32) -> Result<Vec<u8>> {
+    if sample_rate == 0 || sample_rate >= 1 << 20 {
+        return Err(anyhow!("Unsupported sample rate {}", sample_rate));
+    }
+    let samples: Vec<i32> = samples
+        .iter()
+        .map(|&s| (s.clamp(-1.0, 1.0) * i16::MAX as f32) as i32)
+        .collect();
+
+    let mut out = BitWriter::default();
+    out.write_bytes(b"fLaC");
+    write_stream_info(&mut out, sample_rate, samples.len() as u64);
+
+    for (frame_number, block) in samples.chunks(BLOCK_SIZE).enumerate() {
+        write_frame(&mut out, frame_number as u64, block, sample_rate);
+    }
+    Ok(out.into_bytes())
+}
+
+fn write_stream_info(out: &mut BitWriter, sample_rate: u32, total_samples: u64) {
+    // Metadata block header: last block, type 0 (STREAMINFO), 34 bytes long
+    out.write(1, 1);
+    out.write(0, 7);
+    out.write(34, 24);
+
+    out.write(BLOCK_SIZE as u64, 16); // minimum block size
+    out.write(BLOCK_SIZE as u64, 16); // maximum block size
+    out.write(0, 24); // minimum frame size, unknown
+    out.write(0, 24); // maximum frame size, unknown
+    out.write(sample_rate as u64, 20);
+    out.write(0, 3); // channels - 1
+    out.write((BITS_PER_SAMPLE - 1) as u64, 5);
+    out.write(total_samples, 36);
+    // An all-zero MD5 signature means it wasn't computed
+    out.write_bytes(&[0; 16]);
+}
+
+fn write_frame(out: &mut BitWriter, frame_number: u64, block: &[i32], sample_rate: u32) {
+    let frame_start = out.len();
+
+    out.write(0b11_1111_1111_1110, 14); // sync code
+    out.write(0, 1); // reserved
+    out.write(0, 1); // fixed block size
+    out.write(0b0111, 4); // block size - 1 follows as 16 bits
+    out.write(sample_rate_code(sample_rate), 4);
+    out.write(0b0000, 4); // one channel
+    out.write(0b100, 3); // 16 bits per sample
+    out.write(0, 1); // reserved
+    write_utf8_number(out, frame_number);
+    out.write((block.len() - 1) as u64, 16);
+    let crc = crc8(out.bytes_from(frame_start));
+    out.write(crc as u64, 8);
+
+    write_subframe(out, block);
+
+    out.align();
+    let crc = crc16(out.bytes_from(frame_start));
+    out.write(crc as u64, 16);
+}
+
+fn write_subframe(out: &mut BitWriter, block: &[i32]) {
+    // Silence, or any other constant signal, is stored as a single value
+    if block.iter().all(|&s| s == block[0]) {
+        out.write(0, 1);
+        out.write(0b000000, 6);
+        out.write(0, 1);
+        out.write_signed(block[0], BITS_PER_SAMPLE);
+        return;
+    }
+
+    // Pick the fixed predictor and partitioning that code smallest
+    let mut best: Option<(u64, usize, Vec<i32>, Partitioning)> = None;
+    for order in 0..=MAX_FIXED_ORDER.min(block.len() - 1) {
+        let residual = fixed_residual(block, order);
+        let partitioning = best_partitioning(&residual, order, block.len());
+        let bits = order as u64 * BITS_PER_SAMPLE as u64 + partitioning.bits;
+        match &best {
+            Some((best_bits, ..)) if *best_bits <= bits => (),
+            _ => best = Some((bits, order, residual, partitioning)),
+        }
+    }
+
+    let (bits, order, residual, partitioning) = best.expect("at least one predictor order");
+    if bits >= block.len() as u64 * BITS_PER_SAMPLE as u64 {
+        out.write(0, 1);
+        out.write(0b000001, 6);
+        out.write(0, 1);
+        for &sample in block {
+            out.write_signed(sample, BITS_PER_SAMPLE);
+        }
+        return;
+    }
+
+    out.write(0, 1);
+    out.write(0b001000 | order as u64, 6);
+    out.write(0, 1);
+    for &sample in &block[..order] {
+        out.write_signed(sample, BITS_PER_SAMPLE);
+    }
+
+    out.write(0b00, 2); // Rice coding with 4-bit parameters
+    out.write(partitioning.order as u64, 4);
+    let mut start = 0;
+    for (i, &param) in partitioning.params.iter().enumerate() {
+        let len = partition_len(i, partitioning.order, order, block.len());
+        out.write(param as u64, 4);
+        for &r in &residual[start..start + len] {
+            write_rice(out, r, param);
+        }
+        start += len;
+    }
+}
+
+// Prediction errors of the fixed polynomial predictor of the given order,
+// for every sample after the warm-up ones
+fn fixed_residual(block: &[i32], order: usize) -> Vec<i32> {
+    (order..block.len())
+        .map(|i| {
+            let s = |k: usize| block[i - k] as i64;
+            let prediction = match order {
+                0 => 0,
+                1 => s(1),
+                2 => 2 * s(1) - s(2),
+                3 => 3 * s(1) - 3 * s(2) + s(3),
+                _ => 4 * s(1) - 6 * s(2) + 4 * s(3) - s(4),
+            };
+            (block[i] as i64 - prediction) as i32
+        })
+        .collect()
+}
+
+struct Partitioning {
+    order: u32,
+    params: Vec<u32>,
+    // Size of the residual section including parameters
+    bits: u64,
+}
+
+fn best_partitioning(residual: &[i32], predictor_order: usize, block_len: usize) -> Partitioning {
+    let mut best: Option<Partitioning> = None;
+    for order in 0..=MAX_PARTITION_ORDER {
+        // Partitions must split the block evenly and the first one must be
+        // longer than the warm-up
+        let partitions = 1 << order;
+        if !block_len.is_multiple_of(partitions) || block_len / partitions <= predictor_order {
+            break;
+        }
+
+        let mut params = Vec::with_capacity(partitions);
+        let mut bits = 2 + 4;
+        let mut start = 0;
+        for i in 0..partitions {
+            let len = partition_len(i, order, predictor_order, block_len);
+            let part = &residual[start..start + len];
+            let param = rice_param(part);
+            bits += 4 + rice_bits(part, param);
+            params.push(param);
+            start += len;
+        }
+
+        match &best {
+            Some(b) if b.bits <= bits => (),
+            _ => {
+                best = Some(Partitioning {
+                    order,
+                    params,
+                    bits,
+                })
+            }
+        }
+    }
+    best.expect("partition order 0 always fits")
+}
+
+fn partition_len(
+    index: usize,
+    partition_order: u32,
+    predictor_order: usize,
+    block_len: usize,
+) -> usize {
+    let len = block_len >> partition_order;
+    if index == 0 {
+        len - predictor_order
+    } else {
+        len
+    }
+}
+
+fn zigzag(r: i32) -> u64 {
+    ((r << 1) ^ (r >> 31)) as u32 as u64
+}
+
+// Estimate the best Rice parameter from the mean of the folded residuals
+fn rice_param(part: &[i32]) -> u32 {
+    if part.is_empty() {
+        return 0;
+    }
+    let mean = part.iter().map(|&r| zigzag(r)).sum::<u64>() / part.len() as u64;
+    if mean == 0 {
+        0
+    } else {
+        (63 - mean.leading_zeros()).min(MAX_RICE_PARAM)
+    }
+}
+
+fn rice_bits(part: &[i32], param: u32) -> u64 {
+    part.iter()
+        .map(|&r| 1 + param as u64 + (zigzag(r) >> param))
+        .sum()
+}
+
+fn write_rice(out: &mut BitWriter, r: i32, param: u32) {
+    let value = zigzag(r);
+    let mut quotient = value >> param;
+    while quotient >= 32 {
+        out.write(0, 32);
+        quotient -= 32;
+    }
+    out.write(1, quotient as u32 + 1);
+    if param > 0 {
+        out.write(value & ((1 << param) - 1), param);
+    }
+}
+
+fn sample_rate_code(sample_rate: u32) -> u64 {
+    match sample_rate {
+        8000 => 0b0100,
+        16000 => 0b0101,
+        22050 => 0b0110,
+        24000 => 0b0111,
+        32000 => 0b1000,
+        44100 => 0b1001,
+        48000 => 0b1010,
+        96000 => 0b1011,
+        // Taken from STREAMINFO
+        _ => 0b0000,
+    }
+}
+
+// Frame numbers use the same variable-length coding as UTF-8
+fn write_utf8_number(out: &mut BitWriter, n: u64) {
+    if n < 0x80 {
+        out.write(n, 8);
+        return;
+    }
+    let continuation_bytes = match n {
+        0..=0x7ff => 1,
+        0x800..=0xffff => 2,
+        0x1_0000..=0x1f_ffff => 3,
+        0x20_0000..=0x3ff_ffff => 4,
+        0x400_0000..=0x7fff_ffff => 5,
+        _ => 6,
+    };
+    let lead_marker = (0xff00_u64 >> (continuation_bytes + 1)) & 0xff;
+    out.write(lead_marker | (n >> (6 * continuation_bytes)), 8);
+    for i in (0..continuation_bytes).rev() {
+        out.write(0x80 | ((n >> (6 * i)) & 0x3f), 8);
+    }
+}
+
+fn crc8(data: &[u8]) -> u8 {
+    let mut crc = 0u8;
+    for &byte in data {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x8005
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+// MSB-first bit writer
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    acc: u64,
+    acc_bits: u32,
+}
+
+impl BitWriter {
+    // Write the low `bits` bits of `value`, at most 32 at a time
+    fn write(&mut self, value: u64, bits: u32) {
+        if bits > 32 {
+            self.write(value >> 32, bits - 32);
+            self.write(value & 0xffff_ffff, 32);
+            return;
+        }
+        self.acc = (self.acc << bits) | (value & ((1 << bits) - 1));
+        self.acc_bits += bits;
+        while self.acc_bits >= 8 {
+            self.acc_bits -= 8;
+            self.bytes.push((self.acc >> self.acc_bits) as u8);
+        }
+        self.acc &= (1 << self.acc_bits) - 1;
+    }
+
+    fn write_signed(&mut self, value: i32, bits: u32) {
+        self.write(value as u32 as u64, bits);
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.write(byte as u64, 8);
+        }
+    }
+
+    // Pad with zero bits up to the next byte boundary
+    fn align(&mut self) {
+        if self.acc_bits > 0 {
+            self.write(0, 8 - self.acc_bits);
+        }
+    }
+
+    // Bytes written since `start`; only complete bytes are included
+    fn bytes_from(&self, start: usize) -> &[u8] {
+        &self.bytes[start..]
+    }
+
+    fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    fn into_bytes(mut self) -> Vec<u8> {
+        self.align();
+        self.bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use symphonia::core::audio::SampleBuffer;
+    use symphonia::core::codecs::DecoderOptions;
+    use symphonia::core::errors::Error as SymphoniaError;
+    use symphonia::core::formats::FormatOptions;
+    use symphonia::core::io::MediaSourceStream;
+    use symphonia::core::meta::MetadataOptions;
+    use symphonia::core::probe::Hint;
+
+    // Decode a FLAC file with symphonia, which checks the frame CRCs
+    fn decode(data: Vec<u8>, sample_rate: u32) -> Vec<i16> {
+        let stream =
+            MediaSourceStream::new(Box::new(std::io::Cursor::new(data)), Default::default());
+        let mut hint = Hint::new();
+        hint.with_extension("flac");
+        let mut format = symphonia::default::get_probe()
+            .format(
+                &hint,
+                stream,
+                &FormatOptions::default(),
+                &MetadataOptions::default(),
+            )
+            .unwrap()
+            .format;
+        let track = format.default_track().unwrap();
+        assert_eq!(track.codec_params.sample_rate, Some(sample_rate));
+        let mut decoder = symphonia::default::get_codecs()
+            .make(&track.codec_params, &DecoderOptions { verify: true })
+            .unwrap();
+
+        let mut samples = Vec::new();
+        loop {
+            let packet = match format.next_packet() {
+                Ok(packet) => packet,
+                Err(SymphoniaError::IoError(e))
+                    if e.kind() == std::io::ErrorKind::UnexpectedEof =>
+                {
+                    break
+                }
+                Err(e) => panic!("{}", e),
+            };
+            let decoded = decoder.decode(&packet).unwrap();
+            let mut buffer = SampleBuffer::<i16>::new(decoded.capacity() as u64, *decoded.spec());
+            buffer.copy_interleaved_ref(decoded);
+            samples.extend_from_slice(buffer.samples());
+        }
+        samples
+    }
+
+    // The 16-bit samples the encoder stores
+    fn quantized(samples: &[f32]) -> Vec<i16> {
+        samples
+            .iter()
+            .map(|&s| (s.clamp(-1.0, 1.0) * i16::MAX as f32) as i16)
+            .collect()
+    }
+
+    fn assert_round_trip(samples: &[f32], sample_rate: u32) {
+        let data = encode_flac(samples, sample_rate).unwrap();
+        assert_eq!(decode(data, sample_rate), quantized(samples));
+    }
+
+    fn sine(len: usize, sample_rate: u32) -> Vec<f32> {
+        (0..len)
+            .map(|i| {
+                0.8 * (2.0 * std::f32::consts::PI * 440.0 * i as f32 / sample_rate as f32).sin()
+            })
+            .collect()
+    }
+
+    // Deterministic white noise over the full range
+    fn noise(len: usize) -> Vec<f32> {
+        let mut state: u32 = 0x1234_5678;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                (state >> 16) as f32 / 32768.0 - 1.0
+            })
+            .collect()
+    }
+
+    #[test]
+    fn sine_round_trips() {
+        assert_round_trip(&sine(3 * BLOCK_SIZE, 16000), 16000);
+    }
+
+    #[test]
+    fn silence_round_trips() {
+        assert_round_trip(&vec![0.0; 2 * BLOCK_SIZE], 16000);
+    }
+
+    #[test]
+    fn noise_round_trips() {
+        assert_round_trip(&noise(2 * BLOCK_SIZE), 16000);
+        // Full scale square wave, the largest residuals there are
+        let square: Vec<f32> = (0..BLOCK_SIZE)
+            .map(|i| if i % 2 == 0 { 1.0 } else { -1.0 })
+            .collect();
+        assert_round_trip(&square, 16000);
+    }
+
+    #[test]
+    fn odd_block_sizes_round_trip() {
+        for len in [
+            2,
+            3,
+            4,
+            5,
+            17,
+            1000,
+            BLOCK_SIZE - 1,
+            BLOCK_SIZE + 1,
+            2 * BLOCK_SIZE + 3,
+        ] {
+            assert_round_trip(&sine(len, 16000), 16000);
+            assert_round_trip(&noise(len), 16000);
+        }
+    }
+
+    #[test]
+    fn single_sample_round_trips() {
+        assert_round_trip(&[0.5], 16000);
+        assert_round_trip(&[-1.0], 16000);
+    }
+
+    #[test]
+    fn other_sample_rates_round_trip() {
+        for sample_rate in [8000, 22050, 44100, 48000, 11025] {
+            assert_round_trip(&sine(BLOCK_SIZE + 100, sample_rate), sample_rate);
+        }
+    }
+}
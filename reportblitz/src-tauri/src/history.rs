@@ -0,0 +1,399 @@
+//history.rs
+// Persistent transcription history in a SQLite database in the app data
+// directory, with full-text search over the transcript text.
+use anyhow::Result;
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use serde::Serialize;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Bump when the schema changes and add a step to `migrate`
+const SCHEMA_VERSION: i32 = 1;
+
+#[derive(Serialize, Clone, Debug)]
+pub struct HistoryEntry {
+    pub id: i64,
+    pub text: String,
+    // Unix time in milliseconds
+    pub created_at: i64,
+    pub duration_secs: f64,
+    pub provider: String,
+    pub target_app: Option<String>,
+    pub has_audio: bool,
+    pub pinned: bool,
+}
+
+pub struct NewHistoryEntry<'a> {
+    pub text: &'a str,
+    pub duration_secs: f64,
+    pub provider: &'a str,
+    pub target_app: Option<&'a str>,
+    pub audio: Option<&'a [u8]>,
+}
+
+pub struct HistoryStore {
+    conn: Mutex<Connection>,
+}
+
+const ENTRY_COLUMNS: &str =
+    "h.id, h.text, h.created_at, h.duration_secs, h.provider, h.target_app, h.audio IS NOT NULL, h.pinned";
+
+impl HistoryStore {
+    pub fn open(path: &Path) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let conn = Connection::open(path)?;
+        migrate(&conn)?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    pub fn insert(&self, entry: NewHistoryEntry) -> Result<i64> {
+        let created_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as i64)
+            .unwrap_or_default();
+
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO history (text, created_at, duration_secs, provider, target_app, audio)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                entry.text,
+                created_at,
+                entry.duration_secs,
+                entry.provider,
+                entry.target_app,
+                entry.audio
+            ],
+        )?;
+        Ok(conn.last_insert_rowid())
+    }
+
+    // Pinned entries first, then newest first
+    pub fn list(&self, limit: u32, offset: u32) -> Result<Vec<HistoryEntry>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM history h
+             ORDER BY h.pinned DESC, h.created_at DESC
+             LIMIT ?1 OFFSET ?2",
+            ENTRY_COLUMNS
+        ))?;
+        let entries = stmt
+            .query_map(params![limit, offset], entry_from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(entries)
+    }
+
+    // Full-text search. Every word in the query must match, as a prefix.
+    pub fn search(&self, query: &str, limit: u32) -> Result<Vec<HistoryEntry>> {
+        let fts_query = to_fts_query(query);
+        if fts_query.is_empty() {
+            return self.list(limit, 0);
+        }
+
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM history_fts f
+             JOIN history h ON h.id = f.rowid
+             WHERE history_fts MATCH ?1
+             ORDER BY h.pinned DESC, f.rank
+             LIMIT ?2",
+            ENTRY_COLUMNS
+        ))?;
+        let entries = stmt
+            .query_map(params![fts_query, limit], entry_from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(entries)
+    }
+
+    pub fn delete(&self, id: i64) -> Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        let changed = conn.execute("DELETE FROM history WHERE id = ?1", params![id])?;
+        Ok(changed > 0)
+    }
+
+    pub fn set_pinned(&self, id: i64, pinned: bool) -> Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        let changed = conn.execute(
+            "UPDATE history SET pinned = ?2 WHERE id = ?1",
+            params![id, pinned],
+        )?;
+        Ok(changed > 0)
+    }
+
+    pub fn audio(&self, id: i64) -> Result<Option<Vec<u8>>> {
+        let conn = self.conn.lock().unwrap();
+        let audio = conn
+            .query_row(
+                "SELECT audio FROM history WHERE id = ?1",
+                params![id],
+                |row| row.get::<_, Option<Vec<u8>>>(0),
+            )
+            .optional()?;
+        Ok(audio.flatten())
+    }
+}
+
+fn migrate(conn: &Connection) -> Result<()> {
+    let version: i32 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+
+    if version < 1 {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS history (
+                 id INTEGER PRIMARY KEY AUTOINCREMENT,
+                 text TEXT NOT NULL,
+                 created_at INTEGER NOT NULL,
+                 duration_secs REAL NOT NULL,
+                 provider TEXT NOT NULL,
+                 target_app TEXT,
+                 audio BLOB,
+                 pinned INTEGER NOT NULL DEFAULT 0
+             );
+             CREATE INDEX IF NOT EXISTS history_created_at ON history (created_at);
+             CREATE VIRTUAL TABLE IF NOT EXISTS history_fts
+                 USING fts5(text, content='history', content_rowid='id');
+             CREATE TRIGGER IF NOT EXISTS history_ai AFTER INSERT ON history BEGIN
+                 INSERT INTO history_fts (rowid, text) VALUES (new.id, new.text);
+             END;
+             CREATE TRIGGER IF NOT EXISTS history_ad AFTER DELETE ON history BEGIN
+                 INSERT INTO history_fts (history_fts, rowid, text)
+                     VALUES ('delete', old.id, old.text);
+             END;
+             CREATE TRIGGER IF NOT EXISTS history_au AFTER UPDATE OF text ON history BEGIN
+                 INSERT INTO history_fts (history_fts, rowid, text)
+                     VALUES ('delete', old.id, old.text);
+                 INSERT INTO history_fts (rowid, text) VALUES (new.id, new.text);
+             END;",
+        )?;
+    }
+
+    conn.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+    Ok(())
+}
+
+fn entry_from_row(row: &Row) -> rusqlite::Result<HistoryEntry> {
+    Ok(HistoryEntry {
+        id: row.get(0)?,
+        text: row.get(1)?,
+        created_at: row.get(2)?,
+        duration_secs: row.get(3)?,
+        provider: row.get(4)?,
+        target_app: row.get(5)?,
+        has_audio: row.get(6)?,
+        pinned: row.get(7)?,
+    })
+}
+
+// Turn free text into an FTS5 query, quoting each word so user input can't
+// break the query syntax
+fn to_fts_query(query: &str) -> String {
+    query
+        .split_whitespace()
+        .map(|word| format!("\"{}\"*", word.replace('"', "\"\"")))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+    use std::time::Duration;
+
+    fn open(dir: &TempDir) -> HistoryStore {
+        HistoryStore::open(&dir.join("history.db")).unwrap()
+    }
+
+    // Entries are ordered by creation time in milliseconds, so keep them apart
+    fn add(store: &HistoryStore, text: &str) -> i64 {
+        std::thread::sleep(Duration::from_millis(2));
+        store
+            .insert(NewHistoryEntry {
+                text,
+                duration_secs: 1.5,
+                provider: "OpenAI",
+                target_app: Some("Editor"),
+                audio: None,
+            })
+            .unwrap()
+    }
+
+    fn texts(entries: &[HistoryEntry]) -> Vec<&str> {
+        entries.iter().map(|e| e.text.as_str()).collect()
+    }
+
+    #[test]
+    fn inserted_entries_are_listed_newest_first() {
+        let dir = TempDir::new();
+        let store = open(&dir);
+        add(&store, "first");
+        let id = store
+            .insert(NewHistoryEntry {
+                text: "second",
+                duration_secs: 2.0,
+                provider: "Local",
+                target_app: None,
+                audio: Some(b"RIFF"),
+            })
+            .unwrap();
+
+        let entries = store.list(10, 0).unwrap();
+        assert_eq!(texts(&entries), vec!["second", "first"]);
+        let entry = &entries[0];
+        assert_eq!(entry.id, id);
+        assert_eq!(entry.duration_secs, 2.0);
+        assert_eq!(entry.provider, "Local");
+        assert_eq!(entry.target_app, None);
+        assert!(entry.has_audio && !entry.pinned);
+        assert!(!entries[1].has_audio);
+        assert_eq!(entries[1].target_app.as_deref(), Some("Editor"));
+        assert_eq!(store.audio(id).unwrap(), Some(b"RIFF".to_vec()));
+        assert_eq!(store.audio(entries[1].id).unwrap(), None);
+    }
+
+    #[test]
+    fn entries_survive_reopening() {
+        let dir = TempDir::new();
+        add(&open(&dir), "kept");
+        let store = open(&dir);
+        assert_eq!(texts(&store.list(10, 0).unwrap()), vec!["kept"]);
+        assert_eq!(texts(&store.search("kep", 10).unwrap()), vec!["kept"]);
+    }
+
+    #[test]
+    fn search_matches_every_word_as_a_prefix() {
+        let dir = TempDir::new();
+        let store = open(&dir);
+        add(&store, "Patient reports mild chest pain");
+        add(&store, "No chest pain today");
+        add(&store, "Follow up in two weeks");
+
+        assert_eq!(store.search("chest", 10).unwrap().len(), 2);
+        assert_eq!(
+            texts(&store.search("pat chest", 10).unwrap()),
+            vec!["Patient reports mild chest pain"]
+        );
+        assert!(store.search("chest weeks", 10).unwrap().is_empty());
+        // An empty query lists everything
+        assert_eq!(store.search("  ", 10).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn query_syntax_in_user_input_is_searched_literally() {
+        assert_eq!(to_fts_query("a \"b"), "\"a\"* \"\"\"b\"*");
+
+        let dir = TempDir::new();
+        let store = open(&dir);
+        add(&store, "blood pressure NEAR normal");
+        add(&store, "say \"stop\" twice");
+        add(&store, "x-ray ordered");
+
+        // Each of these is an FTS5 syntax error if passed through unquoted
+        for query in [
+            "\"stop",
+            "stop\"",
+            "*",
+            "-",
+            "NEAR(",
+            "AND",
+            "x-ray",
+            "pressure OR",
+        ] {
+            assert!(store.search(query, 10).is_ok(), "{}", query);
+        }
+        assert_eq!(
+            texts(&store.search("NEAR normal", 10).unwrap()),
+            vec!["blood pressure NEAR normal"]
+        );
+        assert_eq!(
+            texts(&store.search("x-ray", 10).unwrap()),
+            vec!["x-ray ordered"]
+        );
+        assert_eq!(
+            texts(&store.search("\"stop\"", 10).unwrap()),
+            vec!["say \"stop\" twice"]
+        );
+    }
+
+    #[test]
+    fn pinned_entries_come_first() {
+        let dir = TempDir::new();
+        let store = open(&dir);
+        let old = add(&store, "old note");
+        add(&store, "new note");
+        assert!(store.set_pinned(old, true).unwrap());
+
+        let entries = store.list(10, 0).unwrap();
+        assert_eq!(texts(&entries), vec!["old note", "new note"]);
+        assert!(entries[0].pinned);
+        assert_eq!(
+            texts(&store.search("note", 10).unwrap()),
+            vec!["old note", "new note"]
+        );
+
+        assert!(store.set_pinned(old, false).unwrap());
+        assert_eq!(
+            texts(&store.list(10, 0).unwrap()),
+            vec!["new note", "old note"]
+        );
+        assert!(!store.set_pinned(old + 100, true).unwrap());
+    }
+
+    #[test]
+    fn deleting_and_editing_keep_the_search_index_in_sync() {
+        let dir = TempDir::new();
+        let store = open(&dir);
+        let deleted = add(&store, "discharge summary");
+        let edited = add(&store, "admission notes");
+
+        assert!(store.delete(deleted).unwrap());
+        assert!(!store.delete(deleted).unwrap());
+        assert!(store.search("discharge", 10).unwrap().is_empty());
+
+        store
+            .conn
+            .lock()
+            .unwrap()
+            .execute(
+                "UPDATE history SET text = 'transfer notes' WHERE id = ?1",
+                params![edited],
+            )
+            .unwrap();
+        assert!(store.search("admission", 10).unwrap().is_empty());
+        assert_eq!(
+            texts(&store.search("transfer", 10).unwrap()),
+            vec!["transfer notes"]
+        );
+        assert_eq!(store.search("notes", 10).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn paging_visits_every_entry_once() {
+        let dir = TempDir::new();
+        let store = open(&dir);
+        let pinned = add(&store, "entry 0");
+        for i in 1..7 {
+            add(&store, &format!("entry {}", i));
+        }
+        store.set_pinned(pinned, true).unwrap();
+
+        // The same loop as the CLI export
+        let mut entries = Vec::new();
+        loop {
+            let page = store.list(3, entries.len() as u32).unwrap();
+            let done = page.len() < 3;
+            entries.extend(page);
+            if done {
+                break;
+            }
+        }
+        assert_eq!(
+            texts(&entries),
+            vec!["entry 0", "entry 6", "entry 5", "entry 4", "entry 3", "entry 2", "entry 1"]
+        );
+    }
+}
@@ -0,0 +1,111 @@
+//insertion.rs
+// How transcribed text gets into the focused application. Typing and the
+// clipboard are only available in the desktop app.
+#[cfg(feature = "gui")]
+use anyhow::Result;
+#[cfg(feature = "gui")]
+use enigo::{Enigo, Key, KeyboardControllable};
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "gui")]
+use std::thread;
+#[cfg(feature = "gui")]
+use std::time::Duration;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum InsertionMode {
+    // Simulate a key press for every character
+    #[default]
+    Type,
+    // Put the text on the clipboard, press the paste shortcut, then put the
+    // previous clipboard contents back
+    ClipboardPaste,
+    // Only copy the text; the user pastes it themselves
+    ClipboardOnly,
+}
+
+impl InsertionMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            InsertionMode::Type => "type",
+            InsertionMode::ClipboardPaste => "clipboard_paste",
+            InsertionMode::ClipboardOnly => "clipboard_only",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "type" => Some(InsertionMode::Type),
+            "clipboard_paste" => Some(InsertionMode::ClipboardPaste),
+            "clipboard_only" => Some(InsertionMode::ClipboardOnly),
+            _ => None,
+        }
+    }
+
+    // Whether this mode sends keystrokes to the focused application
+    pub fn uses_keyboard(&self) -> bool {
+        !matches!(self, InsertionMode::ClipboardOnly)
+    }
+}
+
+#[cfg(feature = "gui")]
+pub fn insert_text(text: &str, mode: InsertionMode) -> Result<()> {
+    match mode {
+        InsertionMode::Type => {
+            type_text(text);
+            Ok(())
+        }
+        InsertionMode::ClipboardPaste => paste_text(text),
+        InsertionMode::ClipboardOnly => {
+            arboard::Clipboard::new()?.set_text(text)?;
+            Ok(())
+        }
+    }
+}
+
+#[cfg(feature = "gui")]
+fn type_text(text: &str) {
+    // Create a new Enigo instance for keyboard control
+    let mut enigo = Enigo::new();
+
+    // Type the text character by character with a small delay between each
+    for character in text.chars() {
+        // Convert each character to a string before typing
+        enigo.key_sequence(&character.to_string());
+
+        // Small delay to prevent overwhelming the target application
+        // Different systems might need different delays
+        thread::sleep(Duration::from_millis(0));
+    }
+}
+
+#[cfg(feature = "gui")]
+fn paste_text(text: &str) -> Result<()> {
+    let mut clipboard = arboard::Clipboard::new()?;
+
+    // Only text is preserved; images or files on the clipboard are lost
+    let previous = clipboard.get_text().ok();
+
+    clipboard.set_text(text)?;
+    // Give the clipboard a moment to settle before the target app reads it
+    thread::sleep(Duration::from_millis(50));
+
+    let mut enigo = Enigo::new();
+    #[cfg(target_os = "macos")]
+    let modifier = Key::Meta;
+    #[cfg(not(target_os = "macos"))]
+    let modifier = Key::Control;
+    enigo.key_down(modifier);
+    enigo.key_click(Key::Layout('v'));
+    enigo.key_up(modifier);
+
+    // The paste is handled asynchronously by the target app, so wait before
+    // restoring or it may paste the old contents instead
+    thread::sleep(Duration::from_millis(250));
+
+    match previous {
+        Some(previous) => clipboard.set_text(previous)?,
+        None => clipboard.clear()?,
+    }
+    Ok(())
+}
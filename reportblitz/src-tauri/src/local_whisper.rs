@@ -0,0 +1,160 @@
+//local_whisper.rs
+// Offline transcription with whisper.cpp. Models are ggml files (for example
+// ggml-base.en.bin) placed in the "models" folder of the app data directory.
+use anyhow::{anyhow, Result};
+use std::path::{Path, PathBuf};
+
+#[cfg(feature = "local-whisper")]
+use crate::transcription::TranscriptionOptions;
+#[cfg(feature = "local-whisper")]
+use once_cell::sync::Lazy;
+#[cfg(feature = "local-whisper")]
+use std::sync::{Arc, Mutex};
+#[cfg(feature = "local-whisper")]
+use whisper_rs::{FullParams, SamplingStrategy, WhisperContext, WhisperContextParameters};
+
+// whisper.cpp only accepts 16 kHz mono audio
+#[cfg(feature = "local-whisper")]
+use crate::audio::TARGET_SAMPLE_RATE;
+
+pub fn models_dir(app_data_dir: &Path) -> PathBuf {
+    app_data_dir.join("models")
+}
+
+// File names of the ggml models available in the models directory
+pub fn list_models(models_dir: &Path) -> Vec<String> {
+    let mut models: Vec<String> = match std::fs::read_dir(models_dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.file_name().to_string_lossy().to_string())
+            .filter(|name| name.starts_with("ggml-") && name.ends_with(".bin"))
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    models.sort();
+    models
+}
+
+// Pick the requested model, or the first one available when none is selected
+pub fn resolve_model(models_dir: &Path, requested: &str) -> Result<PathBuf> {
+    let requested = requested.trim();
+    if !requested.is_empty() {
+        let path = models_dir.join(requested);
+        if path.is_file() {
+            return Ok(path);
+        }
+        return Err(anyhow!(
+            "Local model {} not found in {}",
+            requested,
+            models_dir.display()
+        ));
+    }
+
+    match list_models(models_dir).first() {
+        Some(name) => Ok(models_dir.join(name)),
+        None => Err(anyhow!(
+            "No local Whisper model found. Download a ggml model (e.g. ggml-base.en.bin) into {}",
+            models_dir.display()
+        )),
+    }
+}
+
+// The last loaded model, kept so repeated dictations don't reload it from disk
+#[cfg(feature = "local-whisper")]
+static LOADED_MODEL: Lazy<Mutex<Option<(PathBuf, Arc<WhisperContext>)>>> =
+    Lazy::new(|| Mutex::new(None));
+
+#[cfg(feature = "local-whisper")]
+fn load_model(path: &Path) -> Result<Arc<WhisperContext>> {
+    let mut loaded = LOADED_MODEL.lock().unwrap();
+    if let Some((loaded_path, context)) = &*loaded {
+        if loaded_path == path {
+            return Ok(context.clone());
+        }
+    }
+
+    eprintln!("Loading local Whisper model from {:?}", path);
+    let path_str = path
+        .to_str()
+        .ok_or_else(|| anyhow!("Model path is not valid UTF-8: {:?}", path))?;
+    let context = Arc::new(WhisperContext::new_with_params(
+        path_str,
+        WhisperContextParameters::default(),
+    )?);
+    *loaded = Some((path.to_path_buf(), context.clone()));
+    Ok(context)
+}
+
+// Decode a WAV file into mono f32 samples at 16 kHz
+#[cfg(feature = "local-whisper")]
+fn decode_wav(wav_data: &[u8]) -> Result<Vec<f32>> {
+    let reader = hound::WavReader::new(std::io::Cursor::new(wav_data))?;
+    let spec = reader.spec();
+
+    let samples: Vec<f32> = match spec.sample_format {
+        hound::SampleFormat::Int => {
+            let scale = (1i64 << (spec.bits_per_sample - 1)) as f32;
+            reader
+                .into_samples::<i32>()
+                .map(|s| s.map(|v| v as f32 / scale))
+                .collect::<Result<_, _>>()?
+        }
+        hound::SampleFormat::Float => reader.into_samples::<f32>().collect::<Result<_, _>>()?,
+    };
+
+    let channels = spec.channels.max(1) as usize;
+    let mono: Vec<f32> = if channels > 1 {
+        samples
+            .chunks(channels)
+            .map(|frame| frame.iter().sum::<f32>() / frame.len() as f32)
+            .collect()
+    } else {
+        samples
+    };
+
+    crate::audio::resample(&mono, spec.sample_rate, TARGET_SAMPLE_RATE)
+}
+
+// Transcribe a WAV file entirely on this machine. This is CPU bound and
+// blocks, so callers should run it off the async runtime.
+#[cfg(feature = "local-whisper")]
+pub fn transcribe(
+    model_path: &Path,
+    options: &TranscriptionOptions,
+    wav_data: &[u8],
+) -> Result<String> {
+    let context = load_model(model_path)?;
+    let audio = decode_wav(wav_data)?;
+    if audio.is_empty() {
+        return Ok(String::new());
+    }
+
+    let mut state = context.create_state()?;
+
+    let mut params = FullParams::new(SamplingStrategy::Greedy { best_of: 1 });
+    params.set_language(options.language.as_deref());
+    if let Some(prompt) = &options.prompt {
+        params.set_initial_prompt(prompt);
+    }
+    if let Some(temperature) = options.temperature {
+        params.set_temperature(temperature);
+    }
+    let threads = std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(4)
+        .min(8);
+    params.set_n_threads(threads as i32);
+    params.set_print_special(false);
+    params.set_print_progress(false);
+    params.set_print_realtime(false);
+    params.set_print_timestamps(false);
+
+    state.full(params, &audio)?;
+
+    let mut text = String::new();
+    for segment in 0..state.full_n_segments()? {
+        text.push_str(&state.full_get_segment_text(segment)?);
+    }
+
+    Ok(text.trim().to_string())
+}
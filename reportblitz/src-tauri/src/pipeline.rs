@@ -0,0 +1,565 @@
+//pipeline.rs
+// Capture, provider setup and transcription without any Tauri dependency, so
+// the desktop app and the command line tool run the same pipeline. Progress
+// messages go to stderr; the command line tool prints transcripts on stdout.
+use crate::audio::{self, StreamResampler, TARGET_SAMPLE_RATE};
+use crate::chunking::{self, ChunkerConfig, SilenceChunker};
+use crate::credentials::{self, CredentialStore};
+use crate::devices::{self, SelectedDevice};
+use crate::dictionary::{Dictionary, DictionaryStore};
+use crate::local_whisper;
+use crate::postprocess::{ChatProvider, PostProcessSettings, PostProcessor};
+use crate::retry::{self, RetryPolicy};
+use crate::settings::{self, Settings, TranscriptionSettings};
+use crate::snippets::SnippetExpander;
+use crate::templates::{self, FieldExtraction, Template, TemplateStore};
+use crate::transcription::{
+    LocalProvider, OpenAiCompatibleProvider, OpenAiProvider, ProviderKind, TranscriptionError,
+    TranscriptionProvider,
+};
+use crate::vad::{self, VadConfig};
+use crate::voice_commands::VoiceCommandParser;
+use anyhow::{anyhow, Result};
+use cpal::traits::{DeviceTrait, StreamTrait};
+use cpal::SampleFormat;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::env;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// Must match the identifier in tauri.conf.json, which names the app data directory
+pub const APP_IDENTIFIER: &str = "com.reportblitz.app";
+
+// The directory Tauri uses as the app data dir, e.g.
+// ~/.local/share/com.reportblitz.app on Linux
+pub fn app_data_dir() -> Option<PathBuf> {
+    directories::BaseDirs::new().map(|dirs| dirs.data_dir().join(APP_IDENTIFIER))
+}
+
+// Read the settings the app saved in settings.dat, without upgrading the file
+pub fn load_settings_file(path: &Path) -> Result<Settings> {
+    if !path.exists() {
+        return Ok(Settings::default());
+    }
+    let values: HashMap<String, Value> = serde_json::from_slice(&std::fs::read(path)?)?;
+    let (settings, _) = settings::load(|key| values.get(key).cloned());
+    Ok(settings)
+}
+
+// Path of the optional .env file in the user's config directory, e.g.
+// ~/.config/reportblitz/.env on Linux. Debug builds also read src-tauri/.env.
+pub fn env_file_path() -> Option<PathBuf> {
+    #[cfg(debug_assertions)]
+    {
+        let dev_path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(".env");
+        if dev_path.exists() {
+            return Some(dev_path);
+        }
+    }
+
+    let dirs = directories::ProjectDirs::from("com", "reportblitz", "ReportBlitz")?;
+    Some(dirs.config_dir().join(".env"))
+}
+
+// Read a non-empty `NAME=value` entry from a .env file
+pub fn read_env_file_key(path: &Path, name: &str) -> Option<String> {
+    let file = File::open(path).ok()?;
+    let prefix = format!("{}=", name);
+    BufReader::new(file)
+        .lines()
+        .map_while(|line| line.ok())
+        .find_map(|line| {
+            let value = line.trim().strip_prefix(&prefix)?.trim().trim_matches('"');
+            (!value.is_empty()).then(|| value.to_string())
+        })
+}
+
+// Find a profile's API key: the credential store first, then the
+// environment, then the .env file. Empty if there is none.
+pub fn resolve_api_key(credentials: Option<&dyn CredentialStore>, profile: &str) -> String {
+    // A key saved from the settings takes precedence
+    if let Some(credentials) = credentials {
+        match credentials.get(&credentials::api_key_name(profile)) {
+            Ok(Some(key)) if !key.is_empty() => {
+                eprintln!(
+                    "API key for profile {} loaded from {}",
+                    profile,
+                    credentials.name()
+                );
+                return key;
+            }
+            Ok(_) => (),
+            Err(e) => eprintln!("Error reading {}: {}", credentials.name(), e),
+        }
+    }
+
+    // If not saved, check environment variable
+    let env_var = credentials::api_key_env_var(profile);
+    if let Ok(key) = env::var(&env_var) {
+        if !key.is_empty() {
+            eprintln!("API key loaded from environment variable {}", env_var);
+            return key;
+        }
+    }
+
+    // Final fallback: check the .env file in the user's config directory
+    if let Some(env_path) = env_file_path() {
+        if let Some(key) = read_env_file_key(&env_path, &env_var) {
+            eprintln!("API key loaded from {:?}", env_path);
+            return key;
+        }
+    }
+
+    eprintln!("No API key found for profile {}", profile);
+    String::new()
+}
+
+// Build a transcription provider. `api_key` is only called for providers
+// that need one, with the credential profile to read the key from, or None
+// for the active profile.
+pub fn build_provider(
+    settings: TranscriptionSettings,
+    api_key: impl FnOnce(Option<&str>) -> Result<String>,
+    models_dir: &Path,
+) -> Result<Arc<dyn TranscriptionProvider>> {
+    let provider: Arc<dyn TranscriptionProvider> = match settings.provider {
+        ProviderKind::OpenAi => Arc::new(OpenAiProvider::new(
+            api_key(None)?,
+            settings.options,
+            settings.upload_formats.open_ai,
+        )?),
+        ProviderKind::OpenAiCompatible => Arc::new(OpenAiCompatibleProvider::new(
+            settings.base_url,
+            compatible_server_key(settings.api_key_profile.as_deref(), api_key)?,
+            settings.options,
+            settings.upload_formats.open_ai_compatible,
+        )?),
+        ProviderKind::Local => {
+            let model_path = local_whisper::resolve_model(models_dir, &settings.local_model)?;
+            Arc::new(LocalProvider::new(model_path, settings.options))
+        }
+    };
+
+    eprintln!("Using transcription provider: {}", provider.name());
+    Ok(provider)
+}
+
+// Build the post-processing client. Like for transcription, a compatible
+// server only gets the key of the profile chosen for it.
+pub fn build_post_processor(
+    settings: PostProcessSettings,
+    api_key: impl FnOnce(Option<&str>) -> Result<String>,
+) -> Result<PostProcessor> {
+    let key = match settings.provider {
+        ChatProvider::OpenAi => Some(api_key(None)?),
+        ChatProvider::OpenAiCompatible => {
+            compatible_server_key(settings.api_key_profile.as_deref(), api_key)?
+        }
+    };
+    let processor = PostProcessor::new(settings, key)?;
+    eprintln!("Post-processing with model: {}", processor.model());
+    Ok(processor)
+}
+
+// The key for a server other than OpenAI's: only one set up for it, never
+// the active profile's by default
+pub fn compatible_server_key(
+    profile: Option<&str>,
+    api_key: impl FnOnce(Option<&str>) -> Result<String>,
+) -> Result<Option<String>> {
+    match profile {
+        Some(profile) => Ok(Some(api_key(Some(profile))?).filter(|key| !key.is_empty())),
+        None => Ok(None),
+    }
+}
+
+// Transcribe a complete recording the way live ones are: cut at silences,
+// drop chunks without speech and send the rest one after another. Setting
+// `cancelled` stops before the next chunk and ends any retries.
+pub async fn transcribe_samples(
+    provider: &dyn TranscriptionProvider,
+    samples: &[f32],
+    vad_config: &VadConfig,
+    cancelled: &AtomicBool,
+) -> Result<String, TranscriptionError> {
+    let mut chunker = SilenceChunker::new(ChunkerConfig::default(), TARGET_SAMPLE_RATE);
+    let mut chunks = chunker.push(samples);
+    chunks.extend(chunker.finish());
+
+    let mut parts = Vec::with_capacity(chunks.len());
+    for chunk in chunks {
+        if cancelled.load(Ordering::SeqCst) {
+            return Err(TranscriptionError::Other {
+                message: "Transcription was cancelled".to_string(),
+            });
+        }
+        let chunk = if vad_config.enabled {
+            match vad::trim_silence(&chunk, TARGET_SAMPLE_RATE, vad_config) {
+                Some(trimmed) => trimmed,
+                None => continue,
+            }
+        } else {
+            chunk
+        };
+        let text = retry::transcribe_chunk(
+            provider,
+            &chunk,
+            &RetryPolicy::default(),
+            cancelled,
+            |_, _, _| {},
+        )
+        .await?;
+        parts.push(text);
+    }
+    Ok(chunking::stitch(&parts))
+}
+
+// What the dictation steps report along the way, so the app can raise events
+// and the command line tool can print them
+pub enum DictationEvent<'a> {
+    PostProcessingStarted,
+    PostProcessingFailed(&'a anyhow::Error),
+    TemplateApplied {
+        name: &'a str,
+        missing: &'a [String],
+    },
+    TemplateFailed {
+        name: &'a str,
+        error: &'a anyhow::Error,
+    },
+}
+
+// The report template in use and, for model extraction, the client that
+// picks out its fields
+struct ActiveTemplate {
+    template: Template,
+    extractor: Option<PostProcessor>,
+}
+
+// Everything between a transcript and the finished text: dictionary, spoken
+// commands, post-processing, the report template and snippets, set up from
+// the settings and stores. Steps that can't be set up are skipped: a broken
+// dictionary or snippet list with a message, post-processing and templates by
+// reporting the failure with each dictation, which keeps the text as it was.
+pub struct Dictation {
+    dictionary: Option<Dictionary>,
+    voice_commands: Option<VoiceCommandParser>,
+    snippets: Option<SnippetExpander>,
+    post_processor: Option<Result<PostProcessor>>,
+    template: Option<(String, Result<ActiveTemplate>)>,
+    extraction: FieldExtraction,
+    // Steps that were skipped, for `check`
+    problems: Vec<String>,
+}
+
+impl Dictation {
+    // `template` overrides the active template of the settings
+    pub fn new(
+        settings: &Settings,
+        dictionary: Option<&DictionaryStore>,
+        templates_dir: &Path,
+        template: Option<&str>,
+        api_key: impl Fn(Option<&str>) -> Result<String>,
+    ) -> Self {
+        let mut problems = Vec::new();
+        let mut skipped = |step: &str, e: anyhow::Error| {
+            eprintln!("{} not applied: {}", step, e);
+            problems.push(format!("{}: {}", step, e));
+        };
+
+        let dictionary = match dictionary.filter(|_| settings.dictionary.enabled) {
+            Some(store) => match store.dictionary() {
+                Ok(dictionary) => Some(dictionary),
+                Err(e) => {
+                    skipped("Replacement dictionary", e);
+                    None
+                }
+            },
+            None => None,
+        };
+        let voice_commands = settings.voice_commands.enabled.then(|| {
+            VoiceCommandParser::from_settings(
+                &settings.voice_commands,
+                settings.transcription.options.language.as_deref(),
+            )
+        });
+        let snippets = &settings.snippets;
+        let snippets = if snippets.enabled && !snippets.snippets.is_empty() {
+            match SnippetExpander::new(&snippets.snippets) {
+                Ok(expander) => Some(expander),
+                Err(e) => {
+                    skipped("Snippets", e);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        let post_processor = settings
+            .post_processing
+            .enabled
+            .then(|| build_post_processor(settings.post_processing.clone(), &api_key));
+        let extraction = settings.templates.extraction;
+        let template = template
+            .or(settings.templates.active.as_deref())
+            .map(|name| {
+                let active = TemplateStore::open(templates_dir)
+                    .and_then(|store| store.get(name))
+                    .and_then(|template| {
+                        template.ok_or_else(|| {
+                            anyhow!("The report template \"{}\" doesn't exist", name)
+                        })
+                    })
+                    .and_then(|template| {
+                        // Model extraction uses the post-processing model even
+                        // if rewriting is turned off
+                        let extractor = match extraction {
+                            FieldExtraction::SpokenLabels => None,
+                            FieldExtraction::Model => Some(build_post_processor(
+                                settings.post_processing.clone(),
+                                &api_key,
+                            )?),
+                        };
+                        Ok(ActiveTemplate {
+                            template,
+                            extractor,
+                        })
+                    });
+                (name.to_string(), active)
+            });
+
+        Self {
+            dictionary,
+            voice_commands,
+            snippets,
+            post_processor,
+            template,
+            extraction,
+            problems,
+        }
+    }
+
+    // Fail if any step couldn't be set up, for callers that would rather stop
+    // than skip it
+    pub fn check(&self) -> Result<()> {
+        let mut problems = self.problems.clone();
+        if let Some(Err(e)) = &self.post_processor {
+            problems.push(format!("Post-processing: {}", e));
+        }
+        if let Some((name, Err(e))) = &self.template {
+            problems.push(format!("Report template {}: {}", name, e));
+        }
+        match problems.is_empty() {
+            true => Ok(()),
+            false => Err(anyhow!(problems.join("; "))),
+        }
+    }
+
+    // Turn a transcript into the finished text. A dictation that was only a
+    // snippet trigger comes out as the snippet stands. Snippets in a longer
+    // dictation are expanded last, so neither the model nor the template
+    // fields see their text.
+    pub async fn finish(&self, text: String, on_event: impl Fn(DictationEvent)) -> String {
+        let mut text = text;
+        if let Some(dictionary) = &self.dictionary {
+            text = dictionary.apply(&text);
+        }
+        if let Some(parser) = &self.voice_commands {
+            text = parser.apply(&text);
+        }
+        if let Some(snippet) = self.snippets.as_ref().and_then(|s| s.whole(&text)) {
+            return snippet.to_string();
+        }
+        if text.is_empty() {
+            return text;
+        }
+
+        // Failed steps keep the text they were given, so nothing that was
+        // said gets lost
+        if let Some(processor) = &self.post_processor {
+            on_event(DictationEvent::PostProcessingStarted);
+            match processor {
+                Ok(processor) => match processor.process(&text).await {
+                    Ok(processed) => text = processed,
+                    Err(e) => on_event(DictationEvent::PostProcessingFailed(&e)),
+                },
+                Err(e) => on_event(DictationEvent::PostProcessingFailed(e)),
+            }
+        }
+        if let Some((name, active)) = &self.template {
+            let failed = |error| on_event(DictationEvent::TemplateFailed { name, error });
+            match active {
+                Ok(active) => {
+                    let extractor = active.extractor.as_ref();
+                    match templates::fill(&active.template, &text, self.extraction, extractor).await
+                    {
+                        Ok(report) => {
+                            on_event(DictationEvent::TemplateApplied {
+                                name,
+                                missing: &report.missing,
+                            });
+                            text = report.text;
+                        }
+                        Err(e) => failed(&e),
+                    }
+                }
+                Err(e) => failed(e),
+            }
+        }
+        match &self.snippets {
+            Some(snippets) => snippets.expand(&text),
+            None => text,
+        }
+    }
+}
+
+// Audio being captured from an input device. Samples are downmixed to mono in
+// the device callback and collected at the device's rate until taken.
+pub struct Capture {
+    // Capture stops when this is dropped
+    stream: cpal::Stream,
+    samples: Arc<Mutex<Vec<f32>>>,
+    pub sample_rate: u32,
+}
+
+impl Capture {
+    // Start capturing. Samples are only kept while `active` is set; `on_error`
+    // is called from the audio thread if the stream fails.
+    pub fn start(
+        device: &cpal::Device,
+        active: Arc<AtomicBool>,
+        on_error: impl FnMut(cpal::StreamError) + Send + 'static,
+    ) -> Result<Self> {
+        // Capture in whatever format the device offers; samples are resampled
+        // to 16 kHz by whoever takes them
+        let supported_config = devices::pick_input_config(device)?;
+        eprintln!("Selected input config: {:?}", supported_config);
+
+        let config = supported_config.config();
+        let sample_rate = config.sample_rate.0;
+        let samples = Arc::new(Mutex::new(Vec::new()));
+
+        let stream = match supported_config.sample_format() {
+            SampleFormat::I8 => build_stream::<i8>(device, &config, &samples, active, on_error)?,
+            SampleFormat::I16 => build_stream::<i16>(device, &config, &samples, active, on_error)?,
+            SampleFormat::I32 => build_stream::<i32>(device, &config, &samples, active, on_error)?,
+            SampleFormat::I64 => build_stream::<i64>(device, &config, &samples, active, on_error)?,
+            SampleFormat::U8 => build_stream::<u8>(device, &config, &samples, active, on_error)?,
+            SampleFormat::U16 => build_stream::<u16>(device, &config, &samples, active, on_error)?,
+            SampleFormat::U32 => build_stream::<u32>(device, &config, &samples, active, on_error)?,
+            SampleFormat::U64 => build_stream::<u64>(device, &config, &samples, active, on_error)?,
+            SampleFormat::F32 => build_stream::<f32>(device, &config, &samples, active, on_error)?,
+            SampleFormat::F64 => build_stream::<f64>(device, &config, &samples, active, on_error)?,
+            // pick_input_config only picks the formats above
+            format => return Err(anyhow!("Unsupported sample format {:?}", format)),
+        };
+
+        eprintln!("Audio stream built successfully, playing...");
+        stream.play()?;
+        Ok(Self {
+            stream,
+            samples,
+            sample_rate,
+        })
+    }
+
+    // Everything captured since the last call
+    pub fn take(&self) -> Vec<f32> {
+        std::mem::take(&mut *self.samples.lock().unwrap())
+    }
+
+    pub fn stop(self) {
+        let _ = self.stream.pause();
+    }
+}
+
+// Signed, unsigned and float samples are all converted to f32 around zero
+fn build_stream<T>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    samples: &Arc<Mutex<Vec<f32>>>,
+    active: Arc<AtomicBool>,
+    on_error: impl FnMut(cpal::StreamError) + Send + 'static,
+) -> Result<cpal::Stream>
+where
+    T: cpal::SizedSample,
+    f32: cpal::FromSample<T>,
+{
+    let channels = config.channels as usize;
+    // Collect a tenth of a second before locking, to reduce mutex contention
+    let batch_size = config.sample_rate.0 as usize / 10;
+    let samples = samples.clone();
+    let mut batch: Vec<f32> = Vec::with_capacity(batch_size);
+
+    // Only log once every 2 seconds
+    let log_interval = Duration::from_secs(2);
+    let mut last_log = Instant::now();
+
+    let callback = move |data: &[T], _: &_| {
+        if !active.load(Ordering::SeqCst) || data.is_empty() {
+            return;
+        }
+        audio::push_downmixed(&mut batch, data, channels, |s: T| s.to_sample::<f32>());
+
+        // Only lock the mutex and push when we have a full batch
+        if batch.len() >= batch_size {
+            if last_log.elapsed() >= log_interval {
+                eprintln!("Audio batch collected: {} samples", batch.len());
+                last_log = Instant::now();
+            }
+            samples.lock().unwrap().append(&mut batch);
+        }
+    };
+    Ok(device.build_input_stream(config, callback, on_error, None)?)
+}
+
+// Record from the named input device, or the default one, for `duration` or
+// until `stop` is set. Returns 16 kHz mono samples.
+pub fn record(
+    device_name: Option<&str>,
+    duration: Duration,
+    stop: &AtomicBool,
+) -> Result<Vec<f32>> {
+    let device = match devices::select_input_device(device_name) {
+        Some(SelectedDevice::Preferred(device)) | Some(SelectedDevice::Default(device)) => device,
+        Some(SelectedDevice::Fallback(_)) => {
+            return Err(anyhow!(
+                "No input device named \"{}\"",
+                device_name.unwrap_or_default()
+            ))
+        }
+        None => return Err(anyhow!("No microphone found")),
+    };
+    eprintln!("Using input device: {}", device.name().unwrap_or_default());
+
+    let active = Arc::new(AtomicBool::new(true));
+    let failed = Arc::new(Mutex::new(None));
+    let stream_error = failed.clone();
+    let capture = Capture::start(&device, active.clone(), move |err| {
+        eprintln!("Stream error: {:?}", err);
+        *stream_error.lock().unwrap() = Some(err.to_string());
+    })?;
+
+    let mut resampler = StreamResampler::new(capture.sample_rate, TARGET_SAMPLE_RATE)?;
+    let mut recorded = Vec::new();
+    let started = Instant::now();
+    while started.elapsed() < duration && !stop.load(Ordering::SeqCst) {
+        std::thread::sleep(Duration::from_millis(100));
+        if let Some(error) = failed.lock().unwrap().take() {
+            return Err(anyhow!("Microphone error: {}", error));
+        }
+        recorded.extend(resampler.push(&capture.take())?);
+    }
+    active.store(false, Ordering::SeqCst);
+
+    recorded.extend(resampler.push(&capture.take())?);
+    recorded.extend(resampler.finish()?);
+    capture.stop();
+    Ok(recorded)
+}
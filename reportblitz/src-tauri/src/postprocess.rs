@@ -0,0 +1,243 @@
+//postprocess.rs
+// Optional clean-up of dictation before it is inserted. The raw transcript is
+// sent with an editable system prompt to a chat completions endpoint (OpenAI,
+// or a compatible server such as a local Ollama or llama.cpp) and comes back
+// as punctuated report prose without the filler words.
+use crate::transcription::{TranscriptionError, OPENAI_BASE_URL};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+pub const DEFAULT_CHAT_MODEL: &str = "gpt-4o-mini";
+
+pub const DEFAULT_SYSTEM_PROMPT: &str = "You turn dictated notes into text for a written report. \
+Remove filler words, false starts and repetitions, fix punctuation and capitalization, and break the text into paragraphs. \
+Keep every fact, name, time, number and quotation exactly as dictated and add nothing of your own. \
+Reply with the report text only.";
+
+// Rewriting a long dictation takes a while on slower models
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ChatProvider {
+    #[default]
+    OpenAi,
+    OpenAiCompatible,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct PostProcessSettings {
+    pub enabled: bool,
+    pub provider: ChatProvider,
+    // Required for a compatible server, e.g. http://localhost:11434/v1 for Ollama
+    pub base_url: String,
+    // Credential profile whose API key is sent to a compatible server; None
+    // sends no key
+    pub api_key_profile: Option<String>,
+    pub model: String,
+    // Blank restores DEFAULT_SYSTEM_PROMPT
+    pub system_prompt: String,
+    pub temperature: Option<f32>,
+}
+
+impl Default for PostProcessSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            provider: ChatProvider::default(),
+            base_url: String::new(),
+            api_key_profile: None,
+            model: DEFAULT_CHAT_MODEL.to_string(),
+            system_prompt: DEFAULT_SYSTEM_PROMPT.to_string(),
+            // Low, so the model edits rather than rewrites
+            temperature: Some(0.2),
+        }
+    }
+}
+
+impl PostProcessSettings {
+    // Trim the text fields and fill in blank ones with the defaults
+    pub fn normalized(mut self) -> Self {
+        self.base_url = self.base_url.trim().to_string();
+        self.api_key_profile = self
+            .api_key_profile
+            .map(|name| name.trim().to_string())
+            .filter(|name| !name.is_empty());
+        self.model = self.model.trim().to_string();
+        if self.model.is_empty() {
+            self.model = DEFAULT_CHAT_MODEL.to_string();
+        }
+        self.system_prompt = self.system_prompt.trim().to_string();
+        if self.system_prompt.is_empty() {
+            self.system_prompt = DEFAULT_SYSTEM_PROMPT.to_string();
+        }
+        self
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.provider == ChatProvider::OpenAiCompatible && self.base_url.is_empty() {
+            return Err(anyhow!(
+                "A base URL is required for an OpenAI-compatible post-processing server"
+            ));
+        }
+        if let Some(temperature) = self.temperature {
+            if !(0.0..=2.0).contains(&temperature) {
+                return Err(anyhow!(
+                    "Post-processing temperature must be between 0 and 2"
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+struct ChatCompletion {
+    choices: Vec<ChatChoice>,
+}
+
+#[derive(Deserialize)]
+struct ChatChoice {
+    message: ChatMessage,
+}
+
+#[derive(Deserialize)]
+struct ChatMessage {
+    content: Option<String>,
+}
+
+pub struct PostProcessor {
+    base_url: String,
+    api_key: Option<String>,
+    settings: PostProcessSettings,
+}
+
+impl PostProcessor {
+    // The API key is required for OpenAI and optional for a compatible server
+    pub fn new(settings: PostProcessSettings, api_key: Option<String>) -> Result<Self> {
+        let api_key = api_key.filter(|key| !key.is_empty());
+        let base_url = match settings.provider {
+            ChatProvider::OpenAi => {
+                if api_key.is_none() {
+                    return Err(anyhow!(
+                        "OpenAI API key not set. Please enter it in the settings."
+                    ));
+                }
+                OPENAI_BASE_URL.to_string()
+            }
+            ChatProvider::OpenAiCompatible => {
+                if settings.base_url.is_empty() {
+                    return Err(anyhow!(
+                        "No base URL configured for the OpenAI-compatible post-processing server"
+                    ));
+                }
+                settings.base_url.clone()
+            }
+        };
+        Ok(Self {
+            base_url,
+            api_key,
+            settings,
+        })
+    }
+
+    pub fn model(&self) -> &str {
+        &self.settings.model
+    }
+
+    // Rewrite a transcript. HTTP failures are returned as a TranscriptionError
+    // so callers can tell a bad key from a dropped connection.
+    pub async fn process(&self, text: &str) -> Result<String> {
+        let processed = self
+            .complete(&self.settings.system_prompt, text, false)
+            .await?;
+        if processed.is_empty() {
+            return Err(anyhow!("The post-processing model returned no text"));
+        }
+        Ok(processed)
+    }
+
+    // Pick the values of report template fields out of a dictation. Fields
+    // that weren't mentioned are left out.
+    pub async fn extract_fields(
+        &self,
+        text: &str,
+        fields: &[String],
+    ) -> Result<BTreeMap<String, String>> {
+        let system_prompt = format!(
+            "Extract these fields from the dictated report: {}. \
+Reply with a JSON object with exactly these keys and string values. \
+Use the speaker's wording, fixing only obvious transcription errors, and an empty string for anything not mentioned.",
+            fields.join(", ")
+        );
+        let reply = self.complete(&system_prompt, text, true).await?;
+        let object: BTreeMap<String, Value> = serde_json::from_str(&reply)
+            .map_err(|e| anyhow!("The model did not reply with a JSON object: {}", e))?;
+
+        Ok(object
+            .into_iter()
+            .filter(|(field, _)| fields.contains(field))
+            .filter_map(|(field, value)| {
+                let value = match value {
+                    Value::String(value) => value.trim().to_string(),
+                    Value::Null => String::new(),
+                    other => other.to_string(),
+                };
+                (!value.is_empty()).then_some((field, value))
+            })
+            .collect())
+    }
+
+    // Send one system and one user message and return the reply, trimmed.
+    // `json_reply` asks for a JSON object.
+    async fn complete(&self, system_prompt: &str, text: &str, json_reply: bool) -> Result<String> {
+        let mut body = json!({
+            "model": self.settings.model,
+            "messages": [
+                { "role": "system", "content": system_prompt },
+                { "role": "user", "content": text },
+            ],
+        });
+        if let Some(temperature) = self.settings.temperature {
+            body["temperature"] = json!(temperature);
+        }
+        if json_reply {
+            body["response_format"] = json!({ "type": "json_object" });
+        }
+
+        let url = format!("{}/chat/completions", self.base_url.trim_end_matches('/'));
+        let mut request = reqwest::Client::new()
+            .post(&url)
+            .timeout(REQUEST_TIMEOUT)
+            .json(&body);
+        if let Some(key) = &self.api_key {
+            request = request.header("Authorization", format!("Bearer {}", key));
+        }
+
+        let response = request
+            .send()
+            .await
+            .map_err(TranscriptionError::from_reqwest)?;
+        if !response.status().is_success() {
+            let status = response.status().as_u16();
+            let body = response.text().await.unwrap_or_default();
+            return Err(TranscriptionError::from_status(status, None, body).into());
+        }
+
+        let completion: ChatCompletion = response
+            .json()
+            .await
+            .map_err(TranscriptionError::from_reqwest)?;
+        Ok(completion
+            .choices
+            .into_iter()
+            .next()
+            .and_then(|choice| choice.message.content)
+            .map(|content| content.trim().to_string())
+            .unwrap_or_default())
+    }
+}
@@ -0,0 +1,477 @@
+//queue.rs
+// Recordings whose transcription failed for a transient reason, e.g. no
+// network. Each job is a directory in the app data dir holding the audio of
+// its untranscribed chunks and a job.json, so nothing is lost if the app quits
+// before the job is replayed.
+use crate::audio;
+use crate::chunking;
+use crate::retry::{self, RetryPolicy};
+use crate::transcription::{TranscriptionError, TranscriptionProvider};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const JOB_FILE: &str = "job.json";
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PendingJob {
+    pub id: String,
+    // Unix time in milliseconds
+    pub created_at: i64,
+    pub duration_secs: f64,
+    // Provider that failed; replays use whichever is configured by then
+    pub provider: String,
+    // Replays tried so far, not counting the original recording
+    pub attempts: u32,
+    pub last_error: TranscriptionError,
+    pub chunks: Vec<QueuedChunk>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum QueuedChunk {
+    // Transcribed before the job failed
+    Text { text: String },
+    // Still to transcribe; the WAV file is in the job directory
+    Audio { file: String },
+}
+
+// A chunk handed to `JobQueue::add`
+pub enum NewChunk {
+    Text(String),
+    Audio(Vec<u8>),
+}
+
+pub struct JobQueue {
+    dir: PathBuf,
+    // Serialises changes to job files
+    lock: Mutex<()>,
+    // Held from loading a job until it is delivered and removed, so the same
+    // job is never sent or delivered twice
+    replaying: tokio::sync::Mutex<()>,
+}
+
+impl JobQueue {
+    pub fn open(dir: &Path) -> Result<Self> {
+        fs::create_dir_all(dir)?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            lock: Mutex::new(()),
+            replaying: tokio::sync::Mutex::new(()),
+        })
+    }
+
+    pub fn add(
+        &self,
+        chunks: Vec<NewChunk>,
+        duration_secs: f64,
+        provider: &str,
+        error: TranscriptionError,
+    ) -> Result<PendingJob> {
+        let created_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as i64)
+            .unwrap_or_default();
+        let id = format!("{}-{}", created_at, uuid::Uuid::new_v4().simple());
+
+        // Build the job in a temporary directory and move it into place, so a
+        // half-written job is never listed
+        let _guard = self.lock.lock().unwrap();
+        let tmp_dir = self.dir.join(format!("{}.tmp", id));
+        fs::create_dir_all(&tmp_dir)?;
+
+        let mut queued = Vec::with_capacity(chunks.len());
+        for (index, chunk) in chunks.into_iter().enumerate() {
+            queued.push(match chunk {
+                NewChunk::Text(text) => QueuedChunk::Text { text },
+                NewChunk::Audio(wav_data) => {
+                    let file = format!("chunk-{}.wav", index);
+                    fs::write(tmp_dir.join(&file), wav_data)?;
+                    QueuedChunk::Audio { file }
+                }
+            });
+        }
+
+        let job = PendingJob {
+            id: id.clone(),
+            created_at,
+            duration_secs,
+            provider: provider.to_string(),
+            attempts: 0,
+            last_error: error,
+            chunks: queued,
+        };
+        fs::write(tmp_dir.join(JOB_FILE), serde_json::to_vec_pretty(&job)?)?;
+        fs::rename(&tmp_dir, self.dir.join(&id))?;
+        Ok(job)
+    }
+
+    // Oldest first
+    pub fn list(&self) -> Result<Vec<PendingJob>> {
+        let _guard = self.lock.lock().unwrap();
+        let mut jobs = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path().join(JOB_FILE);
+            if !path.exists() {
+                continue;
+            }
+            match read_job(&path) {
+                Ok(job) => jobs.push(job),
+                Err(e) => eprintln!("Skipping unreadable queued job {:?}: {}", path, e),
+            }
+        }
+        jobs.sort_by_key(|job| job.created_at);
+        Ok(jobs)
+    }
+
+    pub fn count(&self) -> usize {
+        self.list().map(|jobs| jobs.len()).unwrap_or_default()
+    }
+
+    pub fn get(&self, id: &str) -> Result<Option<PendingJob>> {
+        let path = self.job_dir(id)?.join(JOB_FILE);
+        let _guard = self.lock.lock().unwrap();
+        if !path.exists() {
+            return Ok(None);
+        }
+        Ok(Some(read_job(&path)?))
+    }
+
+    // Delete a job and its audio. Returns false if there was no such job.
+    pub fn remove(&self, id: &str) -> Result<bool> {
+        let dir = self.job_dir(id)?;
+        let _guard = self.lock.lock().unwrap();
+        if !dir.exists() {
+            return Ok(false);
+        }
+        fs::remove_dir_all(dir)?;
+        Ok(true)
+    }
+
+    // Transcribe the job's remaining chunks, hand the whole text to `deliver`
+    // and remove the job. Returns what `deliver` returned, or None if the job
+    // is no longer queued, e.g. because another replay just delivered it.
+    // Chunks that succeed are saved as text straight away, so a replay that
+    // fails half way doesn't send them again.
+    pub async fn replay<F, Fut>(
+        &self,
+        id: &str,
+        provider: &dyn TranscriptionProvider,
+        policy: &RetryPolicy,
+        deliver: F,
+    ) -> Result<Option<String>, TranscriptionError>
+    where
+        F: FnOnce(PendingJob, String) -> Fut,
+        Fut: Future<Output = String>,
+    {
+        let _replaying = self.replaying.lock().await;
+        let mut job = match self.get(id).map_err(other_error)? {
+            Some(job) => job,
+            None => return Ok(None),
+        };
+        let dir = self.job_dir(id).map_err(other_error)?;
+        // Replays run to completion; there is no recording to cancel
+        let cancelled = AtomicBool::new(false);
+
+        let mut parts = Vec::with_capacity(job.chunks.len());
+        for index in 0..job.chunks.len() {
+            let file = match &job.chunks[index] {
+                QueuedChunk::Text { text } => {
+                    parts.push(text.clone());
+                    continue;
+                }
+                QueuedChunk::Audio { file } => file.clone(),
+            };
+
+            // Re-encoded for whichever provider is configured now
+            let samples = fs::read(dir.join(&file))
+                .map_err(anyhow::Error::from)
+                .and_then(|wav_data| audio::decode(wav_data, Some("wav")))
+                .map_err(other_error)?;
+            match retry::transcribe_chunk(provider, &samples, policy, &cancelled, |_, _, _| {})
+                .await
+            {
+                Ok(text) => {
+                    job.chunks[index] = QueuedChunk::Text { text: text.clone() };
+                    self.save(&job).map_err(other_error)?;
+                    let _ = fs::remove_file(dir.join(&file));
+                    parts.push(text);
+                }
+                Err(error) => {
+                    job.attempts += 1;
+                    job.last_error = error.clone();
+                    self.save(&job).map_err(other_error)?;
+                    return Err(error);
+                }
+            }
+        }
+
+        let text = deliver(job, chunking::stitch(&parts)).await;
+        if let Err(e) = self.remove(id) {
+            eprintln!("Failed to remove queued job {}: {}", id, e);
+        }
+        Ok(Some(text))
+    }
+
+    fn save(&self, job: &PendingJob) -> Result<()> {
+        let dir = self.job_dir(&job.id)?;
+        let _guard = self.lock.lock().unwrap();
+        if !dir.exists() {
+            return Err(anyhow!("Queued job {} was discarded", job.id));
+        }
+        // Replace job.json in one step so a crash can't leave half a file
+        let tmp_path = dir.join("job.json.tmp");
+        fs::write(&tmp_path, serde_json::to_vec_pretty(job)?)?;
+        fs::rename(&tmp_path, dir.join(JOB_FILE))?;
+        Ok(())
+    }
+
+    // Ids come from the frontend, so make sure they can't point outside the queue
+    fn job_dir(&self, id: &str) -> Result<PathBuf> {
+        if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+            return Err(anyhow!("Invalid job id \"{}\"", id));
+        }
+        Ok(self.dir.join(id))
+    }
+}
+
+fn read_job(path: &Path) -> Result<PendingJob> {
+    Ok(serde_json::from_slice(&fs::read(path)?)?)
+}
+
+fn other_error(e: anyhow::Error) -> TranscriptionError {
+    TranscriptionError::Other {
+        message: e.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoding::EncodedAudio;
+    use crate::test_support::TempDir;
+    use async_trait::async_trait;
+    use std::time::Duration;
+
+    // Answers with the length of the audio it was sent, so the text shows
+    // which chunk it came from, and fails from the given call on
+    struct FakeProvider {
+        calls: Mutex<Vec<usize>>,
+        fail_from_call: usize,
+    }
+
+    impl FakeProvider {
+        fn new(fail_from_call: usize) -> Self {
+            Self {
+                calls: Mutex::new(Vec::new()),
+                fail_from_call,
+            }
+        }
+
+        fn calls(&self) -> Vec<usize> {
+            self.calls.lock().unwrap().clone()
+        }
+    }
+
+    #[async_trait]
+    impl TranscriptionProvider for FakeProvider {
+        fn name(&self) -> &'static str {
+            "Fake"
+        }
+
+        async fn transcribe(&self, audio: &EncodedAudio) -> Result<String> {
+            let len = audio::decode(audio.data.clone(), Some("wav"))?.len();
+            let mut calls = self.calls.lock().unwrap();
+            calls.push(len);
+            if calls.len() >= self.fail_from_call {
+                return Err(network_error().into());
+            }
+            Ok(format!("chunk of {}", len))
+        }
+    }
+
+    fn network_error() -> TranscriptionError {
+        TranscriptionError::Network {
+            message: "offline".to_string(),
+        }
+    }
+
+    fn wav(len: usize) -> NewChunk {
+        NewChunk::Audio(audio::encode_wav(&vec![0.1; len], audio::TARGET_SAMPLE_RATE).unwrap())
+    }
+
+    // Delivery that passes the text through unchanged
+    async fn keep(_: PendingJob, text: String) -> String {
+        text
+    }
+
+    fn no_retries() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 1,
+            base_delay: Duration::ZERO,
+            max_delay: Duration::ZERO,
+        }
+    }
+
+    #[test]
+    fn jobs_are_kept_on_disk_oldest_first() {
+        let dir = TempDir::new();
+        let queue = JobQueue::open(dir.path()).unwrap();
+        let first = queue
+            .add(vec![wav(1600)], 0.1, "OpenAI", network_error())
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(2));
+        let second = queue
+            .add(
+                vec![NewChunk::Text("done".to_string()), wav(800)],
+                2.0,
+                "Local",
+                network_error(),
+            )
+            .unwrap();
+
+        // A queue opened later, e.g. after a restart, sees the same jobs
+        let reopened = JobQueue::open(dir.path()).unwrap();
+        let jobs = reopened.list().unwrap();
+        assert_eq!(
+            jobs.iter().map(|job| job.id.clone()).collect::<Vec<_>>(),
+            vec![first.id.clone(), second.id.clone()]
+        );
+        assert_eq!(reopened.count(), 2);
+
+        let job = reopened.get(&second.id).unwrap().unwrap();
+        assert_eq!(job.provider, "Local");
+        assert_eq!(job.attempts, 0);
+        assert_eq!(job.last_error, network_error());
+        assert!(matches!(&job.chunks[0], QueuedChunk::Text { text } if text == "done"));
+        match &job.chunks[1] {
+            QueuedChunk::Audio { file } => assert!(dir.join(&second.id).join(file).exists()),
+            chunk => panic!("expected audio, got {:?}", chunk),
+        }
+
+        assert!(reopened.remove(&first.id).unwrap());
+        assert!(!reopened.remove(&first.id).unwrap());
+        assert_eq!(reopened.count(), 1);
+    }
+
+    #[test]
+    fn unfinished_and_invalid_jobs_are_not_listed() {
+        let dir = TempDir::new();
+        let queue = JobQueue::open(dir.path()).unwrap();
+        fs::create_dir_all(dir.join("123-abc.tmp")).unwrap();
+        fs::create_dir_all(dir.join("456-def")).unwrap();
+        fs::write(dir.join("456-def").join(JOB_FILE), "not json").unwrap();
+        assert!(queue.list().unwrap().is_empty());
+        assert!(queue.get("../outside").is_err());
+        assert!(queue.remove("").is_err());
+    }
+
+    #[tokio::test]
+    async fn replay_transcribes_the_chunks_in_order() {
+        let dir = TempDir::new();
+        let queue = JobQueue::open(dir.path()).unwrap();
+        let job = queue
+            .add(
+                vec![
+                    wav(300),
+                    NewChunk::Text("kept".to_string()),
+                    wav(100),
+                    wav(200),
+                ],
+                1.0,
+                "OpenAI",
+                network_error(),
+            )
+            .unwrap();
+
+        let provider = FakeProvider::new(usize::MAX);
+        let text = queue.replay(&job.id, &provider, &no_retries(), keep).await;
+        assert_eq!(
+            text.unwrap().as_deref(),
+            Some("chunk of 300 kept chunk of 100 chunk of 200")
+        );
+        assert_eq!(provider.calls(), vec![300, 100, 200]);
+        // Delivered, so it is gone
+        assert_eq!(queue.count(), 0);
+        let again = queue.replay(&job.id, &provider, &no_retries(), keep).await;
+        assert_eq!(again.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn concurrent_replays_deliver_a_job_once() {
+        let dir = TempDir::new();
+        let queue = JobQueue::open(dir.path()).unwrap();
+        let job = queue
+            .add(vec![wav(100), wav(200)], 1.0, "OpenAI", network_error())
+            .unwrap();
+
+        let provider = FakeProvider::new(usize::MAX);
+        let deliveries = Mutex::new(Vec::new());
+        let deliver = |_: PendingJob, text: String| async {
+            // Give the other replay a chance to start while this one delivers
+            tokio::task::yield_now().await;
+            deliveries.lock().unwrap().push(text.clone());
+            text
+        };
+        let policy = no_retries();
+        let (first, second) = tokio::join!(
+            queue.replay(&job.id, &provider, &policy, deliver),
+            queue.replay(&job.id, &provider, &policy, deliver),
+        );
+
+        let mut results = vec![first.unwrap(), second.unwrap()];
+        results.sort();
+        assert_eq!(
+            results,
+            vec![None, Some("chunk of 100 chunk of 200".to_string())]
+        );
+        assert_eq!(
+            *deliveries.lock().unwrap(),
+            vec!["chunk of 100 chunk of 200"]
+        );
+        assert_eq!(provider.calls(), vec![100, 200]);
+        assert_eq!(queue.count(), 0);
+    }
+
+    #[tokio::test]
+    async fn a_failed_replay_keeps_what_was_transcribed() {
+        let dir = TempDir::new();
+        let queue = JobQueue::open(dir.path()).unwrap();
+        let job = queue
+            .add(
+                vec![wav(100), wav(200), wav(300)],
+                1.0,
+                "OpenAI",
+                network_error(),
+            )
+            .unwrap();
+
+        // The second chunk fails
+        let provider = FakeProvider::new(2);
+        let error = queue
+            .replay(&job.id, &provider, &no_retries(), keep)
+            .await
+            .unwrap_err();
+        assert_eq!(error, network_error());
+
+        let saved = queue.get(&job.id).unwrap().unwrap();
+        assert_eq!(saved.attempts, 1);
+        assert!(matches!(&saved.chunks[0], QueuedChunk::Text { text } if text == "chunk of 100"));
+        assert!(matches!(saved.chunks[1], QueuedChunk::Audio { .. }));
+        assert!(!dir.join(&job.id).join("chunk-0.wav").exists());
+
+        // The next replay only sends the chunks that are left
+        let provider = FakeProvider::new(usize::MAX);
+        let text = queue.replay(&job.id, &provider, &no_retries(), keep).await;
+        assert_eq!(
+            text.unwrap().as_deref(),
+            Some("chunk of 100 chunk of 200 chunk of 300")
+        );
+        assert_eq!(provider.calls(), vec![200, 300]);
+    }
+}
@@ -0,0 +1,361 @@
+//retry.rs
+// Retrying transcription requests that failed for transient reasons, with
+// exponential backoff that honours the server's Retry-After.
+use crate::audio::TARGET_SAMPLE_RATE;
+use crate::encoding::{self, AudioFormat, EncodedAudio};
+use crate::transcription::{TranscriptionError, TranscriptionProvider};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    // Including the first try
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 4,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RetryPolicy {
+    // Wait before the given retry (1 for the first): the server's Retry-After
+    // if it sent one, otherwise base_delay doubled per attempt plus jitter so
+    // parallel chunks don't retry in lockstep
+    pub fn delay(&self, retry: u32, error: &TranscriptionError) -> Duration {
+        if let Some(retry_after) = error.retry_after() {
+            return retry_after.min(self.max_delay);
+        }
+        let backoff = self
+            .base_delay
+            .saturating_mul(1 << retry.saturating_sub(1).min(16));
+        (backoff + jitter(self.base_delay / 4)).min(self.max_delay)
+    }
+}
+
+// Transcribe, retrying retryable failures until the policy runs out or
+// `cancelled` is set. `on_retry` is called with the retry number, the error
+// and the delay before each new attempt.
+pub async fn transcribe_with_retry(
+    provider: &dyn TranscriptionProvider,
+    audio: &EncodedAudio,
+    policy: &RetryPolicy,
+    cancelled: &AtomicBool,
+    on_retry: impl Fn(u32, &TranscriptionError, Duration),
+) -> Result<String, TranscriptionError> {
+    let mut attempt = 1;
+    loop {
+        let error = match provider.transcribe(audio).await {
+            Ok(text) => return Ok(text),
+            Err(e) => TranscriptionError::classify(&e),
+        };
+
+        if !error.is_retryable() || attempt >= policy.max_attempts {
+            return Err(error);
+        }
+
+        let delay = policy.delay(attempt, &error);
+        eprintln!(
+            "Transcription attempt {} failed ({}), retrying in {:?}",
+            attempt, error, delay
+        );
+        on_retry(attempt, &error, delay);
+        tokio::time::sleep(delay).await;
+
+        // Nobody is waiting for the text any more
+        if cancelled.load(Ordering::SeqCst) {
+            return Err(error);
+        }
+        attempt += 1;
+    }
+}
+
+// Encode 16 kHz samples in the provider's upload format and transcribe them
+// with retries. If the server refuses a compressed format the chunk is sent
+// again as WAV, which every server reads.
+pub async fn transcribe_chunk(
+    provider: &dyn TranscriptionProvider,
+    samples: &[f32],
+    policy: &RetryPolicy,
+    cancelled: &AtomicBool,
+    on_retry: impl Fn(u32, &TranscriptionError, Duration),
+) -> Result<String, TranscriptionError> {
+    let audio = encode(samples, provider.upload_format())?;
+    eprintln!(
+        "Uploading {:.1} KB of {:?} audio",
+        audio.data.len() as f64 / 1024.0,
+        audio.format
+    );
+    match transcribe_with_retry(provider, &audio, policy, cancelled, &on_retry).await {
+        Err(error) if error.is_format_rejected() && audio.format != AudioFormat::Wav => {
+            eprintln!(
+                "{} rejected {:?} audio ({}), falling back to WAV",
+                provider.name(),
+                audio.format,
+                error
+            );
+            let audio = encode(samples, AudioFormat::Wav)?;
+            transcribe_with_retry(provider, &audio, policy, cancelled, &on_retry).await
+        }
+        result => result,
+    }
+}
+
+fn encode(samples: &[f32], format: AudioFormat) -> Result<EncodedAudio, TranscriptionError> {
+    encoding::encode(samples, TARGET_SAMPLE_RATE, format).map_err(|e| TranscriptionError::Other {
+        message: e.to_string(),
+    })
+}
+
+// A pseudo-random duration below `max`; good enough to spread retries
+fn jitter(max: Duration) -> Duration {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos() as u64)
+        .unwrap_or_default();
+    let max_nanos = max.as_nanos().max(1) as u64;
+    Duration::from_nanos(nanos % max_nanos)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use async_trait::async_trait;
+    use std::collections::VecDeque;
+    use std::sync::Mutex;
+
+    // Fails with the scripted errors in turn, then succeeds. A `wav_only`
+    // provider refuses anything but WAV like a server without a decoder.
+    struct FakeProvider {
+        upload_format: AudioFormat,
+        wav_only: bool,
+        failures: Mutex<VecDeque<TranscriptionError>>,
+        uploads: Mutex<Vec<AudioFormat>>,
+    }
+
+    impl FakeProvider {
+        fn failing(failures: Vec<TranscriptionError>) -> Self {
+            Self {
+                upload_format: AudioFormat::Wav,
+                wav_only: false,
+                failures: Mutex::new(failures.into()),
+                uploads: Mutex::new(Vec::new()),
+            }
+        }
+
+        fn wav_only(upload_format: AudioFormat) -> Self {
+            Self {
+                upload_format,
+                wav_only: true,
+                ..Self::failing(Vec::new())
+            }
+        }
+
+        fn uploads(&self) -> Vec<AudioFormat> {
+            self.uploads.lock().unwrap().clone()
+        }
+    }
+
+    #[async_trait]
+    impl TranscriptionProvider for FakeProvider {
+        fn name(&self) -> &'static str {
+            "Fake"
+        }
+
+        fn upload_format(&self) -> AudioFormat {
+            self.upload_format
+        }
+
+        async fn transcribe(&self, audio: &EncodedAudio) -> Result<String> {
+            self.uploads.lock().unwrap().push(audio.format);
+            if self.wav_only && audio.format != AudioFormat::Wav {
+                return Err(TranscriptionError::Request {
+                    status: 415,
+                    message: "Unsupported media type".to_string(),
+                }
+                .into());
+            }
+            match self.failures.lock().unwrap().pop_front() {
+                Some(error) => Err(error.into()),
+                None => Ok("hello".to_string()),
+            }
+        }
+    }
+
+    fn network_error() -> TranscriptionError {
+        TranscriptionError::Network {
+            message: "offline".to_string(),
+        }
+    }
+
+    fn rate_limited(retry_after_secs: Option<u64>) -> TranscriptionError {
+        TranscriptionError::RateLimited {
+            message: "slow down".to_string(),
+            retry_after_secs,
+        }
+    }
+
+    fn fast_policy(max_attempts: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts,
+            base_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(5),
+        }
+    }
+
+    fn wav() -> EncodedAudio {
+        encode(&[0.1; 1600], AudioFormat::Wav).unwrap()
+    }
+
+    #[test]
+    fn backoff_doubles_per_retry_up_to_the_cap() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(1),
+        };
+        let error = network_error();
+        for (retry, expected) in [(1, 100), (2, 200), (3, 400), (4, 800)] {
+            let delay = policy.delay(retry, &error);
+            let expected = Duration::from_millis(expected);
+            // Jitter adds less than a quarter of the base delay
+            assert!(
+                delay >= expected && delay < expected + Duration::from_millis(25),
+                "retry {}: {:?}",
+                retry,
+                delay
+            );
+        }
+        assert_eq!(policy.delay(5, &error), policy.max_delay);
+        assert_eq!(policy.delay(u32::MAX, &error), policy.max_delay);
+    }
+
+    #[test]
+    fn retry_after_is_honoured_up_to_the_cap() {
+        let policy = RetryPolicy::default();
+        assert_eq!(
+            policy.delay(1, &rate_limited(Some(5))),
+            Duration::from_secs(5)
+        );
+        assert_eq!(policy.delay(1, &rate_limited(Some(600))), policy.max_delay);
+        // Without one the backoff applies
+        assert!(policy.delay(1, &rate_limited(None)) < Duration::from_secs(2));
+    }
+
+    #[tokio::test]
+    async fn transient_failures_are_retried() {
+        let provider = FakeProvider::failing(vec![network_error(), rate_limited(None)]);
+        let retries = Mutex::new(Vec::new());
+        let text = transcribe_with_retry(
+            &provider,
+            &wav(),
+            &fast_policy(3),
+            &AtomicBool::new(false),
+            |retry, error, _| retries.lock().unwrap().push((retry, error.clone())),
+        )
+        .await
+        .unwrap();
+        assert_eq!(text, "hello");
+        assert_eq!(
+            retries.into_inner().unwrap(),
+            vec![(1, network_error()), (2, rate_limited(None))]
+        );
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_max_attempts() {
+        let provider = FakeProvider::failing(vec![network_error(); 5]);
+        let result = transcribe_with_retry(
+            &provider,
+            &wav(),
+            &fast_policy(3),
+            &AtomicBool::new(false),
+            |_, _, _| {},
+        )
+        .await;
+        assert_eq!(result, Err(network_error()));
+        assert_eq!(provider.uploads().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn permanent_failures_are_not_retried() {
+        let auth = TranscriptionError::Auth {
+            message: "bad key".to_string(),
+        };
+        let provider = FakeProvider::failing(vec![auth.clone()]);
+        let result = transcribe_with_retry(
+            &provider,
+            &wav(),
+            &fast_policy(3),
+            &AtomicBool::new(false),
+            |_, _, _| panic!("retried a permanent failure"),
+        )
+        .await;
+        assert_eq!(result, Err(auth));
+        assert_eq!(provider.uploads().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn stops_retrying_once_cancelled() {
+        let provider = FakeProvider::failing(vec![network_error(); 5]);
+        let result = transcribe_with_retry(
+            &provider,
+            &wav(),
+            &fast_policy(5),
+            &AtomicBool::new(true),
+            |_, _, _| {},
+        )
+        .await;
+        assert_eq!(result, Err(network_error()));
+        assert_eq!(provider.uploads().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn rejected_compressed_audio_is_sent_again_as_wav() {
+        let provider = FakeProvider::wav_only(AudioFormat::Flac);
+        let text = transcribe_chunk(
+            &provider,
+            &[0.1; 1600],
+            &fast_policy(3),
+            &AtomicBool::new(false),
+            |_, _, _| {},
+        )
+        .await
+        .unwrap();
+        assert_eq!(text, "hello");
+        assert_eq!(
+            provider.uploads(),
+            vec![AudioFormat::Flac, AudioFormat::Wav]
+        );
+    }
+
+    #[tokio::test]
+    async fn other_rejections_do_not_fall_back_to_wav() {
+        let rejected = TranscriptionError::Request {
+            status: 400,
+            message: "Unknown model".to_string(),
+        };
+        let provider = FakeProvider {
+            upload_format: AudioFormat::Flac,
+            ..FakeProvider::failing(vec![rejected.clone()])
+        };
+        let result = transcribe_chunk(
+            &provider,
+            &[0.1; 1600],
+            &fast_policy(3),
+            &AtomicBool::new(false),
+            |_, _, _| {},
+        )
+        .await;
+        assert_eq!(result, Err(rejected));
+        assert_eq!(provider.uploads(), vec![AudioFormat::Flac]);
+    }
+}
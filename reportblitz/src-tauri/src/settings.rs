@@ -0,0 +1,803 @@
+//settings.rs
+// User preferences, stored as one versioned JSON value under the "settings"
+// key of settings.dat. API keys are not settings and live in their own store.
+use crate::archive::ArchiveFormat;
+use crate::control::DEFAULT_HTTP_PORT;
+use crate::credentials::{self, DEFAULT_PROFILE};
+use crate::encoding::AudioFormat;
+use crate::insertion::InsertionMode;
+use crate::postprocess::PostProcessSettings;
+use crate::snippets::SnippetSettings;
+use crate::templates::{self, FieldExtraction};
+use crate::transcription::{ProviderKind, TranscriptionOptions};
+use crate::vad::VadConfig;
+use crate::voice_commands::VoiceCommandSettings;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+pub const SETTINGS_KEY: &str = "settings";
+
+// Bump when the layout changes and add a step to `migrate`
+pub const SETTINGS_VERSION: u32 = 1;
+
+// Top-level keys settings.dat used before everything moved under SETTINGS_KEY
+pub const LEGACY_KEYS: &[&str] = &[
+    "toggle_shortcut",
+    "hold_shortcut",
+    "cancel_shortcut",
+    "strict_text_field_mode",
+    "insertion_mode",
+    "input_device",
+    "transcription_provider",
+    "transcription_base_url",
+    "transcription_local_model",
+    "transcription_model",
+    "transcription_language",
+    "transcription_prompt",
+    "transcription_temperature",
+    "vad",
+    "history_enabled",
+    "history_keep_audio",
+];
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct Settings {
+    pub version: u32,
+    pub shortcuts: ShortcutSettings,
+    pub strict_text_field_mode: bool,
+    pub insertion_mode: InsertionMode,
+    // Preferred microphone by name; None means the system default
+    pub input_device: Option<String>,
+    pub transcription: TranscriptionSettings,
+    pub dictionary: DictionaryOptions,
+    pub voice_commands: VoiceCommandSettings,
+    pub snippets: SnippetSettings,
+    pub post_processing: PostProcessSettings,
+    pub templates: TemplateOptions,
+    pub vad: VadConfig,
+    pub history: HistoryOptions,
+    pub archive: ArchiveOptions,
+    pub credential_profiles: CredentialProfiles,
+    pub control: ControlOptions,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            version: SETTINGS_VERSION,
+            shortcuts: ShortcutSettings::default(),
+            strict_text_field_mode: false,
+            insertion_mode: InsertionMode::default(),
+            input_device: None,
+            transcription: TranscriptionSettings::default(),
+            dictionary: DictionaryOptions::default(),
+            voice_commands: VoiceCommandSettings::default(),
+            snippets: SnippetSettings::default(),
+            post_processing: PostProcessSettings::default(),
+            templates: TemplateOptions::default(),
+            vad: VadConfig::default(),
+            history: HistoryOptions::default(),
+            archive: ArchiveOptions::default(),
+            credential_profiles: CredentialProfiles::default(),
+            control: ControlOptions::default(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct ShortcutSettings {
+    pub toggle: String,
+    pub hold: String,
+    pub cancel: String,
+}
+
+impl Default for ShortcutSettings {
+    fn default() -> Self {
+        #[cfg(target_os = "windows")]
+        let modifier = "ctrl";
+        #[cfg(not(target_os = "windows"))]
+        let modifier = "super";
+
+        Self {
+            toggle: format!("{}+KeyG", modifier),
+            hold: format!("{}+KeyK", modifier),
+            cancel: format!("{}+KeyC", modifier),
+        }
+    }
+}
+
+impl ShortcutSettings {
+    pub fn validate(&self) -> Result<()> {
+        if self.toggle.is_empty() || self.hold.is_empty() || self.cancel.is_empty() {
+            return Err(anyhow!("Shortcuts cannot be empty"));
+        }
+        if self.toggle == self.hold || self.toggle == self.cancel || self.hold == self.cancel {
+            return Err(anyhow!("All shortcuts must be different"));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct TranscriptionSettings {
+    pub provider: ProviderKind,
+    pub base_url: String,
+    // ggml model file name for the local provider; empty picks the first found
+    pub local_model: String,
+    // Credential profile whose API key is sent to an OpenAI-compatible
+    // server. None sends no key, so the OpenAI key never goes to another host.
+    pub api_key_profile: Option<String>,
+    #[serde(flatten)]
+    pub options: TranscriptionOptions,
+    pub upload_formats: UploadFormats,
+}
+
+impl TranscriptionSettings {
+    pub fn validate(&self) -> Result<()> {
+        if self.provider == ProviderKind::OpenAiCompatible && self.base_url.is_empty() {
+            return Err(anyhow!(
+                "A base URL is required for an OpenAI-compatible server"
+            ));
+        }
+        self.options.validate()?;
+        self.upload_formats.validate()
+    }
+}
+
+// Format audio is uploaded in, per provider. Not every OpenAI-compatible
+// server reads compressed audio, so WAV is the default; a rejected format
+// falls back to WAV anyway.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct UploadFormats {
+    pub open_ai: AudioFormat,
+    pub open_ai_compatible: AudioFormat,
+}
+
+impl UploadFormats {
+    pub fn validate(&self) -> Result<()> {
+        for format in [self.open_ai, self.open_ai_compatible] {
+            if !format.is_available() {
+                return Err(anyhow!(
+                    "{:?} uploads are not available in this build",
+                    format
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct HistoryOptions {
+    pub enabled: bool,
+    pub keep_audio: bool,
+}
+
+impl Default for HistoryOptions {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            keep_audio: false,
+        }
+    }
+}
+
+// The replacement dictionary's entries are kept in dictionary.json
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct DictionaryOptions {
+    pub enabled: bool,
+    // Also send the dictionary's spellings with the transcription prompt
+    pub prompt_hints: bool,
+}
+
+impl Default for DictionaryOptions {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            prompt_hints: false,
+        }
+    }
+}
+
+// Keeping each recording and its transcript on disk for later review
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct ArchiveOptions {
+    pub enabled: bool,
+    // Absolute path; None uses the recordings folder in the app data directory
+    pub folder: Option<String>,
+    pub format: ArchiveFormat,
+    // Recordings older than this are deleted; None keeps them forever
+    pub max_age_days: Option<u32>,
+    // The oldest recordings are deleted beyond this total; None means no limit
+    pub max_size_mb: Option<u64>,
+}
+
+impl ArchiveOptions {
+    pub fn validate(&self) -> Result<()> {
+        if let Some(folder) = &self.folder {
+            if !std::path::Path::new(folder).is_absolute() {
+                return Err(anyhow!("The archive folder must be an absolute path"));
+            }
+        }
+        if self.max_age_days == Some(0) || self.max_size_mb == Some(0) {
+            return Err(anyhow!("Archive limits must be greater than zero"));
+        }
+        Ok(())
+    }
+}
+
+// The local control API that scripts and hardware buttons drive recording through
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct ControlOptions {
+    // Unix socket only the current user can use; not available on Windows
+    pub socket_enabled: bool,
+    // HTTP on 127.0.0.1 for tools that can't use the socket; needs the access token
+    pub http_enabled: bool,
+    pub http_port: u16,
+}
+
+impl Default for ControlOptions {
+    fn default() -> Self {
+        Self {
+            socket_enabled: cfg!(unix),
+            http_enabled: false,
+            http_port: DEFAULT_HTTP_PORT,
+        }
+    }
+}
+
+impl ControlOptions {
+    pub fn validate(&self) -> Result<()> {
+        if self.http_enabled && self.http_port == 0 {
+            return Err(anyhow!("Choose a port for the control API"));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct TemplateOptions {
+    // Report template dictation is rendered into; None inserts the text as is
+    pub active: Option<String>,
+    pub extraction: FieldExtraction,
+}
+
+// Names of the API key profiles; the keys themselves are in the credential store
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct CredentialProfiles {
+    // Profile whose key is used for transcription
+    pub active: String,
+    pub names: Vec<String>,
+}
+
+impl Default for CredentialProfiles {
+    fn default() -> Self {
+        Self {
+            active: DEFAULT_PROFILE.to_string(),
+            names: vec![DEFAULT_PROFILE.to_string()],
+        }
+    }
+}
+
+impl CredentialProfiles {
+    pub fn contains(&self, name: &str) -> bool {
+        self.names.iter().any(|n| n == name)
+    }
+}
+
+impl Settings {
+    // Trim text fields and turn blank optional values into None
+    pub fn normalized(mut self) -> Self {
+        self.shortcuts.toggle = self.shortcuts.toggle.trim().to_string();
+        self.shortcuts.hold = self.shortcuts.hold.trim().to_string();
+        self.shortcuts.cancel = self.shortcuts.cancel.trim().to_string();
+        self.input_device = self
+            .input_device
+            .map(|name| name.trim().to_string())
+            .filter(|name| !name.is_empty());
+        self.transcription.base_url = self.transcription.base_url.trim().to_string();
+        self.transcription.api_key_profile = self
+            .transcription
+            .api_key_profile
+            .map(|name| name.trim().to_string())
+            .filter(|name| !name.is_empty());
+        self.transcription.local_model = self.transcription.local_model.trim().to_string();
+        self.transcription.options = self.transcription.options.normalized();
+        self.voice_commands = self.voice_commands.normalized();
+        self.snippets = self.snippets.normalized();
+        self.post_processing = self.post_processing.normalized();
+        self.templates.active = self
+            .templates
+            .active
+            .map(|name| name.trim().to_string())
+            .filter(|name| !name.is_empty());
+        self.archive.folder = self
+            .archive
+            .folder
+            .map(|folder| folder.trim().to_string())
+            .filter(|folder| !folder.is_empty());
+        self.credential_profiles.active = self.credential_profiles.active.trim().to_string();
+        for name in &mut self.credential_profiles.names {
+            *name = name.trim().to_string();
+        }
+        self
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        self.shortcuts.validate()?;
+        self.transcription.validate()?;
+        self.voice_commands.validate()?;
+        self.snippets.validate()?;
+        self.post_processing.validate()?;
+        if let Some(name) = &self.templates.active {
+            templates::validate_name(name)?;
+        }
+        self.vad.validate()?;
+        self.archive.validate()?;
+        self.control.validate()?;
+
+        let profiles = &self.credential_profiles;
+        for (i, name) in profiles.names.iter().enumerate() {
+            credentials::validate_profile_name(name)?;
+            if profiles.names[..i].contains(name) {
+                return Err(anyhow!("Duplicate credential profile \"{}\"", name));
+            }
+        }
+        if !profiles.contains(DEFAULT_PROFILE) {
+            return Err(anyhow!("The default credential profile cannot be removed"));
+        }
+        let referenced = [
+            Some(&profiles.active),
+            self.transcription.api_key_profile.as_ref(),
+            self.post_processing.api_key_profile.as_ref(),
+        ];
+        for name in referenced.into_iter().flatten() {
+            if !profiles.contains(name) {
+                return Err(anyhow!("Unknown credential profile \"{}\"", name));
+            }
+        }
+        Ok(())
+    }
+}
+
+// Read settings through `get`, which looks up a top-level key in settings.dat.
+// The flag is true when the stored data was in an older layout and the
+// returned settings should be written back.
+pub fn load(get: impl Fn(&str) -> Option<Value>) -> (Settings, bool) {
+    let value = match get(SETTINGS_KEY) {
+        Some(value) => value,
+        None => return (repaired(from_legacy_keys(&get)), true),
+    };
+
+    let stored_version = value.get("version").and_then(|v| v.as_u64()).unwrap_or(0);
+    match serde_json::from_value::<Settings>(migrate(value)) {
+        Ok(settings) => (
+            repaired(settings.normalized()),
+            stored_version < SETTINGS_VERSION as u64,
+        ),
+        Err(e) => {
+            eprintln!("Stored settings are unreadable, using defaults: {}", e);
+            (Settings::default(), false)
+        }
+    }
+}
+
+// Upgrade a stored settings value to SETTINGS_VERSION, one version at a time
+fn migrate(mut value: Value) -> Value {
+    let version = value.get("version").and_then(|v| v.as_u64()).unwrap_or(0);
+    if version > SETTINGS_VERSION as u64 {
+        // Written by a newer build. Unknown fields are ignored and missing
+        // ones take their defaults, but the version stays so saving doesn't
+        // pass the file off as an older one.
+        eprintln!("Settings version {} is newer than this build", version);
+        return value;
+    }
+
+    // Steps go here as the layout changes, e.g.
+    // if version < 2 { ... }
+
+    value["version"] = json!(SETTINGS_VERSION);
+    value
+}
+
+// The value to store under SETTINGS_KEY. Settings that came from a newer build
+// are laid over the `stored` value, so the fields only that build knows about
+// survive an older build saving its changes.
+pub fn to_stored(settings: &Settings, stored: Option<Value>) -> Value {
+    let value = json!(settings);
+    match stored {
+        Some(stored) if settings.version > SETTINGS_VERSION => merge(stored, value),
+        _ => value,
+    }
+}
+
+// `update` laid over `base`, keeping the object keys only `base` has
+fn merge(base: Value, update: Value) -> Value {
+    match (base, update) {
+        (Value::Object(mut base), Value::Object(update)) => {
+            for (key, value) in update {
+                let merged = match base.remove(&key) {
+                    Some(old) => merge(old, value),
+                    None => value,
+                };
+                base.insert(key, merged);
+            }
+            Value::Object(base)
+        }
+        (_, update) => update,
+    }
+}
+
+// Build settings from the flat keys used before settings were versioned. Those
+// were saved without validation; `load` repairs them like any others.
+fn from_legacy_keys(get: &impl Fn(&str) -> Option<Value>) -> Settings {
+    let string = |key: &str| get(key).and_then(|v| v.as_str().map(|s| s.to_string()));
+    let boolean = |key: &str| get(key).and_then(|v| v.as_bool());
+    let defaults = Settings::default();
+
+    Settings {
+        version: SETTINGS_VERSION,
+        shortcuts: ShortcutSettings {
+            toggle: string("toggle_shortcut").unwrap_or(defaults.shortcuts.toggle),
+            hold: string("hold_shortcut").unwrap_or(defaults.shortcuts.hold),
+            cancel: string("cancel_shortcut").unwrap_or(defaults.shortcuts.cancel),
+        },
+        strict_text_field_mode: boolean("strict_text_field_mode").unwrap_or(false),
+        insertion_mode: string("insertion_mode")
+            .and_then(|mode| InsertionMode::parse(&mode))
+            .unwrap_or_default(),
+        input_device: string("input_device"),
+        transcription: TranscriptionSettings {
+            provider: string("transcription_provider")
+                .and_then(|provider| ProviderKind::parse(&provider))
+                .unwrap_or_default(),
+            base_url: string("transcription_base_url").unwrap_or_default(),
+            local_model: string("transcription_local_model").unwrap_or_default(),
+            api_key_profile: None,
+            options: TranscriptionOptions {
+                model: string("transcription_model").unwrap_or_default(),
+                language: string("transcription_language"),
+                prompt: string("transcription_prompt"),
+                temperature: get("transcription_temperature")
+                    .and_then(|v| v.as_f64())
+                    .map(|t| t as f32),
+            },
+            upload_formats: UploadFormats::default(),
+        },
+        dictionary: DictionaryOptions::default(),
+        voice_commands: VoiceCommandSettings::default(),
+        snippets: SnippetSettings::default(),
+        post_processing: PostProcessSettings::default(),
+        templates: TemplateOptions::default(),
+        vad: get("vad")
+            .and_then(|v| serde_json::from_value(v).ok())
+            .unwrap_or_default(),
+        history: HistoryOptions {
+            enabled: boolean("history_enabled").unwrap_or(defaults.history.enabled),
+            keep_audio: boolean("history_keep_audio").unwrap_or(defaults.history.keep_audio),
+        },
+        archive: ArchiveOptions::default(),
+        credential_profiles: CredentialProfiles::default(),
+        control: ControlOptions::default(),
+    }
+    .normalized()
+}
+
+// Settings as loaded with every value that doesn't validate dropped or put
+// back to its default, so a hand-edited file or one written by another build
+// can't carry invalid values into the pipeline. Expects normalized settings.
+fn repaired(mut settings: Settings) -> Settings {
+    fall_back(
+        settings.shortcuts.validate(),
+        &mut settings.shortcuts,
+        "shortcuts",
+    );
+    let transcription = &mut settings.transcription;
+    // e.g. Opus chosen in a build that has it
+    fall_back(
+        transcription.upload_formats.validate(),
+        &mut transcription.upload_formats,
+        "upload formats",
+    );
+    fall_back(
+        transcription.options.validate(),
+        &mut transcription.options,
+        "transcription options",
+    );
+    fall_back(
+        transcription.validate(),
+        transcription,
+        "transcription settings",
+    );
+
+    // Keep the user's own phrases and snippets that are still valid
+    let phrases = &mut settings.voice_commands.phrases;
+    phrases.retain(|language, _| !language.is_empty());
+    for phrases in phrases.values_mut() {
+        phrases.retain(|phrase, _| !phrase.is_empty());
+    }
+    let mut triggers = Vec::new();
+    settings.snippets.snippets.retain(|snippet| {
+        let trigger = snippet.trigger.to_lowercase();
+        let keep = !trigger.is_empty() && !triggers.contains(&trigger);
+        triggers.push(trigger);
+        keep
+    });
+
+    fall_back(
+        settings.post_processing.validate(),
+        &mut settings.post_processing,
+        "post-processing settings",
+    );
+    if let Some(name) = &settings.templates.active {
+        if let Err(e) = templates::validate_name(name) {
+            eprintln!("Ignoring the stored active template: {}", e);
+            settings.templates.active = None;
+        }
+    }
+    fall_back(settings.vad.validate(), &mut settings.vad, "VAD settings");
+    // Keep archiving on, in the default folder and without the broken limit
+    if let Err(e) = settings.archive.validate() {
+        eprintln!("Ignoring part of the stored archive settings: {}", e);
+        let archive = &mut settings.archive;
+        archive.folder = archive
+            .folder
+            .take()
+            .filter(|folder| std::path::Path::new(folder).is_absolute());
+        archive.max_age_days = archive.max_age_days.filter(|days| *days > 0);
+        archive.max_size_mb = archive.max_size_mb.filter(|mb| *mb > 0);
+    }
+    fall_back(
+        settings.control.validate(),
+        &mut settings.control,
+        "control API settings",
+    );
+
+    let profiles = &mut settings.credential_profiles;
+    let mut names: Vec<String> = Vec::new();
+    for name in std::mem::take(&mut profiles.names) {
+        if credentials::validate_profile_name(&name).is_ok() && !names.contains(&name) {
+            names.push(name);
+        }
+    }
+    if !names.iter().any(|name| name == DEFAULT_PROFILE) {
+        names.insert(0, DEFAULT_PROFILE.to_string());
+    }
+    profiles.names = names;
+    if !profiles.contains(&profiles.active) {
+        profiles.active = DEFAULT_PROFILE.to_string();
+    }
+    let known = |name: Option<String>| name.filter(|name| profiles.contains(name));
+    settings.transcription.api_key_profile = known(settings.transcription.api_key_profile.take());
+    settings.post_processing.api_key_profile =
+        known(settings.post_processing.api_key_profile.take());
+
+    if let Err(e) = settings.validate() {
+        eprintln!("Ignoring the stored settings: {}", e);
+        return Settings {
+            version: settings.version,
+            ..Settings::default()
+        };
+    }
+    settings
+}
+
+// Put `value` back to its default if it didn't validate
+fn fall_back<T: Default>(validation: Result<()>, value: &mut T, what: &str) {
+    if let Err(e) = validation {
+        eprintln!("Ignoring the stored {}: {}", what, e);
+        *value = T::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn load_from(values: Value) -> (Settings, bool) {
+        let values: HashMap<String, Value> = serde_json::from_value(values).unwrap();
+        load(|key| values.get(key).cloned())
+    }
+
+    #[test]
+    fn migrates_legacy_keys() {
+        let (settings, upgraded) = load_from(json!({
+            "toggle_shortcut": " ctrl+KeyR ",
+            "strict_text_field_mode": true,
+            "insertion_mode": "clipboard_paste",
+            "input_device": "USB Microphone",
+            "transcription_provider": "openai_compatible",
+            "transcription_base_url": "http://whisper.local/v1",
+            "transcription_language": "de",
+            "transcription_temperature": 0.2,
+            "vad": { "enabled": false },
+            "history_keep_audio": true,
+        }));
+        assert!(upgraded);
+        assert_eq!(settings.version, SETTINGS_VERSION);
+        assert_eq!(settings.shortcuts.toggle, "ctrl+KeyR");
+        assert_eq!(settings.shortcuts.hold, ShortcutSettings::default().hold);
+        assert!(settings.strict_text_field_mode);
+        assert_eq!(settings.insertion_mode, InsertionMode::ClipboardPaste);
+        assert_eq!(settings.input_device.as_deref(), Some("USB Microphone"));
+        assert_eq!(
+            settings.transcription.provider,
+            ProviderKind::OpenAiCompatible
+        );
+        assert_eq!(settings.transcription.base_url, "http://whisper.local/v1");
+        assert_eq!(
+            settings.transcription.options.language.as_deref(),
+            Some("de")
+        );
+        assert_eq!(settings.transcription.options.temperature, Some(0.2));
+        assert!(!settings.vad.enabled);
+        assert!(settings.history.enabled);
+        assert!(settings.history.keep_audio);
+        assert!(settings.validate().is_ok());
+    }
+
+    #[test]
+    fn invalid_legacy_values_fall_back_to_defaults() {
+        let (settings, _) = load_from(json!({
+            "toggle_shortcut": "ctrl+KeyG",
+            "hold_shortcut": "ctrl+KeyG",
+            "strict_text_field_mode": true,
+            "transcription_provider": "openai_compatible",
+            "transcription_model": "whisper-large",
+            "vad": { "frame_ms": 5 },
+        }));
+        assert_eq!(settings.shortcuts, ShortcutSettings::default());
+        assert_eq!(settings.transcription.provider, ProviderKind::OpenAi);
+        assert_eq!(
+            settings.transcription.options,
+            TranscriptionOptions::default()
+        );
+        assert_eq!(settings.vad, VadConfig::default());
+        // Values that were fine are kept
+        assert!(settings.strict_text_field_mode);
+        assert!(settings.validate().is_ok());
+    }
+
+    #[test]
+    fn upgrades_version_0() {
+        let (settings, upgraded) = load_from(json!({
+            "settings": {
+                "strict_text_field_mode": true,
+                "transcription": { "language": "fr" },
+            },
+        }));
+        assert!(upgraded);
+        assert_eq!(settings.version, 1);
+        assert!(settings.strict_text_field_mode);
+        assert_eq!(
+            settings.transcription.options.language.as_deref(),
+            Some("fr")
+        );
+    }
+
+    #[test]
+    fn current_settings_are_not_rewritten() {
+        let stored = json!(Settings::default());
+        let (settings, upgraded) = load_from(json!({ "settings": stored }));
+        assert!(!upgraded);
+        assert_eq!(settings.version, SETTINGS_VERSION);
+    }
+
+    #[test]
+    fn newer_settings_keep_their_version_and_unknown_fields() {
+        let newer = SETTINGS_VERSION + 1;
+        let stored = json!({
+            "version": newer,
+            "strict_text_field_mode": true,
+            "future_option": "kept",
+            "vad": { "enabled": false, "future_vad_option": 3 },
+        });
+        let (mut settings, upgraded) = load_from(json!({ "settings": stored.clone() }));
+        assert!(!upgraded);
+        assert_eq!(settings.version, newer);
+        assert!(settings.strict_text_field_mode);
+        assert!(!settings.vad.enabled);
+
+        settings.strict_text_field_mode = false;
+        let saved = to_stored(&settings, Some(stored));
+        assert_eq!(saved["version"], json!(newer));
+        assert_eq!(saved["strict_text_field_mode"], json!(false));
+        assert_eq!(saved["future_option"], json!("kept"));
+        assert_eq!(saved["vad"]["future_vad_option"], json!(3));
+        assert_eq!(saved["vad"]["enabled"], json!(false));
+    }
+
+    #[test]
+    fn current_settings_are_stored_as_they_are() {
+        let stored = json!({ "version": SETTINGS_VERSION, "removed_option": true });
+        let saved = to_stored(&Settings::default(), Some(stored));
+        assert!(saved.get("removed_option").is_none());
+        assert_eq!(saved, json!(Settings::default()));
+    }
+
+    #[test]
+    fn invalid_stored_values_are_repaired_on_load() {
+        let (settings, upgraded) = load_from(json!({
+            "settings": {
+                "version": SETTINGS_VERSION,
+                "shortcuts": { "toggle": "ctrl+KeyG", "hold": "ctrl+KeyG" },
+                "strict_text_field_mode": true,
+                "transcription": {
+                    "base_url": "http://whisper.local/v1",
+                    "language": "english",
+                    "temperature": 7.0,
+                    "api_key_profile": "gone",
+                },
+                "snippets": {
+                    "snippets": [
+                        { "trigger": "sign off", "text": "Regards" },
+                        { "trigger": " ... ", "text": "no trigger" },
+                        { "trigger": "Sign Off", "text": "duplicate" },
+                    ],
+                },
+                "voice_commands": { "phrases": { "en": { "": "new_line", "next": "new_line" } } },
+                "templates": { "active": "../escape" },
+                "vad": { "frame_ms": 5 },
+                "archive": { "enabled": true, "max_age_days": 0, "max_size_mb": 500 },
+                "control": { "http_enabled": true, "http_port": 0 },
+                "credential_profiles": {
+                    "active": "missing",
+                    "names": ["work", "bad name!", "work"],
+                },
+            },
+        }));
+        assert!(!upgraded);
+        assert!(settings.validate().is_ok());
+        assert_eq!(settings.shortcuts, ShortcutSettings::default());
+        assert!(settings.strict_text_field_mode);
+        assert_eq!(
+            settings.transcription.options,
+            TranscriptionOptions::default()
+        );
+        assert_eq!(settings.transcription.base_url, "http://whisper.local/v1");
+        assert_eq!(settings.transcription.api_key_profile, None);
+        assert_eq!(
+            settings.snippets.snippets,
+            vec![crate::snippets::Snippet {
+                trigger: "sign off".to_string(),
+                text: "Regards".to_string(),
+            }]
+        );
+        assert_eq!(
+            settings.voice_commands.phrases["en"]
+                .keys()
+                .collect::<Vec<_>>(),
+            vec!["next"]
+        );
+        assert_eq!(settings.templates.active, None);
+        assert_eq!(settings.vad, VadConfig::default());
+        assert!(settings.archive.enabled);
+        assert_eq!(settings.archive.max_age_days, None);
+        assert_eq!(settings.archive.max_size_mb, Some(500));
+        assert_eq!(settings.control, ControlOptions::default());
+        assert_eq!(settings.credential_profiles.active, DEFAULT_PROFILE);
+        assert_eq!(
+            settings.credential_profiles.names,
+            vec![DEFAULT_PROFILE.to_string(), "work".to_string()]
+        );
+    }
+
+    #[test]
+    fn unreadable_settings_use_the_defaults() {
+        let (settings, upgraded) = load_from(json!({ "settings": { "shortcuts": 5 } }));
+        assert!(!upgraded);
+        assert_eq!(settings.shortcuts, ShortcutSettings::default());
+    }
+}
@@ -0,0 +1,237 @@
+//snippets.rs
+// Stored text, such as a signature or a disclaimer, that is typed in place of
+// a spoken trigger phrase like "insert signature". The phrase can be the whole
+// dictation or part of it.
+use anyhow::{anyhow, Result};
+use regex::{Captures, Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct Snippet {
+    pub trigger: String,
+    pub text: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct SnippetSettings {
+    pub enabled: bool,
+    pub snippets: Vec<Snippet>,
+}
+
+impl Default for SnippetSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            snippets: Vec::new(),
+        }
+    }
+}
+
+impl SnippetSettings {
+    // Collapse runs of spaces in the triggers; the text is kept exactly
+    pub fn normalized(mut self) -> Self {
+        for snippet in &mut self.snippets {
+            snippet.trigger = trigger_words(&snippet.trigger).join(" ");
+        }
+        self
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        for (i, snippet) in self.snippets.iter().enumerate() {
+            if trigger_words(&snippet.trigger).is_empty() {
+                return Err(anyhow!("Every snippet needs a trigger phrase"));
+            }
+            let duplicate = self.snippets[..i]
+                .iter()
+                .any(|other| other.trigger.to_lowercase() == snippet.trigger.to_lowercase());
+            if duplicate {
+                return Err(anyhow!("Duplicate snippet trigger \"{}\"", snippet.trigger));
+            }
+        }
+        Ok(())
+    }
+}
+
+// The words of a trigger phrase without surrounding punctuation
+fn trigger_words(trigger: &str) -> Vec<&str> {
+    trigger
+        .split_whitespace()
+        .map(|word| word.trim_matches(|c: char| !c.is_alphanumeric()))
+        .filter(|word| !word.is_empty())
+        .collect()
+}
+
+pub struct SnippetExpander {
+    // One alternative per snippet, longest trigger first, or None without
+    // any snippets
+    pattern: Option<Regex>,
+    texts: Vec<String>,
+}
+
+impl SnippetExpander {
+    pub fn new(snippets: &[Snippet]) -> Result<Self> {
+        let mut snippets: Vec<(Vec<&str>, &str)> = snippets
+            .iter()
+            .map(|snippet| (trigger_words(&snippet.trigger), snippet.text.as_str()))
+            .filter(|(words, _)| !words.is_empty())
+            .collect();
+        // So "insert signature long" isn't cut short by "insert signature"
+        snippets.sort_by_key(|(words, _)| std::cmp::Reverse(words.len()));
+
+        // The words may be separated by spaces or punctuation the
+        // transcription added ("Insert, signature."), and a trailing period
+        // belongs to the trigger
+        let alternatives: Vec<String> = snippets
+            .iter()
+            .map(|(words, _)| {
+                let words: Vec<String> = words.iter().map(|word| regex::escape(word)).collect();
+                format!(r"(\b{}\b[.,;:!?]*)", words.join(r"[\s\p{P}]+"))
+            })
+            .collect();
+        let pattern = if alternatives.is_empty() {
+            None
+        } else {
+            Some(
+                RegexBuilder::new(&alternatives.join("|"))
+                    .case_insensitive(true)
+                    .build()?,
+            )
+        };
+        Ok(Self {
+            pattern,
+            texts: snippets.iter().map(|(_, text)| text.to_string()).collect(),
+        })
+    }
+
+    // The snippet's text if the dictation is nothing but its trigger phrase
+    pub fn whole(&self, text: &str) -> Option<&str> {
+        let pattern = self.pattern.as_ref()?;
+        let trimmed = text.trim();
+        let captures = pattern.captures(trimmed)?;
+        if captures.get(0)?.range() != (0..trimmed.len()) {
+            return None;
+        }
+        Some(self.snippet_text(&captures))
+    }
+
+    // Replace each trigger phrase in `text` with its snippet. Expanded text
+    // isn't searched again.
+    pub fn expand(&self, text: &str) -> String {
+        match &self.pattern {
+            Some(pattern) => pattern
+                .replace_all(text, |captures: &Captures| {
+                    self.snippet_text(captures).to_string()
+                })
+                .into_owned(),
+            None => text.to_string(),
+        }
+    }
+
+    // Group i + 1 matched for snippet i
+    fn snippet_text(&self, captures: &Captures) -> &str {
+        (1..captures.len())
+            .find(|&group| captures.get(group).is_some())
+            .map(|group| self.texts[group - 1].as_str())
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expander(snippets: &[(&str, &str)]) -> SnippetExpander {
+        let snippets: Vec<Snippet> = snippets
+            .iter()
+            .map(|&(trigger, text)| Snippet {
+                trigger: trigger.to_string(),
+                text: text.to_string(),
+            })
+            .collect();
+        SnippetExpander::new(&snippets).unwrap()
+    }
+
+    #[test]
+    fn expands_a_trigger_inside_a_dictation() {
+        let expander = expander(&[("insert signature", "Officer Smith\nBadge 123")]);
+        assert_eq!(
+            expander.expand("Report filed. insert signature"),
+            "Report filed. Officer Smith\nBadge 123"
+        );
+        assert_eq!(
+            expander.expand("insert signature and then more"),
+            "Officer Smith\nBadge 123 and then more"
+        );
+    }
+
+    #[test]
+    fn ignores_case_and_punctuation_around_the_trigger() {
+        let expander = expander(&[("insert signature", "SIG")]);
+        assert_eq!(expander.expand("Done. Insert Signature."), "Done. SIG");
+        assert_eq!(expander.expand("Done, insert, signature!"), "Done, SIG");
+        assert_eq!(expander.expand("(INSERT SIGNATURE)"), "(SIG)");
+    }
+
+    #[test]
+    fn matches_whole_words_only() {
+        let expander = expander(&[("sig", "SIG")]);
+        assert_eq!(expander.expand("signal and design"), "signal and design");
+        assert_eq!(expander.expand("add sig here"), "add SIG here");
+    }
+
+    #[test]
+    fn prefers_the_longest_of_overlapping_triggers() {
+        let expander = expander(&[
+            ("insert signature", "short"),
+            ("insert signature long", "long"),
+        ]);
+        assert_eq!(expander.expand("insert signature long"), "long");
+        assert_eq!(expander.expand("insert signature, please"), "short please");
+        assert_eq!(
+            expander.expand("insert signature then insert signature long"),
+            "short then long"
+        );
+    }
+
+    #[test]
+    fn expanded_text_is_not_expanded_again() {
+        let expander = expander(&[("disclaimer", "See the disclaimer below")]);
+        assert_eq!(expander.expand("disclaimer"), "See the disclaimer below");
+    }
+
+    #[test]
+    fn recognizes_a_dictation_that_is_only_a_trigger() {
+        let expander = expander(&[("insert signature", "SIG"), ("disclaimer", "DIS")]);
+        assert_eq!(expander.whole(" Insert signature. "), Some("SIG"));
+        assert_eq!(expander.whole("Disclaimer"), Some("DIS"));
+        assert_eq!(expander.whole("insert signature now"), None);
+        assert_eq!(expander.whole("disclaimer disclaimer"), None);
+        assert_eq!(expander.whole(""), None);
+    }
+
+    #[test]
+    fn without_snippets_the_text_is_unchanged() {
+        let expander = expander(&[]);
+        assert_eq!(expander.expand(" text "), " text ");
+        assert_eq!(expander.whole("text"), None);
+    }
+
+    #[test]
+    fn rejects_empty_and_duplicate_triggers() {
+        let settings = |triggers: &[&str]| SnippetSettings {
+            enabled: true,
+            snippets: triggers
+                .iter()
+                .map(|trigger| Snippet {
+                    trigger: trigger.to_string(),
+                    text: "text".to_string(),
+                })
+                .collect(),
+        };
+        assert!(settings(&["a", "b"]).validate().is_ok());
+        assert!(settings(&["  ..."]).validate().is_err());
+        assert!(settings(&["Sig", "sig"]).validate().is_err());
+    }
+}
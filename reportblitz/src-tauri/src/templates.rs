@@ -0,0 +1,474 @@
+//templates.rs
+// Report templates: Markdown or plain text files in the templates folder of
+// the app data directory, with placeholders like {{location}}. A dictation is
+// mapped onto the fields, by their spoken labels or by the chat model, and
+// rendered into the template.
+use crate::postprocess::PostProcessor;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Always filled with the whole dictation
+pub const TRANSCRIPT_FIELD: &str = "transcript";
+
+const EXTENSIONS: &[&str] = &["md", "txt"];
+
+// Written to a new templates folder so there is something to start from
+const EXAMPLE_NAME: &str = "incident-report";
+const EXAMPLE_TEMPLATE: &str = "# Incident Report
+
+**Date:** {{incident_date}}
+**Location:** {{location}}
+**Persons involved:** {{persons_involved}}
+
+## Summary
+
+{{summary}}
+
+## Actions Taken
+
+{{actions_taken}}
+";
+
+// How a dictation is mapped onto template fields
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FieldExtraction {
+    // The speaker says each field's name before its value, e.g. "location
+    // Fifth and Main". Works offline.
+    #[default]
+    SpokenLabels,
+    // The chat model configured for post-processing picks the values out
+    Model,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Template {
+    pub name: String,
+    pub content: String,
+    // Placeholder names in order of first appearance
+    pub fields: Vec<String>,
+}
+
+impl Template {
+    pub fn new(name: &str, content: String) -> Self {
+        Self {
+            name: name.to_string(),
+            fields: placeholders(&content),
+            content,
+        }
+    }
+
+    // Fields the dictation has to supply
+    pub fn dictated_fields(&self) -> Vec<String> {
+        self.fields
+            .iter()
+            .filter(|field| *field != TRANSCRIPT_FIELD)
+            .cloned()
+            .collect()
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct RenderedReport {
+    pub text: String,
+    pub fields: BTreeMap<String, String>,
+    // Fields the dictation didn't fill; their placeholders are left in the
+    // text so they stand out
+    pub missing: Vec<String>,
+}
+
+pub fn templates_dir(app_data_dir: &Path) -> PathBuf {
+    app_data_dir.join("templates")
+}
+
+pub struct TemplateStore {
+    dir: PathBuf,
+}
+
+impl TemplateStore {
+    // Open the templates folder, creating it with an example template the
+    // first time
+    pub fn open(dir: &Path) -> Result<Self> {
+        if !dir.exists() {
+            fs::create_dir_all(dir)?;
+            fs::write(dir.join(format!("{}.md", EXAMPLE_NAME)), EXAMPLE_TEMPLATE)?;
+        }
+        Ok(Self {
+            dir: dir.to_path_buf(),
+        })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    // Sorted by name
+    pub fn list(&self) -> Result<Vec<Template>> {
+        let mut templates = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            let extension = path
+                .extension()
+                .and_then(|e| e.to_str())
+                .unwrap_or_default();
+            let name = path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .unwrap_or_default();
+            if !EXTENSIONS.contains(&extension) || validate_name(name).is_err() {
+                continue;
+            }
+            match fs::read_to_string(&path) {
+                Ok(content) => templates.push(Template::new(name, content)),
+                Err(e) => eprintln!("Skipping unreadable template {:?}: {}", path, e),
+            }
+        }
+        templates.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(templates)
+    }
+
+    pub fn get(&self, name: &str) -> Result<Option<Template>> {
+        match self.path(name)? {
+            Some(path) => Ok(Some(Template::new(name, fs::read_to_string(path)?))),
+            None => Ok(None),
+        }
+    }
+
+    // Create or replace a template. New ones are saved as Markdown.
+    pub fn save(&self, name: &str, content: &str) -> Result<Template> {
+        let path = match self.path(name)? {
+            Some(path) => path,
+            None => self.dir.join(format!("{}.md", name)),
+        };
+        fs::write(path, content)?;
+        Ok(Template::new(name, content.to_string()))
+    }
+
+    // Returns false if there was no such template
+    pub fn delete(&self, name: &str) -> Result<bool> {
+        match self.path(name)? {
+            Some(path) => {
+                fs::remove_file(path)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn path(&self, name: &str) -> Result<Option<PathBuf>> {
+        validate_name(name)?;
+        Ok(EXTENSIONS
+            .iter()
+            .map(|extension| self.dir.join(format!("{}.{}", name, extension)))
+            .find(|path| path.exists()))
+    }
+}
+
+// Names come from the frontend and become file names, so keep them simple
+pub fn validate_name(name: &str) -> Result<()> {
+    let valid_char = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == ' ';
+    if name.trim().is_empty() || name.trim() != name || !name.chars().all(valid_char) {
+        return Err(anyhow!(
+            "Template names may only contain letters, digits, spaces, '-' and '_'"
+        ));
+    }
+    Ok(())
+}
+
+// Names of the {{field}} placeholders in order of first appearance
+pub fn placeholders(content: &str) -> Vec<String> {
+    let mut fields: Vec<String> = Vec::new();
+    for (_, field, _) in placeholder_spans(content) {
+        if !fields.iter().any(|f| f == field) {
+            fields.push(field.to_string());
+        }
+    }
+    fields
+}
+
+// (start, field name, end) of each placeholder. Names are letters, digits
+// and underscores; anything else between braces is left alone.
+fn placeholder_spans(content: &str) -> Vec<(usize, &str, usize)> {
+    let mut spans = Vec::new();
+    let mut offset = 0;
+    while let Some(start) = content[offset..].find("{{").map(|i| offset + i) {
+        let Some(close) = content[start + 2..].find("}}").map(|i| start + 2 + i) else {
+            break;
+        };
+        let name = content[start + 2..close].trim();
+        let is_field =
+            !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if is_field {
+            spans.push((start, name, close + 2));
+            offset = close + 2;
+        } else {
+            offset = start + 2;
+        }
+    }
+    spans
+}
+
+// Fill in the placeholders. Fields without a value keep their placeholder.
+pub fn render(template: &Template, values: &BTreeMap<String, String>) -> RenderedReport {
+    let mut text = String::with_capacity(template.content.len());
+    let mut last = 0;
+    for (start, field, end) in placeholder_spans(&template.content) {
+        text.push_str(&template.content[last..start]);
+        match values.get(field).filter(|value| !value.is_empty()) {
+            Some(value) => text.push_str(value),
+            None => text.push_str(&template.content[start..end]),
+        }
+        last = end;
+    }
+    text.push_str(&template.content[last..]);
+
+    let missing = template
+        .fields
+        .iter()
+        .filter(|field| values.get(*field).is_none_or(|value| value.is_empty()))
+        .cloned()
+        .collect();
+    RenderedReport {
+        text,
+        fields: values.clone(),
+        missing,
+    }
+}
+
+// Split a dictation at the spoken field labels: "incident date March 3rd
+// location Fifth and Main" gives incident_date and location. A label is the
+// field name with spaces for underscores, matched as whole words. The first
+// mention of each label counts.
+pub fn extract_spoken_fields(text: &str, fields: &[String]) -> BTreeMap<String, String> {
+    // Only ASCII is lowercased, so offsets into `lower` are offsets into `text`
+    let lower = text.to_ascii_lowercase();
+    let mut found: Vec<(usize, usize, &String)> = fields
+        .iter()
+        .filter_map(|field| {
+            let label = field.replace('_', " ").to_ascii_lowercase();
+            find_word(&lower, &label).map(|start| (start, start + label.len(), field))
+        })
+        .collect();
+    found.sort_by_key(|(start, ..)| *start);
+
+    let mut values = BTreeMap::new();
+    for (i, (_, end, field)) in found.iter().enumerate() {
+        let value_end = found.get(i + 1).map_or(text.len(), |(start, ..)| *start);
+        let value = text[*end..value_end.max(*end)]
+            .trim_start_matches(|c: char| c.is_whitespace() || matches!(c, ':' | ',' | '-'))
+            .trim_end_matches(|c: char| c.is_whitespace() || matches!(c, ',' | ';'));
+        if !value.is_empty() {
+            values.insert(field.to_string(), value.to_string());
+        }
+    }
+    values
+}
+
+// Byte offset of the first whole-word match of `word` in `text`
+fn find_word(text: &str, word: &str) -> Option<usize> {
+    let is_word_char = |c: Option<char>| c.is_some_and(|c| c.is_alphanumeric());
+    text.match_indices(word).map(|(i, _)| i).find(|&i| {
+        !is_word_char(text[..i].chars().next_back())
+            && !is_word_char(text[i + word.len()..].chars().next())
+    })
+}
+
+// Map a dictation onto the template's fields and render it. Model extraction
+// needs the post-processing client.
+pub async fn fill(
+    template: &Template,
+    text: &str,
+    extraction: FieldExtraction,
+    processor: Option<&PostProcessor>,
+) -> Result<RenderedReport> {
+    let fields = template.dictated_fields();
+    let mut values = match extraction {
+        FieldExtraction::SpokenLabels => extract_spoken_fields(text, &fields),
+        FieldExtraction::Model if fields.is_empty() => BTreeMap::new(),
+        FieldExtraction::Model => {
+            let processor = processor.ok_or_else(|| {
+                anyhow!("Extracting fields with a model needs the post-processing settings")
+            })?;
+            processor.extract_fields(text, &fields).await?
+        }
+    };
+    values.insert(TRANSCRIPT_FIELD.to_string(), text.to_string());
+    Ok(render(template, &values))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+
+    fn values(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs
+            .iter()
+            .map(|(field, value)| (field.to_string(), value.to_string()))
+            .collect()
+    }
+
+    fn fields(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn placeholders_are_listed_once_in_order() {
+        let content = "{{location}} on {{ incident_date }}, {{location}} again {{transcript}}";
+        assert_eq!(
+            placeholders(content),
+            vec!["location", "incident_date", "transcript"]
+        );
+    }
+
+    #[test]
+    fn braces_that_are_not_field_names_are_left_alone() {
+        let template = Template::new("t", "{{}} {{two words}} {{a-b}} {{ok}} {{open".to_string());
+        assert_eq!(template.fields, vec!["ok"]);
+        let report = render(&template, &values(&[("ok", "yes")]));
+        assert_eq!(report.text, "{{}} {{two words}} {{a-b}} yes {{open");
+    }
+
+    #[test]
+    fn render_fills_every_placeholder_with_a_value() {
+        let template = Template::new(
+            "t",
+            "Date: {{incident_date}}\nWhere: {{location}}\n{{transcript}}\n{{location}}"
+                .to_string(),
+        );
+        let report = render(
+            &template,
+            &values(&[
+                ("incident_date", "March 3rd"),
+                ("location", "Fifth and Main"),
+                ("transcript", "all of it"),
+            ]),
+        );
+        assert_eq!(
+            report.text,
+            "Date: March 3rd\nWhere: Fifth and Main\nall of it\nFifth and Main"
+        );
+        assert!(report.missing.is_empty());
+    }
+
+    #[test]
+    fn placeholders_without_a_value_are_kept_and_reported() {
+        let template = Template::new(
+            "t",
+            "Date: {{incident_date}}\nWhere: {{location}}\nWho: {{persons_involved}}".to_string(),
+        );
+        let report = render(
+            &template,
+            &values(&[("incident_date", "today"), ("location", "")]),
+        );
+        assert_eq!(
+            report.text,
+            "Date: today\nWhere: {{location}}\nWho: {{persons_involved}}"
+        );
+        assert_eq!(report.missing, vec!["location", "persons_involved"]);
+    }
+
+    #[test]
+    fn dictated_fields_leave_out_the_transcript() {
+        let template = Template::new("t", "{{transcript}} {{summary}}".to_string());
+        assert_eq!(template.dictated_fields(), vec!["summary"]);
+    }
+
+    #[test]
+    fn spoken_labels_split_the_dictation() {
+        let text =
+            "Incident date: March 3rd, location Fifth and Main; summary - the suspect fled north.";
+        let extracted = extract_spoken_fields(
+            text,
+            &fields(&["summary", "location", "incident_date", "actions_taken"]),
+        );
+        assert_eq!(
+            extracted,
+            values(&[
+                ("incident_date", "March 3rd"),
+                ("location", "Fifth and Main"),
+                ("summary", "the suspect fled north."),
+            ])
+        );
+    }
+
+    #[test]
+    fn labels_only_match_whole_words_and_the_first_mention() {
+        let text = "relocation was refused, location the depot, then location changed";
+        let extracted = extract_spoken_fields(text, &fields(&["location"]));
+        assert_eq!(
+            extracted,
+            values(&[("location", "the depot, then location changed")])
+        );
+    }
+
+    #[test]
+    fn labels_without_a_value_are_left_out() {
+        let extracted = extract_spoken_fields(
+            "summary location Main Street",
+            &fields(&["summary", "location"]),
+        );
+        assert_eq!(extracted, values(&[("location", "Main Street")]));
+        assert!(extract_spoken_fields("nothing to see", &fields(&["summary"])).is_empty());
+    }
+
+    #[test]
+    fn spoken_fields_render_into_the_template() {
+        let template = Template::new(
+            "t",
+            "**Location:** {{location}}\n**Summary:** {{summary}}\n**Actions:** {{actions_taken}}"
+                .to_string(),
+        );
+        let extracted = extract_spoken_fields(
+            "location Fifth and Main summary minor collision",
+            &template.dictated_fields(),
+        );
+        let report = render(&template, &extracted);
+        assert_eq!(
+            report.text,
+            "**Location:** Fifth and Main\n**Summary:** minor collision\n**Actions:** {{actions_taken}}"
+        );
+        assert_eq!(report.missing, vec!["actions_taken"]);
+    }
+
+    #[test]
+    fn store_saves_lists_and_deletes_templates() {
+        let dir = TempDir::new();
+        let store = TemplateStore::open(&dir.join("templates")).unwrap();
+        let names = |store: &TemplateStore| -> Vec<String> {
+            store.list().unwrap().into_iter().map(|t| t.name).collect()
+        };
+        assert_eq!(names(&store), vec![EXAMPLE_NAME]);
+
+        fs::write(store.dir().join("notes.txt"), "{{summary}}").unwrap();
+        fs::write(store.dir().join("ignored.json"), "{}").unwrap();
+        store.save("traffic stop", "{{plate}}").unwrap();
+        assert_eq!(names(&store), vec![EXAMPLE_NAME, "notes", "traffic stop"]);
+
+        // An existing plain text template stays plain text
+        store.save("notes", "{{location}}").unwrap();
+        assert_eq!(
+            fs::read_to_string(store.dir().join("notes.txt")).unwrap(),
+            "{{location}}"
+        );
+        assert_eq!(
+            store.get("notes").unwrap().unwrap().fields,
+            vec!["location"]
+        );
+
+        assert!(store.delete("notes").unwrap());
+        assert!(!store.delete("notes").unwrap());
+        assert!(store.get("notes").unwrap().is_none());
+    }
+
+    #[test]
+    fn template_names_cannot_leave_the_folder() {
+        for name in ["../secrets", "a/b", "", " padded", "name.md"] {
+            assert!(validate_name(name).is_err(), "{:?}", name);
+        }
+        assert!(validate_name("Incident report_2").is_ok());
+    }
+}
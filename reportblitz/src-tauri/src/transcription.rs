@@ -0,0 +1,583 @@
+//transcription.rs
+// Transcription backends. lib.rs picks one at runtime from the transcription
+// settings.
+use crate::encoding::{AudioFormat, EncodedAudio};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::PathBuf;
+use std::time::Duration;
+
+pub const OPENAI_BASE_URL: &str = "https://api.openai.com/v1";
+pub const DEFAULT_MODEL: &str = "whisper-1";
+
+// Which backend to use, as stored in the settings
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ProviderKind {
+    #[default]
+    OpenAi,
+    OpenAiCompatible,
+    Local,
+}
+
+impl ProviderKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ProviderKind::OpenAi => "open_ai",
+            ProviderKind::OpenAiCompatible => "open_ai_compatible",
+            ProviderKind::Local => "local",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "open_ai" | "openai" => Some(ProviderKind::OpenAi),
+            "open_ai_compatible" | "openai_compatible" => Some(ProviderKind::OpenAiCompatible),
+            "local" => Some(ProviderKind::Local),
+            _ => None,
+        }
+    }
+}
+
+// Request parameters sent with every transcription
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct TranscriptionOptions {
+    pub model: String,
+    // ISO-639-1 code such as "en"; None lets the model detect the language
+    pub language: Option<String>,
+    // Vocabulary hints, e.g. agency acronyms the model should spell correctly
+    pub prompt: Option<String>,
+    pub temperature: Option<f32>,
+}
+
+impl Default for TranscriptionOptions {
+    fn default() -> Self {
+        Self {
+            model: DEFAULT_MODEL.to_string(),
+            language: None,
+            prompt: None,
+            temperature: None,
+        }
+    }
+}
+
+impl TranscriptionOptions {
+    // Trim the text fields and turn blank values into None
+    pub fn normalized(mut self) -> Self {
+        self.model = self.model.trim().to_string();
+        if self.model.is_empty() {
+            self.model = DEFAULT_MODEL.to_string();
+        }
+        self.language = self
+            .language
+            .map(|l| l.trim().to_lowercase())
+            .filter(|l| !l.is_empty());
+        self.prompt = self
+            .prompt
+            .map(|p| p.trim().to_string())
+            .filter(|p| !p.is_empty());
+        self
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if let Some(language) = &self.language {
+            if language.len() != 2 || !language.chars().all(|c| c.is_ascii_lowercase()) {
+                return Err(anyhow!(
+                    "Language must be a two-letter ISO-639-1 code such as \"en\""
+                ));
+            }
+        }
+        if let Some(temperature) = self.temperature {
+            if !(0.0..=1.0).contains(&temperature) {
+                return Err(anyhow!("Temperature must be between 0 and 1"));
+            }
+        }
+        Ok(())
+    }
+}
+
+// Why a transcription failed, so callers can decide whether to retry and the
+// UI can tell the user what to do
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum TranscriptionError {
+    // Missing, invalid or unauthorised API key, or an account without quota
+    Auth {
+        message: String,
+    },
+    RateLimited {
+        message: String,
+        retry_after_secs: Option<u64>,
+    },
+    PayloadTooLarge {
+        message: String,
+    },
+    // Timeouts, refused connections and other transport failures
+    Network {
+        message: String,
+    },
+    Server {
+        status: u16,
+        message: String,
+    },
+    // Any other rejected request, e.g. an unknown model; retrying won't help
+    Request {
+        status: u16,
+        message: String,
+    },
+    // Failures that didn't come from an HTTP exchange, e.g. the local engine
+    Other {
+        message: String,
+    },
+}
+
+impl TranscriptionError {
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            TranscriptionError::RateLimited { .. }
+                | TranscriptionError::Network { .. }
+                | TranscriptionError::Server { .. }
+        )
+    }
+
+    // How long the server asked us to wait, if it did
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            TranscriptionError::RateLimited {
+                retry_after_secs: Some(secs),
+                ..
+            } => Some(Duration::from_secs(*secs)),
+            _ => None,
+        }
+    }
+
+    // Map an error response to its kind
+    pub fn from_status(status: u16, retry_after_secs: Option<u64>, body: String) -> Self {
+        let message = format!("API request failed with status {}: {}", status, body);
+        match status {
+            401 | 403 => TranscriptionError::Auth { message },
+            // OpenAI also answers 429 when the account is out of credit, which
+            // waiting won't fix
+            429 if body.contains("insufficient_quota") => TranscriptionError::Auth { message },
+            429 => TranscriptionError::RateLimited {
+                message,
+                retry_after_secs,
+            },
+            413 => TranscriptionError::PayloadTooLarge { message },
+            408 | 500..=599 => TranscriptionError::Server { status, message },
+            _ => TranscriptionError::Request { status, message },
+        }
+    }
+
+    pub fn from_reqwest(e: reqwest::Error) -> Self {
+        if let Some(status) = e.status() {
+            return Self::from_status(status.as_u16(), None, e.to_string());
+        }
+        if e.is_decode() {
+            return TranscriptionError::Other {
+                message: format!("Unexpected response from the server: {}", e),
+            };
+        }
+        TranscriptionError::Network {
+            message: e.to_string(),
+        }
+    }
+
+    // Whether the server refused the audio encoding, e.g. a self-hosted
+    // server that only reads WAV
+    pub fn is_format_rejected(&self) -> bool {
+        match self {
+            TranscriptionError::Request { status: 415, .. } => true,
+            TranscriptionError::Request {
+                status: 400,
+                message,
+            } => {
+                let message = message.to_lowercase();
+                message.contains("format")
+                    || message.contains("codec")
+                    || message.contains("decode")
+            }
+            _ => false,
+        }
+    }
+
+    // Recover the kind from an error returned by a provider
+    pub fn classify(e: &anyhow::Error) -> Self {
+        match e.downcast_ref::<TranscriptionError>() {
+            Some(error) => error.clone(),
+            None => TranscriptionError::Other {
+                message: e.to_string(),
+            },
+        }
+    }
+}
+
+impl fmt::Display for TranscriptionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TranscriptionError::Auth { message }
+            | TranscriptionError::RateLimited { message, .. }
+            | TranscriptionError::PayloadTooLarge { message }
+            | TranscriptionError::Network { message }
+            | TranscriptionError::Server { message, .. }
+            | TranscriptionError::Request { message, .. }
+            | TranscriptionError::Other { message } => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for TranscriptionError {}
+
+#[async_trait]
+pub trait TranscriptionProvider: Send + Sync {
+    // Short name used in logs and events
+    fn name(&self) -> &'static str;
+
+    // Format audio should be sent in
+    fn upload_format(&self) -> AudioFormat {
+        AudioFormat::Wav
+    }
+
+    // Transcribe a complete in-memory audio file and return the text. HTTP
+    // failures are returned as a TranscriptionError.
+    async fn transcribe(&self, audio: &EncodedAudio) -> Result<String>;
+}
+
+#[derive(Deserialize)]
+struct TranscriptionResponse {
+    text: String,
+}
+
+// Shared request logic for anything speaking the OpenAI transcription API
+async fn post_transcription(
+    base_url: &str,
+    api_key: Option<&str>,
+    options: &TranscriptionOptions,
+    audio: &EncodedAudio,
+) -> Result<String> {
+    let client = reqwest::Client::new();
+
+    let mut form = reqwest::multipart::Form::new().text("model", options.model.clone());
+    if let Some(language) = &options.language {
+        form = form.text("language", language.clone());
+    }
+    if let Some(prompt) = &options.prompt {
+        form = form.text("prompt", prompt.clone());
+    }
+    if let Some(temperature) = options.temperature {
+        form = form.text("temperature", temperature.to_string());
+    }
+
+    // Create a file part from the memory buffer
+    let file_part = reqwest::multipart::Part::bytes(audio.data.clone())
+        .file_name(format!("recording.{}", audio.format.extension()))
+        .mime_str(audio.format.mime_type())?;
+
+    let form = form.part("file", file_part);
+
+    let url = format!("{}/audio/transcriptions", base_url.trim_end_matches('/'));
+    let mut request = client
+        .post(&url)
+        .timeout(std::time::Duration::from_secs(30)) // 30 second timeout
+        .multipart(form);
+
+    if let Some(key) = api_key {
+        request = request.header("Authorization", format!("Bearer {}", key));
+    }
+
+    let response = request
+        .send()
+        .await
+        .map_err(TranscriptionError::from_reqwest)?;
+
+    if !response.status().is_success() {
+        let status = response.status().as_u16();
+        // Only the delay-seconds form is handled; OpenAI doesn't send dates
+        let retry_after_secs = response
+            .headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.trim().parse::<u64>().ok());
+        let body = response.text().await.unwrap_or_default();
+        return Err(TranscriptionError::from_status(status, retry_after_secs, body).into());
+    }
+
+    let transcription: TranscriptionResponse = response
+        .json()
+        .await
+        .map_err(TranscriptionError::from_reqwest)?;
+    Ok(transcription.text)
+}
+
+// Outcome of checking an API key against the server
+#[derive(Serialize, Clone, Debug, PartialEq)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum ApiKeyCheck {
+    Valid,
+    // No key was given or stored
+    Missing,
+    // The server rejected the key
+    Invalid,
+    // The key is recognised but may not list models, e.g. a restricted key
+    Forbidden,
+    // The key works but the account is over its rate limit or quota
+    RateLimited,
+    Unreachable { message: String },
+    Unexpected { status: u16, message: String },
+}
+
+// Check an API key with a cheap authenticated request that lists the models.
+// Without a key, checks whether the server answers without one, as a local
+// server may.
+pub async fn check_api_key(base_url: &str, api_key: Option<&str>) -> ApiKeyCheck {
+    if api_key == Some("") {
+        return ApiKeyCheck::Missing;
+    }
+
+    let url = format!("{}/models", base_url.trim_end_matches('/'));
+    let mut request = reqwest::Client::new()
+        .get(&url)
+        .timeout(std::time::Duration::from_secs(10));
+    if let Some(api_key) = api_key {
+        request = request.header("Authorization", format!("Bearer {}", api_key));
+    }
+    let response = request.send().await;
+
+    let response = match response {
+        Ok(response) => response,
+        Err(e) => {
+            return ApiKeyCheck::Unreachable {
+                message: e.to_string(),
+            }
+        }
+    };
+
+    match response.status().as_u16() {
+        200..=299 => ApiKeyCheck::Valid,
+        401 => ApiKeyCheck::Invalid,
+        403 => ApiKeyCheck::Forbidden,
+        429 => ApiKeyCheck::RateLimited,
+        status => ApiKeyCheck::Unexpected {
+            status,
+            message: response.text().await.unwrap_or_default(),
+        },
+    }
+}
+
+// The hosted OpenAI Whisper API
+pub struct OpenAiProvider {
+    api_key: String,
+    options: TranscriptionOptions,
+    upload_format: AudioFormat,
+}
+
+impl OpenAiProvider {
+    pub fn new(
+        api_key: String,
+        options: TranscriptionOptions,
+        upload_format: AudioFormat,
+    ) -> Result<Self> {
+        if api_key.is_empty() {
+            return Err(anyhow!(
+                "OpenAI API key not set. Please enter it in the settings."
+            ));
+        }
+        Ok(Self {
+            api_key,
+            options,
+            upload_format,
+        })
+    }
+}
+
+#[async_trait]
+impl TranscriptionProvider for OpenAiProvider {
+    fn name(&self) -> &'static str {
+        "openai"
+    }
+
+    fn upload_format(&self) -> AudioFormat {
+        self.upload_format
+    }
+
+    async fn transcribe(&self, audio: &EncodedAudio) -> Result<String> {
+        post_transcription(OPENAI_BASE_URL, Some(&self.api_key), &self.options, audio).await
+    }
+}
+
+// Any server implementing the OpenAI transcription endpoint, e.g. a
+// self-hosted Whisper server. The API key is optional.
+pub struct OpenAiCompatibleProvider {
+    base_url: String,
+    api_key: Option<String>,
+    options: TranscriptionOptions,
+    upload_format: AudioFormat,
+}
+
+impl OpenAiCompatibleProvider {
+    pub fn new(
+        base_url: String,
+        api_key: Option<String>,
+        options: TranscriptionOptions,
+        upload_format: AudioFormat,
+    ) -> Result<Self> {
+        if base_url.trim().is_empty() {
+            return Err(anyhow!(
+                "No base URL configured for the OpenAI-compatible transcription server"
+            ));
+        }
+        Ok(Self {
+            base_url,
+            api_key: api_key.filter(|key| !key.is_empty()),
+            options,
+            upload_format,
+        })
+    }
+}
+
+#[async_trait]
+impl TranscriptionProvider for OpenAiCompatibleProvider {
+    fn name(&self) -> &'static str {
+        "openai_compatible"
+    }
+
+    fn upload_format(&self) -> AudioFormat {
+        self.upload_format
+    }
+
+    async fn transcribe(&self, audio: &EncodedAudio) -> Result<String> {
+        post_transcription(
+            &self.base_url,
+            self.api_key.as_deref(),
+            &self.options,
+            audio,
+        )
+        .await
+    }
+}
+
+// On-device transcription with whisper.cpp, for machines without network
+// access. See local_whisper.rs for how models are found and loaded.
+pub struct LocalProvider {
+    model_path: PathBuf,
+    #[cfg_attr(not(feature = "local-whisper"), allow(dead_code))]
+    options: TranscriptionOptions,
+}
+
+impl LocalProvider {
+    pub fn new(model_path: PathBuf, options: TranscriptionOptions) -> Self {
+        Self {
+            model_path,
+            options,
+        }
+    }
+}
+
+#[async_trait]
+impl TranscriptionProvider for LocalProvider {
+    fn name(&self) -> &'static str {
+        "local"
+    }
+
+    // Audio never leaves the machine, so it stays WAV
+    #[cfg(feature = "local-whisper")]
+    async fn transcribe(&self, audio: &EncodedAudio) -> Result<String> {
+        if audio.format != AudioFormat::Wav {
+            return Err(anyhow!("The local engine only reads WAV audio"));
+        }
+        let model_path = self.model_path.clone();
+        let options = self.options.clone();
+        let wav_data = audio.data.clone();
+        tokio::task::spawn_blocking(move || {
+            crate::local_whisper::transcribe(&model_path, &options, &wav_data)
+        })
+        .await?
+    }
+
+    #[cfg(not(feature = "local-whisper"))]
+    async fn transcribe(&self, _audio: &EncodedAudio) -> Result<String> {
+        Err(anyhow!(
+            "Local transcription is not available in this build (model {:?})",
+            self.model_path
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(status: u16, body: &str) -> TranscriptionError {
+        TranscriptionError::from_status(status, Some(7), body.to_string())
+    }
+
+    #[test]
+    fn statuses_map_to_their_kind() {
+        assert!(matches!(error(401, ""), TranscriptionError::Auth { .. }));
+        assert!(matches!(error(403, ""), TranscriptionError::Auth { .. }));
+        assert!(matches!(
+            error(429, r#"{"error":{"code":"insufficient_quota"}}"#),
+            TranscriptionError::Auth { .. }
+        ));
+        assert!(matches!(
+            error(429, ""),
+            TranscriptionError::RateLimited {
+                retry_after_secs: Some(7),
+                ..
+            }
+        ));
+        assert!(matches!(
+            error(413, ""),
+            TranscriptionError::PayloadTooLarge { .. }
+        ));
+        assert!(matches!(
+            error(408, ""),
+            TranscriptionError::Server { status: 408, .. }
+        ));
+        assert!(matches!(
+            error(503, ""),
+            TranscriptionError::Server { status: 503, .. }
+        ));
+        assert!(matches!(
+            error(400, ""),
+            TranscriptionError::Request { status: 400, .. }
+        ));
+    }
+
+    #[test]
+    fn only_transient_failures_are_retryable() {
+        for status in [429, 408, 500, 502, 503] {
+            assert!(error(status, "").is_retryable(), "{}", status);
+        }
+        for status in [400, 401, 403, 404, 413, 415] {
+            assert!(!error(status, "").is_retryable(), "{}", status);
+        }
+        assert!(!error(429, "insufficient_quota").is_retryable());
+        assert_eq!(error(429, "").retry_after(), Some(Duration::from_secs(7)));
+        assert_eq!(error(503, "").retry_after(), None);
+    }
+
+    #[test]
+    fn format_rejections_are_recognised() {
+        assert!(error(415, "").is_format_rejected());
+        assert!(error(400, "Invalid file format.").is_format_rejected());
+        assert!(error(400, "Unsupported codec").is_format_rejected());
+        assert!(error(400, "Could not decode audio").is_format_rejected());
+        assert!(!error(400, "Unknown model").is_format_rejected());
+        assert!(!error(500, "Invalid file format.").is_format_rejected());
+    }
+
+    #[test]
+    fn classify_recovers_the_kind() {
+        let server = error(502, "bad gateway");
+        assert_eq!(TranscriptionError::classify(&server.clone().into()), server);
+        assert!(matches!(
+            TranscriptionError::classify(&anyhow!("model file missing")),
+            TranscriptionError::Other { .. }
+        ));
+    }
+}
@@ -0,0 +1,209 @@
+//vad.rs
+// Energy based voice activity detection. Trims silence from the edges of a
+// clip, shortens long pauses and reports clips with no speech at all, which
+// Whisper would otherwise "transcribe" as something like "Thank you."
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct VadConfig {
+    pub enabled: bool,
+    pub frame_ms: u32,
+    // Frames louder than this RMS level (or than the noise floor times
+    // `noise_ratio`, whichever is higher) count as speech
+    pub threshold: f32,
+    pub noise_ratio: f32,
+    // Clips with less speech than this are treated as silent
+    pub min_speech_ms: u32,
+    // Audio kept on either side of speech so word edges aren't clipped
+    pub padding_ms: u32,
+    // Pauses longer than this are shortened to this length
+    pub max_pause_ms: u32,
+}
+
+impl Default for VadConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            frame_ms: 30,
+            threshold: 0.01,
+            noise_ratio: 3.0,
+            min_speech_ms: 200,
+            padding_ms: 250,
+            max_pause_ms: 800,
+        }
+    }
+}
+
+impl VadConfig {
+    pub fn validate(&self) -> Result<()> {
+        if !(10..=100).contains(&self.frame_ms) {
+            return Err(anyhow!("VAD frame length must be between 10 and 100 ms"));
+        }
+        if !(0.0..1.0).contains(&self.threshold) {
+            return Err(anyhow!("VAD threshold must be between 0 and 1"));
+        }
+        if self.noise_ratio < 1.0 {
+            return Err(anyhow!("VAD noise ratio must be at least 1"));
+        }
+        if self.max_pause_ms < self.frame_ms {
+            return Err(anyhow!("Maximum pause must be at least one frame long"));
+        }
+        Ok(())
+    }
+}
+
+// Per-frame speech decisions for a mono buffer
+pub fn detect_speech(samples: &[f32], sample_rate: u32, config: &VadConfig) -> Vec<bool> {
+    let frame_len = frame_len(sample_rate, config.frame_ms);
+    let rms: Vec<f32> = samples
+        .chunks(frame_len)
+        .map(|frame| (frame.iter().map(|s| s * s).sum::<f32>() / frame.len() as f32).sqrt())
+        .collect();
+    if rms.is_empty() {
+        return Vec::new();
+    }
+
+    // Estimate the noise floor from the quietest tenth of the frames. It is
+    // capped so a clip that is speech from end to end isn't mistaken for noise.
+    let mut sorted = rms.clone();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let noise_floor = sorted[sorted.len() / 10];
+    let adaptive = (noise_floor * config.noise_ratio).min(config.threshold * 5.0);
+    let threshold = config.threshold.max(adaptive);
+
+    rms.iter().map(|&level| level > threshold).collect()
+}
+
+// Trim and compress silence. Returns None if the clip contains no speech.
+pub fn trim_silence(samples: &[f32], sample_rate: u32, config: &VadConfig) -> Option<Vec<f32>> {
+    let frame_len = frame_len(sample_rate, config.frame_ms);
+    let speech = detect_speech(samples, sample_rate, config);
+
+    let speech_frames = speech.iter().filter(|&&s| s).count();
+    if speech_frames * (config.frame_ms as usize) < config.min_speech_ms as usize {
+        return None;
+    }
+
+    // Widen every speech frame by the padding on both sides
+    let padding = (config.padding_ms / config.frame_ms) as usize;
+    let mut keep = vec![false; speech.len()];
+    for (i, _) in speech.iter().enumerate().filter(|(_, &s)| s) {
+        let start = i.saturating_sub(padding);
+        let end = (i + padding + 1).min(keep.len());
+        keep[start..end].iter_mut().for_each(|k| *k = true);
+    }
+
+    let first = keep.iter().position(|&k| k)?;
+    let last = keep.iter().rposition(|&k| k)?;
+    let max_pause = (config.max_pause_ms / config.frame_ms).max(1) as usize;
+
+    // Copy the kept frames between the first and last speech, shortening
+    // each gap to at most `max_pause` frames
+    let mut out = Vec::with_capacity((last - first + 1) * frame_len);
+    let mut gap = 0;
+    for (i, &k) in keep.iter().enumerate().take(last + 1).skip(first) {
+        if k {
+            gap = 0;
+        } else {
+            gap += 1;
+            if gap > max_pause {
+                continue;
+            }
+        }
+        let start = i * frame_len;
+        let end = (start + frame_len).min(samples.len());
+        out.extend_from_slice(&samples[start..end]);
+    }
+
+    Some(out)
+}
+
+fn frame_len(sample_rate: u32, frame_ms: u32) -> usize {
+    ((sample_rate as usize * frame_ms as usize) / 1000).max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 16_000;
+
+    // Concatenated sections of silence (amplitude 0) and a 440 Hz tone
+    fn clip(sections: &[(u32, f32)]) -> Vec<f32> {
+        let mut samples = Vec::new();
+        for &(ms, amplitude) in sections {
+            let len = (RATE * ms / 1000) as usize;
+            samples.extend((0..len).map(|i| {
+                amplitude * (2.0 * std::f32::consts::PI * 440.0 * i as f32 / RATE as f32).sin()
+            }));
+        }
+        samples
+    }
+
+    // Samples in a whole number of frames
+    fn frames(count: u32, config: &VadConfig) -> usize {
+        frame_len(RATE, config.frame_ms) * count as usize
+    }
+
+    #[test]
+    fn all_silence_is_none() {
+        let config = VadConfig::default();
+        assert_eq!(trim_silence(&clip(&[(3000, 0.0)]), RATE, &config), None);
+        assert_eq!(trim_silence(&clip(&[(3000, 0.002)]), RATE, &config), None);
+        assert_eq!(trim_silence(&[], RATE, &config), None);
+    }
+
+    #[test]
+    fn edges_are_trimmed_to_the_padding() {
+        let config = VadConfig::default();
+        let samples = clip(&[(900, 0.0), (600, 0.5), (900, 0.0)]);
+        let trimmed = trim_silence(&samples, RATE, &config).unwrap();
+
+        let padding = config.padding_ms / config.frame_ms;
+        let speech = 600 / config.frame_ms;
+        assert_eq!(trimmed.len(), frames(padding + speech + padding, &config));
+        // The padding is the silence next to the speech, which is kept as is
+        let padding_len = frames(padding, &config);
+        assert!(trimmed[..padding_len].iter().all(|&s| s == 0.0));
+        assert!(trimmed[trimmed.len() - padding_len..]
+            .iter()
+            .all(|&s| s == 0.0));
+        assert!(trimmed[padding_len..padding_len + 100]
+            .iter()
+            .any(|&s| s != 0.0));
+    }
+
+    #[test]
+    fn long_pauses_are_shortened() {
+        let config = VadConfig::default();
+        let samples = clip(&[(300, 0.5), (3000, 0.0), (300, 0.5)]);
+        let trimmed = trim_silence(&samples, RATE, &config).unwrap();
+
+        let padding = config.padding_ms / config.frame_ms;
+        let speech = 300 / config.frame_ms;
+        let max_pause = config.max_pause_ms / config.frame_ms;
+        // Each word keeps its padding on the inside of the pause, and the rest
+        // of the pause is cut to the maximum
+        let expected = (speech + padding) + max_pause + (padding + speech);
+        assert_eq!(trimmed.len(), frames(expected, &config));
+    }
+
+    #[test]
+    fn short_pauses_are_kept() {
+        let config = VadConfig::default();
+        let samples = clip(&[(300, 0.5), (600, 0.0), (300, 0.5)]);
+        assert_eq!(trim_silence(&samples, RATE, &config).unwrap(), samples);
+    }
+
+    #[test]
+    fn noise_bursts_shorter_than_speech_are_dropped() {
+        let config = VadConfig::default();
+        let burst = clip(&[(1000, 0.0), (90, 0.5), (1000, 0.0)]);
+        assert_eq!(trim_silence(&burst, RATE, &config), None);
+
+        let word = clip(&[(1000, 0.0), (300, 0.5), (1000, 0.0)]);
+        assert!(trim_silence(&word, RATE, &config).is_some());
+    }
+}
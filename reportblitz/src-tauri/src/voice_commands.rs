@@ -0,0 +1,601 @@
+//voice_commands.rs
+// Spoken formatting commands. Phrases like "new paragraph", "comma" or
+// "scratch that" in a transcript are replaced by their effect before the text
+// is typed. Matching is plain word comparison, so the result is the same every
+// time. Each language has its own phrases, which the user can extend.
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+
+// Used when neither the settings nor the transcription name a language
+pub const DEFAULT_LANGUAGE: &str = "en";
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum VoiceCommand {
+    Period,
+    Comma,
+    QuestionMark,
+    ExclamationMark,
+    Colon,
+    Semicolon,
+    NewLine,
+    NewParagraph,
+    OpenQuote,
+    CloseQuote,
+    BulletPoint,
+    // Remove everything since the end of the previous sentence or line
+    ScratchThat,
+    // Type the next word as spoken, e.g. "literal period"
+    Literal,
+}
+
+impl VoiceCommand {
+    fn punctuation(self) -> Option<char> {
+        match self {
+            Self::Period => Some('.'),
+            Self::Comma => Some(','),
+            Self::QuestionMark => Some('?'),
+            Self::ExclamationMark => Some('!'),
+            Self::Colon => Some(':'),
+            Self::Semicolon => Some(';'),
+            _ => None,
+        }
+    }
+
+    // Whether the next word starts a sentence
+    fn ends_sentence(self) -> bool {
+        matches!(
+            self,
+            Self::Period
+                | Self::QuestionMark
+                | Self::ExclamationMark
+                | Self::NewLine
+                | Self::NewParagraph
+                | Self::BulletPoint
+        )
+    }
+}
+
+const ENGLISH: &[(&str, VoiceCommand)] = &[
+    ("period", VoiceCommand::Period),
+    ("full stop", VoiceCommand::Period),
+    ("comma", VoiceCommand::Comma),
+    ("question mark", VoiceCommand::QuestionMark),
+    ("exclamation mark", VoiceCommand::ExclamationMark),
+    ("exclamation point", VoiceCommand::ExclamationMark),
+    ("colon", VoiceCommand::Colon),
+    ("semicolon", VoiceCommand::Semicolon),
+    ("new line", VoiceCommand::NewLine),
+    ("newline", VoiceCommand::NewLine),
+    ("next line", VoiceCommand::NewLine),
+    ("new paragraph", VoiceCommand::NewParagraph),
+    ("next paragraph", VoiceCommand::NewParagraph),
+    ("open quote", VoiceCommand::OpenQuote),
+    ("begin quote", VoiceCommand::OpenQuote),
+    ("close quote", VoiceCommand::CloseQuote),
+    ("end quote", VoiceCommand::CloseQuote),
+    ("unquote", VoiceCommand::CloseQuote),
+    ("bullet point", VoiceCommand::BulletPoint),
+    ("scratch that", VoiceCommand::ScratchThat),
+    ("delete that", VoiceCommand::ScratchThat),
+    ("literal", VoiceCommand::Literal),
+];
+
+const GERMAN: &[(&str, VoiceCommand)] = &[
+    ("punkt", VoiceCommand::Period),
+    ("komma", VoiceCommand::Comma),
+    ("fragezeichen", VoiceCommand::QuestionMark),
+    ("ausrufezeichen", VoiceCommand::ExclamationMark),
+    ("doppelpunkt", VoiceCommand::Colon),
+    ("semikolon", VoiceCommand::Semicolon),
+    ("neue zeile", VoiceCommand::NewLine),
+    ("neuer absatz", VoiceCommand::NewParagraph),
+    ("anführungszeichen auf", VoiceCommand::OpenQuote),
+    ("anführungszeichen unten", VoiceCommand::OpenQuote),
+    ("anführungszeichen zu", VoiceCommand::CloseQuote),
+    ("anführungszeichen oben", VoiceCommand::CloseQuote),
+    ("aufzählungspunkt", VoiceCommand::BulletPoint),
+    ("streich das", VoiceCommand::ScratchThat),
+    ("lösch das", VoiceCommand::ScratchThat),
+    ("wörtlich", VoiceCommand::Literal),
+];
+
+const FRENCH: &[(&str, VoiceCommand)] = &[
+    ("point", VoiceCommand::Period),
+    ("virgule", VoiceCommand::Comma),
+    ("point d'interrogation", VoiceCommand::QuestionMark),
+    ("point d'exclamation", VoiceCommand::ExclamationMark),
+    ("deux points", VoiceCommand::Colon),
+    ("deux-points", VoiceCommand::Colon),
+    ("point-virgule", VoiceCommand::Semicolon),
+    ("point virgule", VoiceCommand::Semicolon),
+    ("à la ligne", VoiceCommand::NewLine),
+    ("nouvelle ligne", VoiceCommand::NewLine),
+    ("nouveau paragraphe", VoiceCommand::NewParagraph),
+    ("ouvrez les guillemets", VoiceCommand::OpenQuote),
+    ("ouvrir les guillemets", VoiceCommand::OpenQuote),
+    ("fermez les guillemets", VoiceCommand::CloseQuote),
+    ("fermer les guillemets", VoiceCommand::CloseQuote),
+    ("puce", VoiceCommand::BulletPoint),
+    ("efface ça", VoiceCommand::ScratchThat),
+    ("annule ça", VoiceCommand::ScratchThat),
+    ("littéralement", VoiceCommand::Literal),
+];
+
+const SPANISH: &[(&str, VoiceCommand)] = &[
+    ("punto", VoiceCommand::Period),
+    ("coma", VoiceCommand::Comma),
+    ("signo de interrogación", VoiceCommand::QuestionMark),
+    ("signo de exclamación", VoiceCommand::ExclamationMark),
+    ("dos puntos", VoiceCommand::Colon),
+    ("punto y coma", VoiceCommand::Semicolon),
+    ("nueva línea", VoiceCommand::NewLine),
+    ("nuevo párrafo", VoiceCommand::NewParagraph),
+    ("abrir comillas", VoiceCommand::OpenQuote),
+    ("cerrar comillas", VoiceCommand::CloseQuote),
+    ("viñeta", VoiceCommand::BulletPoint),
+    ("borra eso", VoiceCommand::ScratchThat),
+    ("literal", VoiceCommand::Literal),
+];
+
+// Built-in phrases for a language code such as "en" or "de"
+pub fn builtin_phrases(language: &str) -> &'static [(&'static str, VoiceCommand)] {
+    match language {
+        "en" => ENGLISH,
+        "de" => GERMAN,
+        "fr" => FRENCH,
+        "es" => SPANISH,
+        _ => &[],
+    }
+}
+
+// "en-US" and "EN" both become "en"
+pub fn normalize_language(language: &str) -> String {
+    language
+        .trim()
+        .split(['-', '_'])
+        .next()
+        .unwrap_or_default()
+        .to_lowercase()
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct VoiceCommandSettings {
+    pub enabled: bool,
+    // Phrases of this language are recognized; None follows the transcription
+    // language
+    pub language: Option<String>,
+    // Extra phrases per language, e.g. {"en": {"next item": "bullet_point"}}.
+    // null turns off a built-in phrase.
+    pub phrases: BTreeMap<String, BTreeMap<String, Option<VoiceCommand>>>,
+}
+
+impl VoiceCommandSettings {
+    // Lowercase language codes and phrases and collapse runs of spaces
+    pub fn normalized(mut self) -> Self {
+        self.language = self
+            .language
+            .map(|language| normalize_language(&language))
+            .filter(|language| !language.is_empty());
+        self.phrases = self
+            .phrases
+            .into_iter()
+            .map(|(language, phrases)| {
+                let phrases = phrases
+                    .into_iter()
+                    .map(|(phrase, command)| (normalize_phrase(&phrase), command))
+                    .collect();
+                (normalize_language(&language), phrases)
+            })
+            .collect();
+        self
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        for (language, phrases) in &self.phrases {
+            if language.is_empty() {
+                return Err(anyhow!("Voice command phrases need a language code"));
+            }
+            if phrases.keys().any(|phrase| phrase.is_empty()) {
+                return Err(anyhow!("Voice command phrases cannot be empty"));
+            }
+        }
+        Ok(())
+    }
+
+    // The language whose phrases apply. `transcription_language` is the one
+    // requested from the transcription provider, if any.
+    pub fn effective_language(&self, transcription_language: Option<&str>) -> String {
+        self.language
+            .clone()
+            .or_else(|| transcription_language.map(normalize_language))
+            .filter(|language| !language.is_empty())
+            .unwrap_or_else(|| DEFAULT_LANGUAGE.to_string())
+    }
+}
+
+fn normalize_phrase(phrase: &str) -> String {
+    phrase
+        .split_whitespace()
+        .map(word_key)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// A word as it is compared: lowercase, without surrounding punctuation, with
+// typographic apostrophes made plain
+fn word_key(word: &str) -> String {
+    word.trim_matches(|c: char| !c.is_alphanumeric())
+        .to_lowercase()
+        .replace('\u{2019}', "'")
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct VoicePhrase {
+    pub phrase: String,
+    pub command: VoiceCommand,
+}
+
+pub struct VoiceCommandParser {
+    // Phrase words, longest phrases first so "point virgule" beats "point"
+    phrases: Vec<(Vec<String>, VoiceCommand)>,
+}
+
+impl VoiceCommandParser {
+    // Built-in phrases of `language` with the user's additions applied
+    pub fn new(language: &str, custom: Option<&BTreeMap<String, Option<VoiceCommand>>>) -> Self {
+        let mut table: BTreeMap<String, Option<VoiceCommand>> = builtin_phrases(language)
+            .iter()
+            .map(|(phrase, command)| (phrase.to_string(), Some(*command)))
+            .collect();
+        if let Some(custom) = custom {
+            for (phrase, command) in custom {
+                table.insert(normalize_phrase(phrase), *command);
+            }
+        }
+
+        let mut phrases: Vec<(Vec<String>, VoiceCommand)> = table
+            .into_iter()
+            .filter_map(|(phrase, command)| {
+                let words: Vec<String> = phrase.split_whitespace().map(word_key).collect();
+                command.filter(|_| !words.is_empty()).map(|c| (words, c))
+            })
+            .collect();
+        phrases.sort_by_key(|(words, _)| Reverse(words.len()));
+        Self { phrases }
+    }
+
+    pub fn from_settings(
+        settings: &VoiceCommandSettings,
+        transcription_language: Option<&str>,
+    ) -> Self {
+        let language = settings.effective_language(transcription_language);
+        Self::new(&language, settings.phrases.get(&language))
+    }
+
+    pub fn phrases(&self) -> Vec<VoicePhrase> {
+        let mut phrases: Vec<VoicePhrase> = self
+            .phrases
+            .iter()
+            .map(|(words, command)| VoicePhrase {
+                phrase: words.join(" "),
+                command: *command,
+            })
+            .collect();
+        phrases.sort_by(|a, b| a.phrase.cmp(&b.phrase));
+        phrases
+    }
+
+    // Replace the command phrases in `text` with their effect. Punctuation the
+    // transcription put around a phrase ("arrived, new paragraph.") is dropped,
+    // since the command says what should be there.
+    pub fn apply(&self, text: &str) -> String {
+        let words: Vec<&str> = text.split_whitespace().collect();
+        let keys: Vec<String> = words.iter().map(|word| word_key(word)).collect();
+
+        let mut out = String::with_capacity(text.len());
+        let mut capitalize_next = false;
+        let mut glue_next = false;
+        let mut i = 0;
+        while i < words.len() {
+            let Some((length, command)) = self.match_at(&keys[i..]) else {
+                push_word(&mut out, words[i], capitalize_next, glue_next);
+                capitalize_next = false;
+                glue_next = false;
+                i += 1;
+                continue;
+            };
+            i += length;
+
+            if let Some(mark) = command.punctuation() {
+                if !out.is_empty() {
+                    trim_trailing(&mut out, &[',', ';', ':', '.', '!', '?']);
+                    out.push(mark);
+                }
+            } else {
+                match command {
+                    VoiceCommand::NewLine => {
+                        trim_trailing(&mut out, &[]);
+                        out.push('\n');
+                    }
+                    VoiceCommand::NewParagraph => {
+                        trim_trailing(&mut out, &[]);
+                        if !out.is_empty() && !out.ends_with("\n\n") {
+                            out.push_str(if out.ends_with('\n') { "\n" } else { "\n\n" });
+                        }
+                    }
+                    VoiceCommand::OpenQuote => {
+                        push_word(&mut out, "\"", false, glue_next);
+                        glue_next = true;
+                    }
+                    VoiceCommand::CloseQuote => {
+                        trim_trailing(&mut out, &[',']);
+                        out.push('"');
+                    }
+                    VoiceCommand::BulletPoint => {
+                        trim_trailing(&mut out, &[]);
+                        if !out.is_empty() && !out.ends_with('\n') {
+                            out.push('\n');
+                        }
+                        out.push_str("- ");
+                    }
+                    VoiceCommand::ScratchThat => {
+                        scratch_last_sentence(&mut out);
+                        capitalize_next = true;
+                        continue;
+                    }
+                    VoiceCommand::Literal => {
+                        if let Some(word) = words.get(i) {
+                            push_word(&mut out, word, capitalize_next, glue_next);
+                            i += 1;
+                        }
+                        capitalize_next = false;
+                        glue_next = false;
+                        continue;
+                    }
+                    _ => {}
+                }
+            }
+            if command.ends_sentence() {
+                capitalize_next = true;
+            }
+        }
+        out.trim_end().to_string()
+    }
+
+    // Word count and command of the longest phrase at the start of `keys`
+    fn match_at(&self, keys: &[String]) -> Option<(usize, VoiceCommand)> {
+        self.phrases
+            .iter()
+            .find(|(words, _)| keys.len() >= words.len() && keys[..words.len()] == words[..])
+            .map(|(words, command)| (words.len(), *command))
+    }
+}
+
+fn push_word(out: &mut String, word: &str, capitalize: bool, glue: bool) {
+    if !glue && !out.is_empty() && !out.ends_with(char::is_whitespace) {
+        out.push(' ');
+    }
+    if !capitalize {
+        out.push_str(word);
+        return;
+    }
+    match word.char_indices().find(|(_, c)| c.is_alphabetic()) {
+        Some((index, c)) => {
+            out.push_str(&word[..index]);
+            out.extend(c.to_uppercase());
+            out.push_str(&word[index + c.len_utf8()..]);
+        }
+        None => out.push_str(word),
+    }
+}
+
+// Remove trailing spaces and any of `marks`, but never a line break
+fn trim_trailing(out: &mut String, marks: &[char]) {
+    let trimmed =
+        out.trim_end_matches(|c: char| (c.is_whitespace() && c != '\n') || marks.contains(&c));
+    out.truncate(trimmed.len());
+}
+
+// Drop the text after the last sentence end or line break, not counting the
+// punctuation the removed sentence itself ends with
+fn scratch_last_sentence(out: &mut String) {
+    let body = out.trim_end_matches(|c: char| c.is_whitespace() && c != '\n');
+    let body = body.trim_end_matches(['.', '!', '?', ',', ';', ':']);
+    match body.rfind(['.', '!', '?', '\n']) {
+        Some(index) => out.truncate(index + 1),
+        None => out.clear(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn english(text: &str) -> String {
+        VoiceCommandParser::new("en", None).apply(text)
+    }
+
+    #[test]
+    fn punctuation_attaches_to_the_previous_word() {
+        assert_eq!(
+            english("the suspect fled comma then stopped period he was calm"),
+            "the suspect fled, then stopped. He was calm"
+        );
+        assert_eq!(english("Is that all question mark"), "Is that all?");
+        assert_eq!(english("Stop exclamation point"), "Stop!");
+        assert_eq!(
+            english("Items colon keys semicolon wallet"),
+            "Items: keys; wallet"
+        );
+    }
+
+    #[test]
+    fn punctuation_added_by_the_transcription_is_replaced() {
+        assert_eq!(english("The car was red, period."), "The car was red.");
+        assert_eq!(
+            english("Arrived at 10. New paragraph. The door was open."),
+            "Arrived at 10.\n\nThe door was open."
+        );
+        assert_eq!(english("First, Comma, second"), "First, second");
+    }
+
+    #[test]
+    fn phrases_match_case_insensitively() {
+        assert_eq!(english("One NEW LINE two Newline three"), "One\nTwo\nThree");
+    }
+
+    #[test]
+    fn line_breaks_and_paragraphs() {
+        assert_eq!(
+            english("Summary new line details new paragraph actions"),
+            "Summary\nDetails\n\nActions"
+        );
+        assert_eq!(english("One new line new paragraph two"), "One\n\nTwo");
+        assert_eq!(english("new paragraph one new paragraph"), "One");
+    }
+
+    #[test]
+    fn bullet_points() {
+        assert_eq!(
+            english("Seized items colon bullet point knife bullet point phone"),
+            "Seized items:\n- Knife\n- Phone"
+        );
+        assert_eq!(english("bullet point first"), "- First");
+    }
+
+    #[test]
+    fn quotes() {
+        assert_eq!(
+            english("He said comma open quote leave me alone close quote period"),
+            "He said, \"leave me alone\"."
+        );
+        assert_eq!(
+            english("She said, open quote, no, close quote, and left."),
+            "She said, \"no\" and left."
+        );
+    }
+
+    #[test]
+    fn scratch_that_removes_the_last_sentence() {
+        assert_eq!(
+            english("The car was blue. It was parked. Scratch that. It was moving."),
+            "The car was blue. It was moving."
+        );
+        assert_eq!(
+            english("wrong start scratch that right start"),
+            "Right start"
+        );
+        assert_eq!(
+            english("Line one new line line two delete that line three"),
+            "Line one\nLine three"
+        );
+        assert_eq!(english("scratch that"), "");
+    }
+
+    #[test]
+    fn literal_keeps_the_next_word() {
+        assert_eq!(
+            english("a literal period of three days"),
+            "a period of three days"
+        );
+        assert_eq!(english("ends with literal"), "ends with");
+    }
+
+    #[test]
+    fn text_without_commands_is_unchanged() {
+        let text = "The officer arrived at 10:45 and spoke to the witness.";
+        assert_eq!(english(text), text);
+        assert_eq!(english(""), "");
+    }
+
+    #[test]
+    fn other_languages() {
+        let german = VoiceCommandParser::new("de", None);
+        assert_eq!(
+            german.apply("Der Wagen war rot Punkt neuer Absatz Zeugen Doppelpunkt keine"),
+            "Der Wagen war rot.\n\nZeugen: keine"
+        );
+        let french = VoiceCommandParser::new("fr", None);
+        assert_eq!(
+            french.apply("Qui était là point d’interrogation personne point virgule rien"),
+            "Qui était là? Personne; rien"
+        );
+        let spanish = VoiceCommandParser::new("es", None);
+        assert_eq!(
+            spanish.apply("uno coma dos punto y coma tres punto"),
+            "uno, dos; tres."
+        );
+    }
+
+    #[test]
+    fn custom_phrases_extend_and_disable_builtins() {
+        let custom = BTreeMap::from([
+            ("Next  Item".to_string(), Some(VoiceCommand::BulletPoint)),
+            ("period".to_string(), None),
+        ]);
+        let parser = VoiceCommandParser::new("en", Some(&custom));
+        assert_eq!(
+            parser.apply("a period next item b full stop"),
+            "a period\n- B."
+        );
+        assert!(parser.phrases().iter().all(|p| p.phrase != "period"));
+        assert!(parser.phrases().contains(&VoicePhrase {
+            phrase: "next item".to_string(),
+            command: VoiceCommand::BulletPoint,
+        }));
+    }
+
+    #[test]
+    fn unknown_languages_only_use_custom_phrases() {
+        let custom = BTreeMap::from([("punto".to_string(), Some(VoiceCommand::Period))]);
+        let parser = VoiceCommandParser::new("it", Some(&custom));
+        assert_eq!(parser.apply("fine punto comma"), "fine. Comma");
+        assert_eq!(
+            VoiceCommandParser::new("it", None).apply("a comma b"),
+            "a comma b"
+        );
+    }
+
+    #[test]
+    fn language_follows_the_settings_then_the_transcription() {
+        let mut settings = VoiceCommandSettings::default();
+        assert_eq!(settings.effective_language(None), "en");
+        assert_eq!(settings.effective_language(Some("de-DE")), "de");
+        settings.language = Some("FR".to_string());
+        settings = settings.normalized();
+        assert_eq!(settings.effective_language(Some("de")), "fr");
+    }
+
+    #[test]
+    fn settings_are_normalized_and_validated() {
+        let settings = VoiceCommandSettings {
+            enabled: true,
+            language: Some("  ".to_string()),
+            phrases: BTreeMap::from([(
+                "EN_gb".to_string(),
+                BTreeMap::from([(
+                    "  Next   Item ".to_string(),
+                    Some(VoiceCommand::BulletPoint),
+                )]),
+            )]),
+        }
+        .normalized();
+        assert_eq!(settings.language, None);
+        assert!(settings.phrases["en"].contains_key("next item"));
+        assert!(settings.validate().is_ok());
+
+        let empty_phrase = VoiceCommandSettings {
+            phrases: BTreeMap::from([(
+                "en".to_string(),
+                BTreeMap::from([("!".to_string(), None)]),
+            )]),
+            ..Default::default()
+        }
+        .normalized();
+        assert!(empty_phrase.validate().is_err());
+    }
+}